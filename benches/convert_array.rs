@@ -0,0 +1,51 @@
+//! Measures how long `Json2Csv::convert_from_array` takes to convert a big array. Run it with and
+//! without the `rayon` feature to compare the sequential and the parallel flattening:
+//!
+//! ```text
+//! cargo bench --bench convert_array
+//! cargo bench --bench convert_array --features rayon
+//! ```
+
+use flatten_json_object::Flattener;
+use json_objects_to_csv::Json2Csv;
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+
+/// Number of objects in the array converted.
+const OBJECTS: usize = 200_000;
+
+/// Number of times the array is converted, keeping the fastest one.
+const RUNS: usize = 5;
+
+fn main() {
+    let objects: Vec<Value> = (0..OBJECTS)
+        .map(|i| {
+            json!({
+                "id": i,
+                "user": {"name": format!("user {i}"), "tags": ["a", "b", i % 7]},
+                "values": [i, i * 2, {"nested": i % 3 == 0}],
+            })
+        })
+        .collect();
+    let json2csv = Json2Csv::new(Flattener::new());
+
+    let mut fastest = Duration::MAX;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        json2csv
+            .convert_from_array(&objects, csv::Writer::from_writer(std::io::sink()))
+            .unwrap();
+        fastest = fastest.min(start.elapsed());
+    }
+
+    let flattening = if cfg!(feature = "rayon") {
+        "parallel"
+    } else {
+        "sequential"
+    };
+    let threads = std::thread::available_parallelism().map_or(1, usize::from);
+    println!(
+        "{OBJECTS} objects with {flattening} flattening and {threads} threads: {fastest:?} \
+         (fastest of {RUNS} runs)"
+    );
+}
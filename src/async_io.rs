@@ -0,0 +1,271 @@
+use crate::document::Document;
+use crate::error::Error;
+use crate::{FlatMapSink, HeaderCollector, Json2Csv, LongWriter, OutputShape, RowWriter};
+use serde_json::{Deserializer, Map, Value};
+use std::io::{SeekFrom, Write};
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite};
+use tokio::io::{AsyncWriteExt, BufReader};
+
+/// Size of the chunks read from the input and written to the output.
+const CHUNK_SIZE: usize = 8 * 1024;
+
+impl Json2Csv {
+    /// Flattens the JSON objects read from an asynchronous reader, transforming each of them into
+    /// a CSV row that is written to an asynchronous writer, without blocking the thread. It works
+    /// like `convert_from_reader` with a CSV writer created by `csv_writer`, storing the flattened
+    /// objects in a temporary file until the headers are known, but the input must contain JSON
+    /// objects one after the other, not in an array. The position of a parsing error is relative
+    /// to the end of the last value parsed before it. Requires the `tokio` feature.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub async fn convert_from_async_reader(
+        &self,
+        reader: impl AsyncRead + Unpin,
+        mut writer: impl AsyncWrite + Unpin,
+    ) -> Result<usize, Error> {
+        let mut collector = HeaderCollector::default();
+        let buffer = SharedBuffer::default();
+
+        if let OutputShape::Long { .. } = self.output_shape {
+            let mut long_writer = LongWriter::new(self, self.csv_writer(buffer.clone()));
+            self.flatten_async_reader(
+                reader,
+                &mut long_writer,
+                &mut collector,
+                &buffer,
+                &mut writer,
+            )
+            .await?;
+            let rows = long_writer.finish()?;
+            return finish_output(&buffer, writer).await.map(|()| rows);
+        }
+
+        if let Some(schema) = &self.schema {
+            let mut row_writer =
+                RowWriter::new(self, schema.headers(), self.csv_writer(buffer.clone()));
+            self.flatten_async_reader(
+                reader,
+                &mut row_writer,
+                &mut collector,
+                &buffer,
+                &mut writer,
+            )
+            .await?;
+            let rows = row_writer.finish()?;
+            return finish_output(&buffer, writer).await.map(|()| rows);
+        }
+
+        // Opening the file may block, so it is done in a thread where blocking is fine
+        let keep_temp_file = self.keep_temp_file.clone();
+        let file =
+            tokio::task::spawn_blocking(move || crate::open_temp_file(keep_temp_file.as_deref()))
+                .await
+                .map_err(std::io::Error::other)??;
+        let mut file = tokio::fs::File::from_std(file);
+        let mut batch = Batch {
+            len: 0,
+            buffer: buffer.clone(),
+        };
+        self.flatten_async_reader(reader, &mut batch, &mut collector, &buffer, &mut file)
+            .await?;
+
+        let headers = self.collected_headers(collector)?;
+        // If we could not extract headers there is nothing to write to the CSV file
+        if headers.is_empty() {
+            return Ok(0);
+        }
+
+        file.flush().await?;
+        file.seek(SeekFrom::Start(0)).await?;
+        let mut lines = BufReader::new(file).lines();
+        let mut row_writer = RowWriter::new(self, &headers, self.csv_writer(buffer.clone()));
+        while let Some(line) = lines.next_line().await? {
+            row_writer.add(serde_json::from_str(&line)?)?;
+            if buffer.len() >= CHUNK_SIZE {
+                writer.write_all(&buffer.take()).await?;
+            }
+        }
+        let rows = row_writer.finish()?;
+        finish_output(&buffer, writer).await.map(|()| rows)
+    }
+
+    /// Flattens the JSON objects read from `reader` and pushes them to `sink`, which writes them
+    /// to `buffer`, until the input ends or no more objects are needed. The content of the buffer
+    /// is written to `destination` after each chunk of the input is processed.
+    async fn flatten_async_reader(
+        &self,
+        mut reader: impl AsyncRead + Unpin,
+        sink: &mut impl FlatMapSink,
+        collector: &mut HeaderCollector,
+        buffer: &SharedBuffer,
+        destination: &mut (impl AsyncWrite + Unpin),
+    ) -> Result<(), Error> {
+        let mut input = Vec::new();
+        let mut chunk = vec![0; CHUNK_SIZE];
+        // Offset of the input where the documents that have not been flattened yet start
+        let mut start = 0;
+        // An incomplete document is parsed again once the input has doubled its size, instead of
+        // after every chunk, so that big documents are not parsed over and over
+        let mut retry_at = 0;
+        loop {
+            let read = reader.read(&mut chunk).await?;
+            input.extend_from_slice(&chunk[..read]);
+            let eof = read == 0;
+            if !eof && input.len() < retry_at {
+                continue;
+            }
+
+            let (consumed, flow) =
+                self.flatten_complete_documents(&input[start..], eof, sink, collector)?;
+            start += consumed;
+            collector.check_collisions()?;
+            destination.write_all(&buffer.take()).await?;
+            if eof || flow.is_break() {
+                return Ok(());
+            }
+            retry_at = 2 * input.len() - start;
+            if start > input.len() / 2 {
+                input.drain(..start);
+                retry_at -= start;
+                start = 0;
+            }
+        }
+    }
+
+    /// Flattens the complete JSON documents at the start of `input` and returns the number of
+    /// bytes they take, and whether more documents are needed. Unless the end of the input has
+    /// been reached, an incomplete document at the end is left for later, when more of the input
+    /// is available. With `set_skip_invalid` the rest of the line where an error is found is
+    /// skipped, like `convert_from_reader` does.
+    fn flatten_complete_documents(
+        &self,
+        input: &[u8],
+        eof: bool,
+        sink: &mut impl FlatMapSink,
+        collector: &mut HeaderCollector,
+    ) -> Result<(usize, ControlFlow<()>), Error> {
+        let mut consumed = 0;
+        loop {
+            let base = consumed;
+            let rest = &input[base..];
+            let mut documents = Deserializer::from_slice(rest).into_iter::<Document>();
+            let err = loop {
+                let document = match documents.next() {
+                    Some(Ok(document)) => document,
+                    Some(Err(err)) if err.is_eof() && !eof => {
+                        return Ok((consumed, ControlFlow::Continue(())))
+                    }
+                    Some(Err(err)) => break err,
+                    None => return Ok((input.len(), ControlFlow::Continue(()))),
+                };
+                let end = base + documents.byte_offset();
+                let document = match document.into_value(self.reject_duplicate_keys) {
+                    Ok(document) => document,
+                    Err(_) if self.skip_invalid => {
+                        consumed = end;
+                        continue;
+                    }
+                    Err(err) => return Err(err),
+                };
+                // A number or a literal at the end may continue in the next chunk of the input
+                let delimited = matches!(
+                    document,
+                    Value::Object(_) | Value::Array(_) | Value::String(_)
+                );
+                if !delimited && !eof && end == input.len() {
+                    return Ok((consumed, ControlFlow::Continue(())));
+                }
+                self.flatten_document(&document, sink, collector, &Map::new())?;
+                consumed = end;
+                if self.keep_reading(sink.len()).is_break() {
+                    return Ok((consumed, ControlFlow::Break(())));
+                }
+            };
+            if !self.skip_invalid {
+                return Err(err.into());
+            }
+            match end_of_line(rest, err.line()) {
+                Some(end) => consumed = base + end,
+                None if eof => return Ok((input.len(), ControlFlow::Continue(()))),
+                None => return Ok((consumed, ControlFlow::Continue(()))),
+            }
+        }
+    }
+}
+
+/// Returns the offset just after the end of the given line of `input`, counting from one, if
+/// the line is complete.
+fn end_of_line(input: &[u8], line: usize) -> Option<usize> {
+    let start: usize = input
+        .split_inclusive(|&byte| byte == b'\n')
+        .take(line.saturating_sub(1))
+        .map(<[u8]>::len)
+        .sum();
+    let end = input[start..].iter().position(|&byte| byte == b'\n')?;
+    Some(start + end + 1)
+}
+
+/// Writes what is left in the buffer to the writer and flushes it.
+async fn finish_output(
+    buffer: &SharedBuffer,
+    mut writer: impl AsyncWrite + Unpin,
+) -> Result<(), Error> {
+    writer.write_all(&buffer.take()).await?;
+    writer.flush().await?;
+    Ok(())
+}
+
+/// Writes the flattened objects to a buffer, one per line.
+struct Batch {
+    /// Number of objects pushed so far.
+    len: usize,
+    buffer: SharedBuffer,
+}
+
+impl FlatMapSink for Batch {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, map: Map<String, Value>, _index: usize) -> Result<(), Error> {
+        serde_json::to_writer(&mut self.buffer, &map)?;
+        self.buffer.write_all(b"\n")?;
+        self.len += 1;
+        Ok(())
+    }
+}
+
+/// Buffer written by the synchronous writers, like the CSV writer, which does not give access to
+/// its output, and emptied to write its content to an asynchronous writer.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn len(&self) -> usize {
+        self.0.lock().expect("The lock is never poisoned").len()
+    }
+
+    /// Returns the content of the buffer, leaving it empty.
+    fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.0.lock().expect("The lock is never poisoned"))
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0
+            .lock()
+            .expect("The lock is never poisoned")
+            .extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
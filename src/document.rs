@@ -0,0 +1,122 @@
+use crate::error::Error;
+use serde::de::value::SeqAccessDeserializer;
+use serde::de::{self, MapAccess, SeqAccess, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::map::Entry;
+use serde_json::{Map, Value};
+use std::fmt;
+
+/// A top level JSON document of the input, together with the first key repeated in it, if it is an
+/// object. `serde_json` keeps the last value of repeated keys, so they can only be detected while
+/// parsing.
+pub(crate) struct Document {
+    value: Value,
+    duplicate_key: Option<String>,
+}
+
+impl Document {
+    /// The parsed document, failing with `Error::DuplicateKey` if `reject_duplicate_keys` is set
+    /// and a key is repeated in it.
+    pub(crate) fn into_value(self, reject_duplicate_keys: bool) -> Result<Value, Error> {
+        match self.duplicate_key {
+            Some(key) if reject_duplicate_keys => Err(Error::DuplicateKey { key }),
+            _ => Ok(self.value),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Document {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(DocumentVisitor)
+    }
+}
+
+/// Parses the top level objects like `serde_json` does, remembering the first repeated key. Any
+/// other value is parsed by `serde_json` itself.
+struct DocumentVisitor;
+
+impl DocumentVisitor {
+    fn document(value: Value) -> Document {
+        Document {
+            value,
+            duplicate_key: None,
+        }
+    }
+}
+
+/// Key of the map `serde_json` uses to give numbers with `arbitrary_precision`.
+#[cfg(feature = "arbitrary_precision")]
+const NUMBER_TOKEN: &str = "$serde_json::private::Number";
+
+impl<'de> Visitor<'de> for DocumentVisitor {
+    type Value = Document;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Document, A::Error> {
+        let mut object = Map::new();
+        let mut duplicate_key = None;
+        while let Some(key) = map.next_key::<String>()? {
+            // With `arbitrary_precision` `serde_json` gives numbers as a map with a single private
+            // key, whose value is the number as it is written in the input.
+            #[cfg(feature = "arbitrary_precision")]
+            if object.is_empty() && key == NUMBER_TOKEN {
+                let number: String = map.next_value()?;
+                return number
+                    .parse()
+                    .map(|number| Self::document(Value::Number(number)))
+                    .map_err(de::Error::custom);
+            }
+            let value = map.next_value()?;
+            match object.entry(key) {
+                Entry::Vacant(entry) => {
+                    entry.insert(value);
+                }
+                Entry::Occupied(mut entry) => {
+                    if duplicate_key.is_none() {
+                        duplicate_key = Some(entry.key().clone());
+                    }
+                    entry.insert(value);
+                }
+            }
+        }
+        Ok(Document {
+            value: Value::Object(object),
+            duplicate_key,
+        })
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, seq: A) -> Result<Document, A::Error> {
+        Value::deserialize(SeqAccessDeserializer::new(seq)).map(Self::document)
+    }
+
+    fn visit_bool<E: de::Error>(self, value: bool) -> Result<Document, E> {
+        Ok(Self::document(Value::Bool(value)))
+    }
+
+    fn visit_i64<E: de::Error>(self, value: i64) -> Result<Document, E> {
+        Ok(Self::document(Value::from(value)))
+    }
+
+    fn visit_u64<E: de::Error>(self, value: u64) -> Result<Document, E> {
+        Ok(Self::document(Value::from(value)))
+    }
+
+    fn visit_f64<E: de::Error>(self, value: f64) -> Result<Document, E> {
+        Ok(Self::document(Value::from(value)))
+    }
+
+    fn visit_str<E: de::Error>(self, value: &str) -> Result<Document, E> {
+        Ok(Self::document(Value::from(value)))
+    }
+
+    fn visit_string<E: de::Error>(self, value: String) -> Result<Document, E> {
+        Ok(Self::document(Value::String(value)))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<Document, E> {
+        Ok(Self::document(Value::Null))
+    }
+}
@@ -9,9 +9,11 @@ pub enum Error {
     Flattening(#[from] flatten_json_object::Error),
 
     #[error(
-        "Two objects have keys that should be different but end looking the same after flattening"
+        "Two objects have keys that should be different but end looking the same after \
+         flattening: {}",
+        examples(keys)
     )]
-    FlattenedKeysCollision,
+    FlattenedKeysCollision { keys: Vec<String> },
 
     #[error("Writting a CSV record failed: {0}")]
     WrittingCSV(#[from] csv::Error),
@@ -19,9 +21,164 @@ pub enum Error {
     #[error("Parsing JSON failed: {0}")]
     ParsingJson(#[from] serde_json::Error),
 
+    #[error("Parsing the JSON value at position {index} of the input failed: {source}")]
+    ParsingDocument {
+        index: usize,
+        source: serde_json::Error,
+    },
+
+    #[error("Parsing the JSON object in line {line} of the input failed: {source}")]
+    ParsingLine {
+        line: usize,
+        source: serde_json::Error,
+    },
+
+    #[error("Converting the input at position {index} failed: {source}")]
+    Input { index: usize, source: Box<Error> },
+
     #[error("Input/output error: {0}")]
     InputOutput(#[from] std::io::Error),
 
     #[error("Could not extract the inner file from a BufWriter: {0}")]
     IntoFile(#[from] std::io::IntoInnerError<BufWriter<File>>),
+
+    #[cfg(feature = "zip")]
+    #[error("Writing to the ZIP archive failed: {0}")]
+    Zip(#[from] zip::result::ZipError),
+
+    #[error("The JSON pointer '{pointer}' does not point to an array or an object")]
+    RootPointer { pointer: String },
+
+    #[error("The key '{key}' contains empty keys")]
+    EmptyKey { key: String },
+
+    #[error("The deadline was exceeded after processing {objects_processed} objects")]
+    DeadlineExceeded { objects_processed: usize },
+
+    #[error("The input contains more than {limit} objects")]
+    TooManyObjects { limit: usize },
+
+    #[error("The object at position {object_index} has too many fields after flattening: {count}")]
+    TooManyFieldsInObject { object_index: usize, count: usize },
+
+    #[error("The key '{name}' can not be used as the name of a table")]
+    InvalidTableName { name: String },
+
+    #[error("The array at '{path}' mixes objects with other values")]
+    MixedArray { path: String },
+
+    #[error("The object at position {object_index} is not valid: {message}")]
+    ValidationFailed {
+        object_index: usize,
+        message: String,
+    },
+
+    #[error("The value at position {index} of the input is not an object")]
+    NotAnObject { index: usize },
+
+    #[error("The setting '{setting}' can not be recorded in a conversion plan")]
+    NotRecordable { setting: String },
+
+    #[error("The conversion plan has version {version}, which is newer than the supported one")]
+    UnsupportedPlanVersion { version: u32 },
+
+    #[error("The column '{name}' has the same name as a key of the flattened objects")]
+    ColumnNameClash { name: String },
+
+    #[error("The key '{key}' is not valid UTF-8 after decoding it")]
+    KeyDecoding { key: String },
+
+    #[error(
+        "The object at position {object_index} has the key '{key}', which is not in the schema"
+    )]
+    UnknownKey { object_index: usize, key: String },
+
+    #[error("The key '{key}' is repeated in an object of the input")]
+    DuplicateKey { key: String },
+
+    #[error("The CSV would have {count} columns, more than the limit of {limit}")]
+    TooManyColumns { count: usize, limit: usize },
+
+    #[error(
+        "The separator '{separator}' is empty or contains another one reserved for internal use, \
+         which can be changed with `set_internal_sentinels`"
+    )]
+    InvalidSeparatorConfig { separator: String },
+
+    #[error("The key '{key}' contains a character reserved for internal use")]
+    ReservedCharacterInInput { key: String },
+
+    #[error(
+        "The value of '{key}' in the object at position {object_index} is nested, which requires \
+         converting the objects with `convert_from_array`"
+    )]
+    NestedValueInFlatInput { object_index: usize, key: String },
+}
+
+/// Number of keys listed in the message of a collision error.
+const MAX_EXAMPLES: usize = 3;
+
+/// Lists the first keys, quoted, and how many are left.
+fn examples(keys: &[String]) -> String {
+    let mut examples = keys
+        .iter()
+        .take(MAX_EXAMPLES)
+        .map(|key| format!("'{key}'"))
+        .collect::<Vec<_>>()
+        .join(", ");
+    if keys.len() > MAX_EXAMPLES {
+        examples.push_str(&format!(" and {} more", keys.len() - MAX_EXAMPLES));
+    }
+    examples
+}
+
+impl Error {
+    /// Number of characters shown before and after the position of an error by `render_context`.
+    const CONTEXT_CHARS: usize = 20;
+
+    /// Renders the part of the input around the position where parsing the JSON failed, with a
+    /// caret pointing at it, so it can be shown to a human. `input` must be the input given to the
+    /// conversion. Returns `None` if the error is not a parsing error with a known position.
+    ///
+    /// The output looks like this:
+    ///
+    /// ```text
+    /// line 2, column 7:
+    /// {"a": x}
+    ///       ^
+    /// ```
+    #[must_use]
+    pub fn render_context(&self, input: &str) -> Option<String> {
+        let (line_number, err) = match self {
+            Error::ParsingJson(err) | Error::ParsingDocument { source: err, .. } => {
+                (err.line(), err)
+            }
+            // Each line is parsed on its own, so the error is always in its first line
+            Error::ParsingLine { line, source } => (*line, source),
+            _ => return None,
+        };
+        let line = input.lines().nth(line_number.checked_sub(1)?)?;
+
+        // The column is the 1-based byte position of the last consumed character
+        let mut offset = err.column().saturating_sub(1).min(line.len());
+        while !line.is_char_boundary(offset) {
+            offset -= 1;
+        }
+        let (before, after) = line.split_at(offset);
+
+        let before: Vec<char> = before.chars().collect();
+        let before: String = before[before.len().saturating_sub(Self::CONTEXT_CHARS)..]
+            .iter()
+            .collect();
+        let after: String = after.chars().take(Self::CONTEXT_CHARS + 1).collect();
+
+        Some(format!(
+            "line {}, column {}:\n{}{}\n{}^",
+            line_number,
+            err.column(),
+            before,
+            after,
+            " ".repeat(before.chars().count()),
+        ))
+    }
 }
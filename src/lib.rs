@@ -37,13 +37,38 @@
 //!
 //! - How objects are flattened and the CSV format (e.g. the field separator) can be configured.
 //! - Each top level object in the input will be transformed into a CSV row.
+//! - A reader may also contain a single JSON array of objects instead, whose elements are read one
+//!   at a time.
 //! - The headers are sorted alphabetically and are the union of all the keys in all the objects in
-//!   the input after they are flattened.
+//!   the input after they are flattened. They can also be kept in the order in which they are first
+//!   found with `set_header_order`.
 //! - Key collisions after flattening the input will be reported as errors, i.e. if two objects have
 //!   keys that should be different but end looking the same after flattening. For example,
 //!   flattening a file that contains `{"a": {"b": 1}} {"a.b": 2}` results by default in an error.
 //! - Any instance of `{}` (when not a top level object), `[]` or `Null` results in an empty CSV
-//!   field.
+//!   field. The text written for `Null` can be changed with `set_null_representation`.
+//!
+//! ### Features
+//!
+//! - `arbitrary_precision`: enables the feature of the same name of `serde_json`, so the numbers of
+//!   the input are written to the CSV exactly as they are in the input, however big or precise they
+//!   are, instead of being converted to a 64 bits integer or float first. Useful for IDs and
+//!   financial data.
+//! - `gzip`: adds `Json2Csv::convert_from_gzip_reader` and `Json2Csv::convert_from_reader_to_gzip`,
+//!   to read gzip compressed JSON and to write gzip compressed CSV.
+//! - `preserve_order`: enables the feature of the same name of `serde_json`, so the maps of JSON
+//!   objects keep the keys in the order they have in the input, instead of sorting them. This affects
+//!   the maps that this library exposes, like the flattened objects, but not the order of the CSV
+//!   columns, which is always the order of the headers.
+//! - `rayon`: flattens the objects of the arrays given to `Json2Csv::convert_from_array` in
+//!   parallel, a chunk at a time. The output is the same. `benches/convert_array.rs` measures the
+//!   conversion with and without it.
+//! - `serde`: implements `Serialize` and `Deserialize` for `ConversionPlan`, which records all the
+//!   settings of a conversion so it can be replayed later.
+//! - `tokio`: adds `Json2Csv::convert_from_async_reader`, to convert the objects read from a
+//!   `tokio::io::AsyncRead` without blocking the thread.
+//! - `zip`: adds `Json2Csv::convert_from_array_to_zip`, to write the CSV output as an entry of a ZIP
+//!   archive.
 //!
 //! ### Example reading from a `Read` implementer
 //!
@@ -147,30 +172,194 @@
 //!# }
 //! ```
 
+use document::Document;
 use flatten_json_object::ArrayFormatting;
-use serde_json::{Deserializer, Value};
-use std::collections::BTreeMap;
-use std::collections::BTreeSet;
+use options::natural_cmp;
+use serde::de::{SeqAccess, Visitor};
+use serde_json::{Deserializer, Map, Value};
+use std::borrow::{Borrow, Cow};
+use std::cmp::Ordering;
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::fmt;
+use std::fs::File;
 use std::io::Seek;
 use std::io::SeekFrom;
-use std::io::{BufReader, BufWriter};
+use std::io::{BufRead, BufReader, BufWriter};
 use std::io::{Read, Write};
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 use tempfile::tempfile;
 
 pub use csv;
 pub use error::Error;
 pub use flatten_json_object;
+pub use options::{
+    ArrayOfObjectsMode, Callback, CaseStyle, ComplexValueMode, EmptyKey, HeaderOrder,
+    HeaderStrategy, KeyDecoder, MetadataColumns, MixedArray, OutputShape, QuoteStyle,
+    RecordTerminator, ScalarElement, SortDir,
+};
+pub use plan::{ArrayFormattingPlan, ConversionPlan, FlattenerPlan};
+pub use report::{ConversionReport, DryRunReport};
+pub use schema::{HeaderDiff, Schema, SchemaBuilder};
+#[cfg(feature = "zip")]
+pub use zip;
 
+#[cfg(feature = "tokio")]
+mod async_io;
+mod document;
 mod error;
+mod options;
+mod plan;
+mod report;
+mod schema;
+
+/// Number of objects processed between two checks of the deadline.
+const DEADLINE_CHECK_INTERVAL: usize = 64;
+
+/// Number of objects flattened in parallel at a time with the `rayon` feature.
+#[cfg(feature = "rayon")]
+const PARALLEL_CHUNK_SIZE: usize = 1024;
+
+/// Byte order mark of UTF-8.
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
 
 /// Basic struct of this crate. It contains the configuration.Instantiate it and use the method
 /// `convert_from_array` or `convert_from_file` to convert the JSON input into a CSV file.
+///
+/// It is its own builder, like `Flattener`: `new` sets the defaults of every option, and each
+/// `set_*` method consumes the object and returns it with one option changed, so they can be
+/// chained. The conversion methods take `&self`, so a configured object can be reused.
+///
+/// ```
+/// use flatten_json_object::Flattener;
+/// use json_objects_to_csv::{HeaderOrder, Json2Csv};
+///
+/// let json2csv = Json2Csv::new(Flattener::new())
+///     .set_header_order(HeaderOrder::FirstSeen)
+///     .set_null_representation("NULL".to_string());
+/// let output = json2csv.convert_from_reader_to_string(r#"{"b": null} {"a": 1}"#.as_bytes())?;
+/// assert_eq!(output, "b,a\nNULL,\n,1\n");
+/// # Ok::<(), json_objects_to_csv::Error>(())
+/// ```
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Json2Csv {
     /// The flattener that we use internally.
     flattener: flatten_json_object::Flattener,
     /// The flattener provided by the user of the library.
     original_flattener: flatten_json_object::Flattener,
+    /// If `true`, `convert_from_array` stores the flattened objects in a temporary file instead of
+    /// keeping them in memory until the CSV is written.
+    spill_to_temp_file: bool,
+    /// If `true`, comment lines with statistics about the columns are appended after the rows.
+    emit_trailer_stats: bool,
+    /// How the keys of the input objects are decoded before flattening them.
+    key_decoder: KeyDecoder,
+    /// Columns with information about the origin of each row, added by `convert_from_files`.
+    metadata_columns: MetadataColumns,
+    /// JSON Pointer to the array or object to convert inside each top level JSON document.
+    root_pointer: Option<String>,
+    /// What to do with the empty keys of the input objects.
+    empty_key_handling: EmptyKey,
+    /// Instant after which the conversions are aborted.
+    deadline: Option<Instant>,
+    /// Maximum number of objects that can be converted.
+    max_objects: Option<usize>,
+    /// Headers to use instead of computing them from the input.
+    schema: Option<Schema>,
+    /// Quote character used by the CSV writers created by this object.
+    quote: u8,
+    /// Format of the line with the number of rows appended after the last row.
+    append_row_count: Option<String>,
+    /// What to do with the arrays that mix objects with other values.
+    mixed_array_handling: MixedArray,
+    /// Columns used to sort the rows before writing them.
+    sort_by_columns: Vec<(String, SortDir)>,
+    /// Which flattened keys become the headers of the CSV.
+    header_strategy: HeaderStrategy,
+    /// Shape of the CSV output.
+    output_shape: OutputShape,
+    /// Function that validates each object before flattening it.
+    object_validator: Option<Callback<ObjectValidator>>,
+    /// Escape character for quotes used by the CSV writers created by this object.
+    escape: Option<u8>,
+    /// If `true` quotes are escaped by doubling them, otherwise with the escape character.
+    double_quote: bool,
+    /// What to do with the values of the input that are not objects.
+    scalar_element_handling: ScalarElement,
+    /// Path of the file used instead of an anonymous temporary file, kept for debugging.
+    keep_temp_file: Option<PathBuf>,
+    /// Columns filled with the first non empty value of some candidate keys.
+    coalesce: Vec<(String, Vec<String>)>,
+    /// Maximum number of fields that a flattened object can have.
+    max_fields_per_object: Option<usize>,
+    /// Case style of the keys of the flattened objects.
+    header_case_style: CaseStyle,
+    /// If `false` keys with a null value are considered absent by the header strategy.
+    null_counts_as_present: bool,
+    /// Order of the headers of the CSV.
+    header_order: HeaderOrder,
+    /// Text written for null values.
+    null_representation: String,
+    /// Text written for the headers missing in a flattened object.
+    missing_representation: String,
+    /// Text written for the empty arrays and objects preserved by the flattener.
+    empty_container_representation: String,
+    /// The only keys of the flattened objects that are written, in order.
+    header_allowlist: Option<Vec<String>>,
+    /// Keys, or prefixes of keys if they end with `*`, of the flattened objects that are dropped.
+    header_denylist: Vec<String>,
+    /// If `true` the values of the input that can not be parsed are skipped.
+    skip_invalid: bool,
+    /// If `true` the keys that are not in the schema are an error instead of being dropped.
+    reject_unknown_keys: bool,
+    /// Texts written for `true` and `false`.
+    bool_representation: (String, String),
+    /// Number of decimals written for floating point numbers.
+    float_precision: Option<usize>,
+    /// If `true` the UTF-8 byte order mark is written before the headers.
+    write_bom: bool,
+    /// What to do with the arrays whose elements are all objects.
+    array_of_objects_mode: ArrayOfObjectsMode,
+    /// How the arrays and objects that were not flattened are written.
+    complex_value_mode: ComplexValueMode,
+    /// If `true` the keys repeated in a top level object of the input are an error.
+    reject_duplicate_keys: bool,
+    /// Terminator of the records written by the CSV writers created by this object.
+    terminator: RecordTerminator,
+    /// Maximum number of columns of the CSV.
+    max_columns: Option<usize>,
+    /// Columns appended after the headers, with the same value in every row.
+    constant_columns: Vec<(String, String)>,
+    /// Functions that write the values of some columns, by header.
+    column_transforms: BTreeMap<String, Callback<ColumnTransform>>,
+    /// Key separator, array start and array end used by the flattener that we use internally.
+    internal_sentinels: (String, String, String),
+    /// The separators that the flattener used internally puts in the flattened keys, or the one
+    /// that makes the configuration invalid. Computed when the flatteners change, so it is not
+    /// checked again for each object.
+    reserved_separators: Result<Vec<String>, String>,
+    /// If `true` the headers of a schema or an allowlist are written when there are no rows.
+    headers_without_rows: bool,
+    /// Which fields are quoted by the CSV writers created by this object.
+    quote_style: QuoteStyle,
+    /// Column prepended to the headers with the position of each row.
+    row_index_column: Option<String>,
+    /// Position of the first row in the row index column.
+    row_index_start: usize,
+    /// If `true` the empty strings are written like nulls.
+    empty_string_as_null: bool,
+    /// Function that writes the missing values of a column, by header.
+    missing_value_fn: Option<Callback<MissingValueFn>>,
+    /// If `true` the rows equal to a previous one are not written.
+    deduplicate_rows: bool,
+    /// Maximum number of rows written.
+    max_rows: Option<usize>,
+    /// If `true` and there is a maximum number of rows, the headers only come from the rows
+    /// written.
+    headers_from_written_rows: bool,
 }
 
 impl Json2Csv {
@@ -180,21 +369,782 @@ impl Json2Csv {
         // We use replace the separators provided with control characters (which should not be
         // present in normal input) to be able to detect collisions like the one that happens when
         // converting `[{"a": {"b": 1}} {"a.b": 2}]` to CSV with a `.` separator.
-        let key_sep = "␝";
-        let array_start = "␞";
-        let array_end = "␟";
-        Json2Csv {
-            flattener: match flattener.array_formatting() {
-                ArrayFormatting::Plain => flattener.clone().set_key_separator(key_sep),
-                ArrayFormatting::Surrounded { start: _, end: _ } => flattener
-                    .clone()
-                    .set_key_separator(key_sep)
-                    .set_array_formatting(ArrayFormatting::Surrounded {
-                        start: array_start.to_string(),
-                        end: array_end.to_string(),
-                    }),
-            },
+        let internal_sentinels = ("␝".to_string(), "␞".to_string(), "␟".to_string());
+        let mut json2csv = Json2Csv {
+            flattener: Self::internal_flattener(&flattener, &internal_sentinels),
             original_flattener: flattener,
+            spill_to_temp_file: false,
+            emit_trailer_stats: false,
+            key_decoder: KeyDecoder::None,
+            metadata_columns: MetadataColumns::default(),
+            root_pointer: None,
+            empty_key_handling: EmptyKey::Keep,
+            deadline: None,
+            max_objects: None,
+            schema: None,
+            quote: b'"',
+            append_row_count: None,
+            mixed_array_handling: MixedArray::Flatten,
+            sort_by_columns: Vec::new(),
+            header_strategy: HeaderStrategy::Union,
+            output_shape: OutputShape::Wide,
+            object_validator: None,
+            escape: None,
+            double_quote: true,
+            scalar_element_handling: ScalarElement::Error,
+            keep_temp_file: None,
+            coalesce: Vec::new(),
+            max_fields_per_object: None,
+            header_case_style: CaseStyle::AsIs,
+            null_counts_as_present: true,
+            header_order: HeaderOrder::Alphabetical,
+            null_representation: String::new(),
+            missing_representation: String::new(),
+            empty_container_representation: String::new(),
+            header_allowlist: None,
+            header_denylist: Vec::new(),
+            skip_invalid: false,
+            reject_unknown_keys: false,
+            bool_representation: ("true".to_string(), "false".to_string()),
+            float_precision: None,
+            write_bom: false,
+            array_of_objects_mode: ArrayOfObjectsMode::Flatten,
+            complex_value_mode: ComplexValueMode::Blank,
+            reject_duplicate_keys: false,
+            terminator: RecordTerminator::Any(b'\n'),
+            max_columns: None,
+            constant_columns: Vec::new(),
+            column_transforms: BTreeMap::new(),
+            internal_sentinels,
+            reserved_separators: Ok(Vec::new()),
+            headers_without_rows: true,
+            quote_style: QuoteStyle::Necessary,
+            row_index_column: None,
+            row_index_start: 0,
+            empty_string_as_null: false,
+            missing_value_fn: None,
+            deduplicate_rows: false,
+            max_rows: None,
+            headers_from_written_rows: false,
+        };
+        json2csv.reserved_separators = json2csv.reserved_separators();
+        json2csv
+    }
+
+    /// Makes `convert_from_array` store the flattened objects in a temporary file, like
+    /// `convert_from_reader` does, instead of keeping all of them in memory while the headers are
+    /// computed. This reduces the peak memory usage when converting big slices, at the cost of
+    /// some extra IO. Disabled by default.
+    #[must_use]
+    pub fn set_spill_to_temp_file(mut self, value: bool) -> Self {
+        self.spill_to_temp_file = value;
+        self
+    }
+
+    /// Appends some comment lines, prefixed by `#`, after the last row of the CSV output. They
+    /// contain the number of rows, the number of columns and the fill rate of each column, i.e.
+    /// the percentage of rows that have a non empty value in it. Note that most CSV readers do not
+    /// understand comments unless explicitly configured to do so. Disabled by default.
+    ///
+    /// The output would look like this:
+    ///
+    /// ```csv
+    /// a,b
+    /// 1,
+    /// 2,3
+    /// # rows: 2
+    /// # columns: 2
+    /// # fill rate a: 100.00%
+    /// # fill rate b: 50.00%
+    /// ```
+    #[must_use]
+    pub fn set_emit_trailer_stats(mut self, value: bool) -> Self {
+        self.emit_trailer_stats = value;
+        self
+    }
+
+    /// Sets how the keys of the input objects, at any nesting level, are decoded before the
+    /// objects are flattened. By default keys are used as they are.
+    ///
+    /// Since decoding happens before flattening, decoded keys take part in the collision
+    /// detection. For example, with `KeyDecoder::PercentDecode` and a `.` key separator, the key
+    /// `a%2Eb` is decoded into `a.b`, which collides with the flattened key of `{"a": {"b": 1}}`.
+    #[must_use]
+    pub fn set_key_decoder(mut self, key_decoder: KeyDecoder) -> Self {
+        self.key_decoder = key_decoder;
+        self
+    }
+
+    /// Sets the metadata columns that `convert_from_files`, `convert_from_readers` and
+    /// `convert_from_reader_to_path` add to each row, with the path of the file or the label of
+    /// the reader the row comes from and the time at which the conversion started. They are headers like the keys of the objects, so they are
+    /// ordered and counted like them. By default no metadata columns are added.
+    #[must_use]
+    pub fn set_metadata_columns(mut self, metadata_columns: MetadataColumns) -> Self {
+        self.metadata_columns = metadata_columns;
+        self
+    }
+
+    /// Sets a [JSON Pointer](https://datatracker.ietf.org/doc/html/rfc6901), like `/results`, that
+    /// selects what is converted inside each top level JSON document of the input, ignoring the
+    /// rest of the document. If it points to an array each one of its elements is converted into
+    /// a row, and if it points to an object that object is converted into a row. By default the
+    /// top level documents are converted.
+    ///
+    /// For example, with the pointer `/results` the document
+    /// `{"count": 2, "results": [{"a": 1}, {"a": 2}]}` produces two rows.
+    #[must_use]
+    pub fn set_root_pointer(mut self, root_pointer: Option<String>) -> Self {
+        self.root_pointer = root_pointer;
+        self
+    }
+
+    /// Sets what happens with the empty keys of the input objects, like the one in `{"": 1}`, at
+    /// any nesting level. By default they are kept, which can result in empty headers or headers
+    /// that start or end with the key separator, like `a.` for `{"a": {"": 1}}`.
+    #[must_use]
+    pub fn set_empty_key_handling(mut self, empty_key_handling: EmptyKey) -> Self {
+        self.empty_key_handling = empty_key_handling;
+        self
+    }
+
+    /// Sets an instant after which the conversions are aborted with
+    /// `Error::DeadlineExceeded`. The deadline is checked periodically while the input is
+    /// flattened and while the CSV rows are written. In the latter case the rows written so far
+    /// are flushed before returning the error. By default there is no deadline.
+    #[must_use]
+    pub fn set_deadline(mut self, deadline: Option<Instant>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    /// Sets the maximum number of objects that can be converted. Conversions of inputs with more
+    /// objects fail with `Error::TooManyObjects` as soon as the limit is exceeded, without reading
+    /// the rest of the input. By default there is no limit.
+    #[must_use]
+    pub fn set_max_objects(mut self, max_objects: Option<usize>) -> Self {
+        self.max_objects = max_objects;
+        self
+    }
+
+    /// Sets the maximum number of rows written by the conversions, e.g. to preview big inputs.
+    /// Unlike `set_max_objects`, inputs with more objects are not an error: the rest of the rows
+    /// are just not written. By default the whole input is still read and the headers are the
+    /// ones of all the objects, see `set_headers_from_written_rows`. The limit applies after
+    /// sorting and deduplicating the rows, and in the long shape it limits the number of objects
+    /// written. By default there is no limit.
+    #[must_use]
+    pub fn set_max_rows(mut self, max_rows: Option<usize>) -> Self {
+        self.max_rows = max_rows;
+        self
+    }
+
+    /// If `true` and there is a maximum number of rows, the conversions stop reading the input as
+    /// soon as the objects of those rows are found, and the headers only come from them, so big
+    /// inputs can be previewed without processing all of them. Note that then the rows sorted or
+    /// deduplicated are only the ones of those objects. Disabled by default.
+    #[must_use]
+    pub fn set_headers_from_written_rows(mut self, headers_from_written_rows: bool) -> Self {
+        self.headers_from_written_rows = headers_from_written_rows;
+        self
+    }
+
+    /// Makes the conversions use the headers of the schema instead of computing them from the
+    /// input, so the output always has the same columns. Keys of the flattened objects that are
+    /// not part of the schema are ignored. Since there is no need to read the whole input to
+    /// compute the headers, the rows are written as soon as the objects are flattened, without
+    /// using temporary files. Note that the rows written before an error is found, like a key
+    /// collision, are kept in the output. If the input is empty the output only has the headers.
+    /// The header row has the names given to the headers with `Schema::rename`, if any.
+    #[must_use]
+    pub fn with_schema(mut self, schema: Schema) -> Self {
+        self.schema = Some(schema);
+        self
+    }
+
+    /// Sets the quote character used by the CSV writers that this object creates, i.e. the ones
+    /// returned by `csv_writer` and the ones used internally by methods that do not receive a CSV
+    /// writer. Quote characters inside the fields are escaped as configured with
+    /// `set_double_quote`. By default `"` is used.
+    #[must_use]
+    pub fn set_quote(mut self, quote: u8) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    /// Sets the character used to escape quote characters inside the fields when they are not
+    /// escaped by doubling them, see `set_double_quote`. `None` means the default, `\`.
+    #[must_use]
+    pub fn set_escape(mut self, escape: Option<u8>) -> Self {
+        self.escape = escape;
+        self
+    }
+
+    /// Sets if quote characters inside the fields are escaped by doubling them, as in `"a""b"`,
+    /// which is the standard way. If `false` they are preceded by the escape character instead,
+    /// as in `"a\"b"`, which some non standard consumers expect. By default they are doubled.
+    #[must_use]
+    pub fn set_double_quote(mut self, double_quote: bool) -> Self {
+        self.double_quote = double_quote;
+        self
+    }
+
+    /// Sets the terminator of the records written by the CSV writers created by this object, like
+    /// the ones of `csv_writer` and the methods that write to strings or files. Every record ends
+    /// with it, including the last one, so the output always ends with a terminator unless it is
+    /// empty. Use `RecordTerminator::Crlf` for consumers that require `\r\n`. CSV writers created
+    /// otherwise must be configured with `csv::WriterBuilder::terminator` instead. The lines of
+    /// the row count and the trailer statistics end with this terminator in any case. By default
+    /// records end with `\n`.
+    #[must_use]
+    pub fn set_terminator(mut self, terminator: RecordTerminator) -> Self {
+        self.terminator = terminator;
+        self
+    }
+
+    /// Sets which fields are quoted by the CSV writers created by this object, like the ones of
+    /// `csv_writer` and the methods that write to strings or files. With `QuoteStyle::Always`
+    /// every field is quoted, including the numbers and the empty fields of the missing and empty
+    /// values, which are written as `""`. CSV writers created otherwise must be configured with
+    /// `csv::WriterBuilder::quote_style` instead. By default only the fields that need it are
+    /// quoted.
+    #[must_use]
+    pub fn set_quote_style(mut self, quote_style: QuoteStyle) -> Self {
+        self.quote_style = quote_style;
+        self
+    }
+
+    /// Sets the maximum number of columns that the CSV can have. If the headers computed from the
+    /// input are more, the conversion fails before writing anything, instead of producing a CSV
+    /// with thousands of columns, usually because of a big array. Unlike
+    /// `set_max_fields_per_object`, it limits the columns of all the objects together. The
+    /// headers of a schema are not limited. By default there is no limit.
+    #[must_use]
+    pub fn set_max_columns(mut self, max_columns: Option<usize>) -> Self {
+        self.max_columns = max_columns;
+        self
+    }
+
+    /// Prepends a column with the given name to the headers, with the position of each row in the
+    /// output, which is counted from the value set with `set_row_index_start`. The rows are
+    /// numbered after sorting them, so the numbers always grow. Like the constant columns, with a
+    /// schema it is only written if the schema has its column, and it is not part of the long
+    /// shape.
+    ///
+    /// The conversion fails with `Error::ColumnNameClash` as soon as an object has a flattened
+    /// key with the same name, or if the header allowlist has the name. Disabled by default.
+    #[must_use]
+    pub fn set_row_index_column(mut self, row_index_column: Option<String>) -> Self {
+        self.row_index_column = row_index_column;
+        self
+    }
+
+    /// Sets the position of the first row in the column of `set_row_index_column`, usually `0` or
+    /// `1`. By default it is `0`.
+    #[must_use]
+    pub fn set_row_index_start(mut self, row_index_start: usize) -> Self {
+        self.row_index_start = row_index_start;
+        self
+    }
+
+    /// Adds a column named `name` with `value` in every row, e.g. to tag the rows with the batch
+    /// they come from. The constant columns are appended after the headers computed from the
+    /// input, in the order they are added. If a schema is used they are only written if the schema
+    /// has their columns. They are not part of the long shape.
+    ///
+    /// A constant column never takes the value of a key of the input: the conversion fails with
+    /// `Error::ColumnNameClash` as soon as an object has a flattened key with the same name, even
+    /// if a schema is used, and also if the header allowlist has the name. The keys dropped by the
+    /// header denylist do not clash.
+    #[must_use]
+    pub fn add_constant_column(mut self, name: String, value: String) -> Self {
+        self.constant_columns.push((name, value));
+        self
+    }
+
+    /// Registers a function that writes the values of the column `header` instead of the default
+    /// rendering, e.g. to redact or normalize them. The function receives the flattened value,
+    /// which is never an array or an object unless they are empty, and returns the content of the
+    /// cell. It is not called for the rows where the key is missing, which are written as usual.
+    /// With the long output shape it is called for the values of the rows with `header` as key.
+    /// Registering another function for the same header replaces the previous one. By default
+    /// there are no transforms.
+    #[must_use]
+    pub fn add_column_transform(
+        mut self,
+        header: String,
+        transform: impl Fn(&Value) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.column_transforms
+            .insert(header, Callback::new(Arc::new(transform)));
+        self
+    }
+
+    /// Sets if the headers are written when the input produces no rows, so downstream tools still
+    /// see the columns of an empty input. It only applies when the headers are known in advance,
+    /// because they come from a schema, as with `with_schema` or
+    /// `convert_from_reader_with_headers`, or from `set_header_allowlist`. Otherwise an input
+    /// without rows has no headers, and the output is always empty. Enabled by default.
+    #[must_use]
+    pub fn set_headers_without_rows(mut self, value: bool) -> Self {
+        self.headers_without_rows = value;
+        self
+    }
+
+    /// Creates a CSV writer that writes to `writer`, configured with the CSV options of this
+    /// object (e.g. the quote character or the byte order mark), that can be passed to the
+    /// conversion methods.
+    pub fn csv_writer<W: Write>(&self, writer: W) -> csv::Writer<impl Write> {
+        self.writer_builder()
+            .from_writer(BomWriter::new(writer, self.write_bom))
+    }
+
+    /// Creates a CSV writer like `csv_writer`, but that separates the fields with tabs, to write
+    /// TSV. The fields that contain tabs, quotes or line breaks are quoted.
+    pub fn tsv_writer<W: Write>(&self, writer: W) -> csv::Writer<impl Write> {
+        self.writer_builder()
+            .delimiter(b'\t')
+            .from_writer(BomWriter::new(writer, self.write_bom))
+    }
+
+    /// A CSV writer builder configured with the CSV options of this object.
+    fn writer_builder(&self) -> csv::WriterBuilder {
+        let mut builder = csv::WriterBuilder::new();
+        builder
+            .quote(self.quote)
+            .double_quote(self.double_quote)
+            .terminator(self.terminator.into())
+            .quote_style(self.quote_style.into());
+        if let Some(escape) = self.escape {
+            builder.escape(escape);
+        }
+        builder
+    }
+
+    /// Appends a line with the number of data rows after the last row of the CSV output, for
+    /// consumers that expect it. The line is the provided format string with `{}` replaced by the
+    /// number of rows, e.g. `# rows: {}`, and it is written as it is, without quoting. Note that
+    /// this makes the output non-standard CSV. If the trailer statistics are also enabled they are
+    /// written after this line. The line is written whenever the headers are, even if there are no
+    /// rows. By default no line is appended.
+    #[must_use]
+    pub fn set_append_row_count(mut self, format: Option<String>) -> Self {
+        self.append_row_count = format;
+        self
+    }
+
+    /// Sets what happens with the arrays that mix objects with other values, like the one in
+    /// `{"a": [1, {"b": 2}, 3]}`. By default they are flattened like any other array, which
+    /// produces a mix of columns with scalar values and columns with the keys of the nested
+    /// objects. The arrays are inspected after the keys are decoded and before flattening.
+    #[must_use]
+    pub fn set_mixed_array_handling(mut self, mixed_array_handling: MixedArray) -> Self {
+        self.mixed_array_handling = mixed_array_handling;
+        self
+    }
+
+    /// Sorts the rows by the given columns before writing them. Rows are compared by the first
+    /// column, then by the second one when the first has the same value, and so on. Rows that are
+    /// still equal keep the order of the input. By default rows are not sorted.
+    ///
+    /// Numbers are compared numerically and strings lexicographically. If a column mixes values of
+    /// different types, booleans go before numbers and numbers before strings. Rows that do not
+    /// have a value in a column always go after the ones that have it, in both directions.
+    ///
+    /// Note that to sort them all the rows are kept in memory until the last one is flattened.
+    #[must_use]
+    pub fn set_sort_by_columns(mut self, columns: Vec<(String, SortDir)>) -> Self {
+        self.sort_by_columns = columns;
+        self
+    }
+
+    /// Makes the conversions skip the rows whose fields are exactly the same as the ones of a row
+    /// already written, keeping the first one, e.g. for logs with many repeated entries. The
+    /// column of `set_row_index_column` is not compared. The returned number of rows, the row
+    /// count and the trailer statistics only count the rows written. It does not apply to the long
+    /// shape. Disabled by default.
+    ///
+    /// Note that every distinct row is kept in memory until the conversion ends, even when the
+    /// rows are read from a temporary file, so it needs as much memory as the deduplicated
+    /// output.
+    #[must_use]
+    pub fn set_deduplicate_rows(mut self, deduplicate_rows: bool) -> Self {
+        self.deduplicate_rows = deduplicate_rows;
+        self
+    }
+
+    /// Sets which flattened keys become the headers of the CSV. By default it is the union of the
+    /// keys of all the objects. With `HeaderStrategy::Intersection` only the keys present in every
+    /// object are used, which produces an output without empty cells due to missing keys, and the
+    /// rest of the values are dropped. The headers of a schema are used as they are.
+    ///
+    /// The keys are the ones that result from flattening, so an empty array or object only
+    /// counts as present in an object if the flattener preserves it, in which case its key is
+    /// kept only if every object has an empty array or object there too, since a non empty one
+    /// is flattened into other keys. Keys with a `null` value count as present only with
+    /// `set_null_counts_as_present`.
+    #[must_use]
+    pub fn set_header_strategy(mut self, header_strategy: HeaderStrategy) -> Self {
+        self.header_strategy = header_strategy;
+        self
+    }
+
+    /// Sets the shape of the CSV output of `convert_from_array` and `convert_from_reader`. By
+    /// default it is wide, with one row per object. The long shape, with one row per flattened
+    /// key, is written as the input is read, and the schema, the header strategy, the sorting and
+    /// the trailers do not apply to it.
+    ///
+    /// For example, `{"a": 1, "b": {"c": "x"}}` with `OutputShape::Long { with_types: true,
+    /// skip_empty: false }` results in:
+    ///
+    /// ```csv
+    /// row_id,key,value,type
+    /// 0,a,1,number
+    /// 0,b.c,x,string
+    /// ```
+    #[must_use]
+    pub fn set_output_shape(mut self, output_shape: OutputShape) -> Self {
+        self.output_shape = output_shape;
+        self
+    }
+
+    /// Sets a function that validates each object of the input before it is flattened, to enforce
+    /// rules like required fields or value ranges during the conversion. If it returns
+    /// `Err(message)` the conversion fails with `Error::ValidationFailed`, which contains the
+    /// position of the object in the input, starting at 0, and the message. The objects are passed
+    /// as they are in the input, before decoding their keys. By default objects are not
+    /// validated.
+    #[must_use]
+    pub fn set_object_validator(
+        mut self,
+        validator: impl Fn(&Value) -> Result<(), String> + Send + Sync + 'static,
+    ) -> Self {
+        self.object_validator = Some(Callback::new(Arc::new(validator)));
+        self
+    }
+
+    /// Sets what happens with the values of the input that should be objects but are scalars or
+    /// arrays, like the `null` in `{"a": 1} null {"b": 2}`, which some log streams emit from
+    /// time to time. By default they are reported with `Error::NotAnObject`, but they can also be
+    /// skipped or converted into an empty row.
+    #[must_use]
+    pub fn set_scalar_element_handling(mut self, scalar_element_handling: ScalarElement) -> Self {
+        self.scalar_element_handling = scalar_element_handling;
+        self
+    }
+
+    /// Debugging aid: makes the conversions that store the flattened objects in a temporary file
+    /// use the file at `path` instead, and keep it after the conversion, so the intermediate data
+    /// can be inspected. It contains the flattened objects as JSON, one after the other. The file
+    /// is overwritten if it exists. By default an anonymous temporary file is used, which is
+    /// deleted when no longer necessary. Not meant to be used in normal runs.
+    #[must_use]
+    pub fn set_keep_temp_file(mut self, path: Option<PathBuf>) -> Self {
+        self.keep_temp_file = path;
+        self
+    }
+
+    /// Merges several flattened keys into a single column, for inputs where a value can be under
+    /// different keys depending on the source, like `email` or `email_address`. For each pair of
+    /// column and candidate keys, the column takes the value of the first candidate, in the order
+    /// provided, that is not empty, and the candidates are removed from the headers. The column is
+    /// only added to the objects that have any of the candidates, and it can have the same name as
+    /// one of them. By default no keys are merged.
+    #[must_use]
+    pub fn set_coalesce(mut self, coalesce: Vec<(String, Vec<String>)>) -> Self {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Sets the maximum number of fields that an object can have after flattening it. Objects with
+    /// more fields make the conversion fail, which protects the output from a single object with
+    /// a huge array that would add thousands of columns to the headers. By default there is no
+    /// limit.
+    #[must_use]
+    pub fn set_max_fields_per_object(mut self, max_fields_per_object: Option<usize>) -> Self {
+        self.max_fields_per_object = max_fields_per_object;
+        self
+    }
+
+    /// Sets the case style of the headers, for consistent column names across sources that use
+    /// different conventions. Each key of the input objects is converted on its own, so the key
+    /// separator and the array formatting of the flattener are preserved. If two different keys
+    /// end looking the same after the conversion, like `userName` and `user_name` with
+    /// `CaseStyle::Snake`, it is reported as a collision. By default keys are used as they are.
+    #[must_use]
+    pub fn set_header_case_style(mut self, header_case_style: CaseStyle) -> Self {
+        self.header_case_style = header_case_style;
+        self
+    }
+
+    /// Converts each segment of a flattened key, which still has our magic separators, to the case
+    /// style requested.
+    fn apply_case_style<'a>(&self, key: &'a str) -> Cow<'a, str> {
+        if self.header_case_style == CaseStyle::AsIs {
+            return Cow::Borrowed(key);
+        }
+
+        let mut separators = vec![self.flattener.key_separator()];
+        if let ArrayFormatting::Surrounded { start, end } = self.flattener.array_formatting() {
+            separators.extend([start.as_str(), end.as_str()]);
+        }
+
+        let mut converted = String::with_capacity(key.len());
+        let mut rest = key;
+        while let Some((pos, separator)) = separators
+            .iter()
+            .filter_map(|separator| rest.find(separator).map(|pos| (pos, separator)))
+            .min_by_key(|(pos, _)| *pos)
+        {
+            converted.push_str(&self.header_case_style.convert(&rest[..pos]));
+            converted.push_str(separator);
+            rest = &rest[pos + separator.len()..];
+        }
+        converted.push_str(&self.header_case_style.convert(rest));
+        Cow::Owned(converted)
+    }
+
+    /// Sets if a key with a `null` value counts as present when analyzing which keys the objects
+    /// have. If `false`, with `HeaderStrategy::Intersection` a key only becomes a column if all the
+    /// objects have it with a value other than `null`, so keys that are `null` in some objects are
+    /// dropped. It has no effect on `HeaderStrategy::Union`, since there the columns of null
+    /// values are still needed by the objects that have other values, nor on the types reported by
+    /// the data dictionary, which always ignore nulls. By default nulls count as present.
+    #[must_use]
+    pub fn set_null_counts_as_present(mut self, null_counts_as_present: bool) -> Self {
+        self.null_counts_as_present = null_counts_as_present;
+        self
+    }
+
+    /// Sets the order of the headers computed from the input. By default they are sorted
+    /// alphabetically, but they can also be in the order in which they are first found in the
+    /// input, which often mirrors the layout of the source better. The headers of a schema are
+    /// used as they are.
+    #[must_use]
+    pub fn set_header_order(mut self, header_order: HeaderOrder) -> Self {
+        self.header_order = header_order;
+        self
+    }
+
+    /// Sets the text written for `null` values, like `null` or `\\N`, to tell them apart from
+    /// missing keys, which are still written as empty fields. By default nulls are written as
+    /// empty fields too.
+    #[must_use]
+    pub fn set_null_representation(mut self, null_representation: String) -> Self {
+        self.null_representation = null_representation;
+        self
+    }
+
+    /// Makes the empty strings be written like `null` values, with the text set with
+    /// `set_null_representation`, for inputs that use them for missing data. Disabled by default.
+    #[must_use]
+    pub fn set_empty_string_as_null(mut self, empty_string_as_null: bool) -> Self {
+        self.empty_string_as_null = empty_string_as_null;
+        self
+    }
+
+    /// Sets the text written in the columns of the keys that a flattened object does not have, to
+    /// tell them apart from values that are present but empty. By default they are written as
+    /// empty fields.
+    #[must_use]
+    pub fn set_missing_representation(mut self, missing_representation: String) -> Self {
+        self.missing_representation = missing_representation;
+        self
+    }
+
+    /// Sets a function that writes the columns of the keys that a flattened object does not have,
+    /// instead of `set_missing_representation`, e.g. to fill them with a default that depends on
+    /// the column, like `0` for a count. The function receives the header of the column and
+    /// returns the content of the cell. By default the missing representation is used for all
+    /// the columns.
+    #[must_use]
+    pub fn set_missing_value_fn(
+        mut self,
+        missing_value_fn: impl Fn(&str) -> String + Send + Sync + 'static,
+    ) -> Self {
+        self.missing_value_fn = Some(Callback::new(Arc::new(missing_value_fn)));
+        self
+    }
+
+    /// Sets the text written for the empty arrays and objects, which only have a column if the
+    /// flattener is set to preserve them. By default they are written as empty fields.
+    #[must_use]
+    pub fn set_empty_container_representation(
+        mut self,
+        empty_container_representation: String,
+    ) -> Self {
+        self.empty_container_representation = empty_container_representation;
+        self
+    }
+
+    /// Sets the only keys of the flattened objects that are written, which are the headers of the
+    /// CSV in the order given, replacing the ones computed from the input. The rest of the keys
+    /// are dropped as soon as the objects are flattened, so collisions between them are not
+    /// reported. The keys that no object has are still written as empty columns, so the headers
+    /// are always the same. By default all the keys are written.
+    #[must_use]
+    pub fn set_header_allowlist(mut self, header_allowlist: Option<Vec<String>>) -> Self {
+        self.header_allowlist = header_allowlist;
+        self
+    }
+
+    /// Sets keys of the flattened objects that are never written. A key that ends with `*` drops
+    /// all the keys that start with the rest of it, e.g. `debug.*` drops `debug.trace` and
+    /// `debug.span.id`. The keys are dropped as soon as the objects are flattened, before looking
+    /// for collisions, so collisions between dropped keys are not reported. The keys are matched
+    /// after applying the case style, so they must look like the headers of the CSV. The keys of
+    /// the allowlist that are denied still have a column, which is always empty. By default no key
+    /// is dropped.
+    #[must_use]
+    pub fn set_header_denylist(mut self, header_denylist: Vec<String>) -> Self {
+        self.header_denylist = header_denylist;
+        self
+    }
+
+    /// Returns `true` if the key of a flattened object has to be written according to the
+    /// allowlist and the denylist.
+    fn is_key_allowed(&self, key: &str) -> bool {
+        let denied = self
+            .header_denylist
+            .iter()
+            .any(|denied| match denied.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == denied,
+            });
+        let allowed = self.header_allowlist.as_ref().map_or(true, |allowlist| {
+            allowlist.iter().any(|allowed| allowed == key)
+        });
+        allowed && !denied
+    }
+
+    /// Makes the conversions from readers skip the values of the input that can not be parsed,
+    /// instead of failing, for best effort conversions. Parsing resumes at the line after the one
+    /// where the error was found, so this works best with inputs with one object per line. The
+    /// errors are reported by `convert_from_reader_with_report` and
+    /// `convert_from_reader_with_error_sink`, and the positions of those after the first one are
+    /// relative to the line where parsing resumed. Errors in the middle of a top level JSON array
+    /// can not be skipped. Disabled by default.
+    #[must_use]
+    pub fn set_skip_invalid(mut self, skip_invalid: bool) -> Self {
+        self.skip_invalid = skip_invalid;
+        self
+    }
+
+    /// Makes the conversions with a schema, or with the headers given to methods like
+    /// `convert_from_array_append`, fail if a flattened object has a key that is not one of the
+    /// headers, instead of silently dropping it. Useful to detect when the input
+    /// does not match the schema anymore. Disabled by default.
+    #[must_use]
+    pub fn set_reject_unknown_keys(mut self, reject_unknown_keys: bool) -> Self {
+        self.reject_unknown_keys = reject_unknown_keys;
+        self
+    }
+
+    /// Sets the texts written for `true` and `false`, like `1` and `0` or `yes` and `no`. By
+    /// default they are written as `true` and `false`. Only the values are affected, not the keys.
+    /// In the columns of booleans, the objects that do not have the key are still written with
+    /// `set_missing_representation`, and the `null` values with `set_null_representation`.
+    #[must_use]
+    pub fn set_bool_representation(mut self, true_str: String, false_str: String) -> Self {
+        self.bool_representation = (true_str, false_str);
+        self
+    }
+
+    /// Sets the number of decimals written for floating point numbers, which are rounded or padded
+    /// with zeros as needed, e.g. with a precision of 2 `1.005e3` is written as `1005.00`. This
+    /// avoids the scientific notation and the long fractions that some CSV consumers can not
+    /// handle. Integers are written as they are. By default floats are written with the shortest
+    /// representation that keeps their value.
+    #[must_use]
+    pub fn set_float_precision(mut self, float_precision: Option<usize>) -> Self {
+        self.float_precision = float_precision;
+        self
+    }
+
+    /// Makes the CSV writers created by this object write the UTF-8 byte order mark at the start
+    /// of the output, before the headers, which some programs, like Excel, need to read non ASCII
+    /// characters correctly. Nothing is written if the output is empty. Like the other CSV
+    /// options, it only applies to the writers created by `csv_writer` and `tsv_writer`, and to
+    /// the methods that create their own writer, like `convert_from_array_to_string`. Disabled by
+    /// default.
+    #[must_use]
+    pub fn set_write_bom(mut self, write_bom: bool) -> Self {
+        self.write_bom = write_bom;
+        self
+    }
+
+    /// Sets what happens with the arrays whose elements are all objects, like the one in
+    /// `{"items": [{"x": 1}, {"x": 2}]}`. By default they are flattened like any other array,
+    /// which produces the columns `items.0.x` and `items.1.x`, so the number of columns depends on
+    /// the length of the longest array. With `ArrayOfObjectsMode::JsonString` they are written as
+    /// JSON in a single column instead, `items` in the example. The arrays are inspected after the
+    /// keys are decoded and before the mixed arrays are handled.
+    #[must_use]
+    pub fn set_array_of_objects_mode(mut self, array_of_objects_mode: ArrayOfObjectsMode) -> Self {
+        self.array_of_objects_mode = array_of_objects_mode;
+        self
+    }
+
+    /// Sets how the arrays and objects that were not flattened are written. The flattener only
+    /// leaves the empty ones, when it is set to preserve them, but with
+    /// `ComplexValueMode::JsonString` any non empty one that reaches the output is written as
+    /// compact JSON instead of being blanked. Blanked by default.
+    #[must_use]
+    pub fn set_complex_value_mode(mut self, complex_value_mode: ComplexValueMode) -> Self {
+        self.complex_value_mode = complex_value_mode;
+        self
+    }
+
+    /// Makes the conversions from readers fail with `Error::DuplicateKey` if a key is repeated in
+    /// a top level object of the input, like `a` in `{"a": 1, "a": 2}`, which is usually a sign of
+    /// corrupted data. By default the last value of the key is kept, like `serde_json` does. The
+    /// objects already parsed, like the ones given to `convert_from_array`, can not be checked.
+    /// With `set_skip_invalid` the objects with repeated keys are skipped instead.
+    #[must_use]
+    pub fn set_reject_duplicate_keys(mut self, value: bool) -> Self {
+        self.reject_duplicate_keys = value;
+        self
+    }
+
+    /// Sets the key separator, array start and array end that the flattener used internally puts
+    /// in the flattened keys, before they are replaced by the ones of the flattener provided. They
+    /// must not appear in the keys of the input, which is reported with
+    /// `Error::ReservedCharacterInInput`, so they can be changed if the defaults, the Unicode
+    /// control pictures `␝`, `␞` and `␟`, are legitimate content of the input. They must not
+    /// appear either in the key separator or in the array formatting of the flattener provided,
+    /// since they would be replaced too when transforming the keys back, which is reported with
+    /// `Error::InvalidSeparatorConfig` when converting. The same error is reported if any of them
+    /// is empty, or if one of them contains another one, since the flattened keys could not be
+    /// transformed back either.
+    #[must_use]
+    pub fn set_internal_sentinels(
+        mut self,
+        key_separator: String,
+        array_start: String,
+        array_end: String,
+    ) -> Self {
+        self.internal_sentinels = (key_separator, array_start, array_end);
+        self.flattener =
+            Self::internal_flattener(&self.original_flattener, &self.internal_sentinels);
+        self.reserved_separators = self.reserved_separators();
+        self
+    }
+
+    /// The flattener that we use internally, which is the one provided with the sentinels as key
+    /// separator and array formatting.
+    fn internal_flattener(
+        flattener: &flatten_json_object::Flattener,
+        (key_separator, array_start, array_end): &(String, String, String),
+    ) -> flatten_json_object::Flattener {
+        match flattener.array_formatting() {
+            ArrayFormatting::Plain => flattener.clone().set_key_separator(key_separator),
+            ArrayFormatting::Surrounded { start: _, end: _ } => flattener
+                .clone()
+                .set_key_separator(key_separator)
+                .set_array_formatting(ArrayFormatting::Surrounded {
+                    start: array_start.clone(),
+                    end: array_end.clone(),
+                }),
         }
     }
 
@@ -225,393 +1175,6650 @@ impl Json2Csv {
         }
     }
 
-    /// Flattens each one of the objects in the array slice and transforms each of them into a CSV
-    /// row.
-    ///
-    /// The headers of the CSV are the union of all the keys that result from flattening the
-    /// objects in the input.
-    ///
-    /// # Errors
-    /// Will return `Err` if `objects` does not contain actual JSON objects. It will also report an
-    /// error if two objects have keys that should be different but end looking the same after
-    /// flattening, and if writing the CSV fails.
-    pub fn convert_from_array(
-        self,
-        objects: &[Value],
-        mut csv_writer: csv::Writer<impl Write>,
-    ) -> Result<(), error::Error> {
-        // We have to flatten the JSON object since there is no other way to convert nested objects to CSV
-        let mut orig_flat_maps = Vec::<serde_json::value::Map<String, Value>>::new();
-
-        for obj in objects {
-            let obj = self.flattener.flatten(obj)?;
-            if let Value::Object(map) = obj {
-                orig_flat_maps.push(map);
-            } else {
-                unreachable!("Flattening a JSON object always produces a JSON object");
-            }
+    /// Returns the separators that our flattener uses. Fails with the separator at fault if the
+    /// internal sentinels are empty or contain each other, or if the key separator or the array
+    /// formatting of the flattener provided contain one of the separators that our flattener
+    /// uses, since the flattened keys could not be transformed back without corrupting them.
+    fn reserved_separators(&self) -> Result<Vec<String>, String> {
+        let (key_separator, array_start, array_end) = &self.internal_sentinels;
+        let sentinels = [key_separator, array_start, array_end];
+        // Any string contains the empty one, so empty sentinels are reported first
+        let invalid = sentinels
+            .iter()
+            .find(|sentinel| sentinel.is_empty())
+            .or_else(|| {
+                sentinels.iter().enumerate().find_map(|(i, sentinel)| {
+                    sentinels
+                        .iter()
+                        .enumerate()
+                        .any(|(j, other)| i != j && sentinel.contains(other.as_str()))
+                        .then_some(sentinel)
+                })
+            });
+        if let Some(sentinel) = invalid {
+            return Err(sentinel.to_string());
+        }
+        let mut reserved = vec![self.flattener.key_separator()];
+        let mut separators = vec![self.original_flattener.key_separator()];
+        if let (
+            ArrayFormatting::Surrounded { start, end },
+            ArrayFormatting::Surrounded { start: os, end: oe },
+        ) = (
+            self.flattener.array_formatting(),
+            self.original_flattener.array_formatting(),
+        ) {
+            reserved.extend([start.as_str(), end.as_str()]);
+            separators.extend([os.as_str(), oe.as_str()]);
+        }
+        match separators
+            .into_iter()
+            .find(|separator| reserved.iter().any(|r| separator.contains(r)))
+        {
+            Some(separator) => Err(separator.to_string()),
+            None => Ok(reserved.into_iter().map(str::to_string).collect()),
         }
-        let orig_flat_maps = orig_flat_maps;
+    }
 
-        let mut flat_maps = Vec::<serde_json::value::Map<String, Value>>::new();
+    /// Fails if a key in `value` contains one of the characters that our flattener uses as
+    /// separators, since they could not be told apart from the separators when transforming the
+    /// flattened keys, which would result in wrong headers. Fails as well if the separators can
+    /// not be used.
+    fn check_reserved_characters(&self, value: &Value) -> Result<(), error::Error> {
+        let reserved = self.reserved_separators.as_ref().map_err(|separator| {
+            Error::InvalidSeparatorConfig {
+                separator: separator.clone(),
+            }
+        })?;
 
-        // The headers are the union of the keys of the flattened objects, sorted.
-        // We collect the headers with our magic separators, and the headers with the separators that the user requested.
-        let mut orig_headers = BTreeSet::<String>::new();
-        let mut headers = BTreeSet::<String>::new();
-        for orig_map in orig_flat_maps {
-            let mut map = serde_json::value::Map::new();
-            for (orig_key, value) in orig_map {
-                let key = self.transform_key(&orig_key);
-                map.insert(key.clone(), value);
-                orig_headers.insert(orig_key);
-                headers.insert(key);
+        let mut pending = vec![value];
+        while let Some(value) = pending.pop() {
+            match value {
+                Value::Object(obj) => {
+                    for (key, value) in obj {
+                        if reserved.iter().any(|sep| key.contains(sep.as_str())) {
+                            return Err(Error::ReservedCharacterInInput { key: key.clone() });
+                        }
+                        pending.push(value);
+                    }
+                }
+                Value::Array(arr) => pending.extend(arr),
+                _ => {}
             }
-            flat_maps.push(map);
         }
+        Ok(())
+    }
 
-        // If we could not extract headers there is nothing to write to the CSV file
-        if headers.is_empty() {
-            return Ok(());
-        }
+    /// Applies the empty key handling to a key produced by our flattener. Each one of the keys
+    /// that was joined to produce it is considered separately.
+    fn handle_empty_keys<'a>(&self, key: &'a str) -> Result<Cow<'a, str>, error::Error> {
+        let array_start = match self.flattener.array_formatting() {
+            ArrayFormatting::Plain => None,
+            ArrayFormatting::Surrounded { start, end: _ } => Some(start.as_str()),
+        };
+        // With surrounded array formatting the position in the array is appended to the key of
+        // the array, so a segment like `[0]` also comes from an empty key.
+        let is_empty = |segment: &str| {
+            segment.is_empty() || array_start.is_some_and(|s| segment.starts_with(s))
+        };
 
-        // Check that there are no collisions between flattened keys in different objects
-        if headers.len() != orig_headers.len() {
-            return Err(Error::FlattenedKeysCollision);
+        let key_sep = self.flattener.key_separator();
+        if !key.split(key_sep).any(is_empty) {
+            return Ok(Cow::Borrowed(key));
         }
 
-        csv_writer.write_record(&headers)?;
-        for map in flat_maps {
-            csv_writer.write_record(build_record(&headers, map))?;
+        match &self.empty_key_handling {
+            EmptyKey::Keep => Ok(Cow::Borrowed(key)),
+            EmptyKey::Error => Err(Error::EmptyKey {
+                key: self.transform_key(key),
+            }),
+            EmptyKey::Replace(placeholder) => Ok(Cow::Owned(
+                key.split(key_sep)
+                    .map(|segment| {
+                        if is_empty(segment) {
+                            placeholder.clone() + segment
+                        } else {
+                            segment.to_string()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(key_sep),
+            )),
         }
+    }
 
-        Ok(())
+    /// Returns the values that have to be converted into rows for a top level document of the
+    /// input, according to the root pointer.
+    fn select_rows<'a>(&self, document: &'a Value) -> Result<&'a [Value], error::Error> {
+        let pointer = match &self.root_pointer {
+            Some(pointer) => pointer,
+            None => return Ok(std::slice::from_ref(document)),
+        };
+        match document.pointer(pointer) {
+            Some(Value::Array(values)) => Ok(values),
+            Some(value @ Value::Object(_)) => Ok(std::slice::from_ref(value)),
+            _ => Err(Error::RootPointer {
+                pointer: pointer.clone(),
+            }),
+        }
     }
 
-    /// Flattens the JSON objects in the file, transforming each of them into a CSV row.
-    ///
-    /// The headers of the CSV are the union of all the keys that result from flattening the objects
-    /// in the input. The file must contain JSON objects one immediately after the other or
-    /// separated by whitespace. Note that it uses a temporary file to store the flattened input,
-    /// which is automatically deleted when lo longer necessary.
+    /// Adds the values in `metadata` to a flattened object, and the keys of both to the
+    /// `collector`, so the metadata columns are headers like the keys of the objects. Returns the
+    /// object.
     ///
     /// # Errors
-    /// Will return `Err` if parsing the file fails or if the JSONs there are not objects. It will
-    /// also report an error if two objects have keys that should be different but end looking the
-    /// same after flattening, and if writing the CSV or to the temporary file fails.
-    pub fn convert_from_reader(
-        self,
-        reader: impl Read,
-        mut csv_writer: csv::Writer<impl Write>,
-    ) -> Result<(), error::Error> {
-        // We have to flatten the JSON objects into a file because it can potentially be a really big
-        // stream. We cannot directly convert into CSV because we cannot be sure about all the objects
-        // resulting in the same headers.
-        let mut tmp_file = BufWriter::new(tempfile()?);
-
-        // The headers are the union of the keys of the flattened objects, sorted.
-        // We collect the headers with our magic separators, and the headers with the separators that the user requested.
-        let mut orig_headers = BTreeSet::<String>::new();
-        let mut headers = BTreeSet::<String>::new();
-
-        for obj in Deserializer::from_reader(reader).into_iter::<Value>() {
-            let obj = obj?; // Ensure that we can parse the input properly
-            let obj = self.flattener.flatten(&obj)?;
-
-            let orig_map = match obj {
-                Value::Object(map) => map,
-                _ => unreachable!("Flattening a JSON object always produces a JSON object"),
-            };
-
-            let mut map = BTreeMap::new();
-            for (orig_key, value) in orig_map {
-                let key = self.transform_key(&orig_key);
-                map.insert(key.clone(), value);
-                orig_headers.insert(orig_key);
-                headers.insert(key);
+    /// Will return `Err` if the row index column, a constant column or a metadata column has the
+    /// same name as a key of the object.
+    fn collect(
+        &self,
+        flat_object: FlatObject,
+        metadata: &Map<String, Value>,
+        collector: &mut HeaderCollector,
+    ) -> Result<Map<String, Value>, error::Error> {
+        let FlatObject {
+            mut map,
+            keys,
+            targets,
+        } = flat_object;
+        if let Some(name) = self
+            .row_index_column
+            .iter()
+            .chain(self.constant_columns.iter().map(|(name, _)| name))
+            .chain(metadata.keys())
+            .find(|name| map.contains_key(*name))
+        {
+            return Err(Error::ColumnNameClash { name: name.clone() });
+        }
+        for (orig_key, key) in keys {
+            if map.contains_key(&key) {
+                collector.insert(orig_key, key);
             }
-            serde_json::to_writer(&mut tmp_file, &map)?;
         }
-
-        // If we could not extract headers there is nothing to write to the CSV file
-        if headers.is_empty() {
-            return Ok(());
+        for target in targets {
+            collector.insert(target.clone(), target);
         }
-
-        // Check that there are no collisions between flattened keys in different objects
-        if headers.len() != orig_headers.len() {
-            return Err(Error::FlattenedKeysCollision);
+        for (name, value) in metadata {
+            collector.insert(name.clone(), name.clone());
+            map.insert(name.clone(), value.clone());
         }
+        collector.finish_object(&map, self.null_counts_as_present);
+        Ok(map)
+    }
 
-        tmp_file.seek(SeekFrom::Start(0))?;
-        let tmp_file = BufReader::new(tmp_file.into_inner()?);
+    /// Flattens a JSON object and transforms its keys to the format requested by the user. The
+    /// keys are not collected yet, so objects can be flattened in parallel. The values that are
+    /// not objects never get here, since `flatten_document_with` handles them before.
+    fn flatten_object(&self, obj: &Value) -> Result<FlatObject, error::Error> {
+        let obj = self.key_decoder.decode_keys(obj)?;
+        let obj = self.array_of_objects_mode.apply(&obj);
+        let obj = self.mixed_array_handling.apply(&obj)?;
+        self.check_reserved_characters(&obj)?;
+        let orig_map = match self.flattener.flatten(&obj)? {
+            Value::Object(map) => map,
+            _ => unreachable!("Flattening a JSON object always produces a JSON object"),
+        };
 
-        csv_writer.write_record(&headers)?;
-        for obj in Deserializer::from_reader(tmp_file).into_iter::<Value>() {
-            let map = match obj? {
-                Value::Object(map) => map,
-                _ => unreachable!("Flattening a JSON object always produces a JSON object"),
-            };
-            csv_writer.write_record(build_record(&headers, map))?;
+        let mut map = Map::new();
+        let mut keys = Vec::with_capacity(orig_map.len());
+        for (orig_key, value) in orig_map {
+            let key = self.handle_empty_keys(&orig_key)?;
+            let key = self.transform_key(&self.apply_case_style(&key));
+            map.insert(key.clone(), value);
+            keys.push((orig_key, key));
         }
 
-        Ok(())
+        let mut targets = self.coalesce(&mut map)?;
+        map.retain(|key, _| self.is_key_allowed(key));
+        targets.retain(|target| self.is_key_allowed(target));
+        Ok(FlatObject { map, keys, targets })
     }
-}
 
-fn build_record(
-    headers: &BTreeSet<String>,
-    mut map: serde_json::Map<String, Value>,
-) -> Vec<String> {
-    let mut record: Vec<String> = vec![];
-    for header in headers {
-        if let Some(val) = map.remove(header) {
-            match val {
-                Value::String(s) => record.push(s),
-                // _ => record.push(val.to_string()),
-                Value::Bool(_) | Value::Number(_) => record.push(val.to_string()),
-                // Any array or object here must be empty, because it would have been flattened
-                // otherwise. In addition, to reach this for arrays and objects the flattener must
-                // have been set to preserve them when empty. Makes no sense to add them or `Null`
-                // to the CSV output, so we replace them with the empty string.
-                Value::Null | Value::Array(_) | Value::Object(_) => record.push("".to_string()),
+    /// Like `flatten_object`, but for an object whose values must already be scalars, which is
+    /// at position `object_index` of the input.
+    fn flat_object(&self, obj: &Value, object_index: usize) -> Result<FlatObject, error::Error> {
+        let orig_map = obj.as_object().expect("Only objects are flattened");
+        let mut map = Map::new();
+        let mut keys = Vec::with_capacity(orig_map.len());
+        for (orig_key, value) in orig_map {
+            if value.is_object() || value.is_array() {
+                return Err(Error::NestedValueInFlatInput {
+                    object_index,
+                    key: orig_key.clone(),
+                });
             }
-        } else {
-            record.push("".to_string());
+            let key = self.apply_case_style(orig_key).into_owned();
+            map.insert(key.clone(), value.clone());
+            keys.push((orig_key.clone(), key));
         }
+
+        let mut targets = self.coalesce(&mut map)?;
+        map.retain(|key, _| self.is_key_allowed(key));
+        targets.retain(|target| self.is_key_allowed(target));
+        Ok(FlatObject { map, keys, targets })
     }
-    record
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    /// Replaces the candidate keys of each coalesced column with the column, set to the value of
+    /// the first candidate that is not empty. Returns the columns added.
+    fn coalesce(&self, map: &mut Map<String, Value>) -> Result<Vec<String>, error::Error> {
+        let mut targets = Vec::new();
+        for (target, candidates) in &self.coalesce {
+            let mut present = false;
+            let mut coalesced = None;
+            for candidate in candidates {
+                if let Some(value) = map.remove(candidate) {
+                    present = true;
+                    if coalesced.is_none() && !is_empty_value(&value) {
+                        coalesced = Some(value);
+                    }
+                }
+            }
+            if !present {
+                continue;
+            }
+            if map.contains_key(target) {
+                return Err(Error::ColumnNameClash {
+                    name: target.clone(),
+                });
+            }
+            map.insert(target.clone(), coalesced.unwrap_or(Value::Null));
+            targets.push(target.clone());
+        }
+        Ok(targets)
+    }
+
+    /// Fails if the deadline has been exceeded. To keep the overhead low the clock is only checked
+    /// once every `DEADLINE_CHECK_INTERVAL` objects.
+    fn check_deadline(&self, objects_processed: usize) -> Result<(), error::Error> {
+        match self.deadline {
+            Some(deadline)
+                if objects_processed % DEADLINE_CHECK_INTERVAL == 0
+                    && Instant::now() >= deadline =>
+            {
+                Err(Error::DeadlineExceeded { objects_processed })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The headers of the CSV for the keys collected from the flattened objects, which are the
+    /// allowed ones if there is an allowlist.
+    ///
+    /// # Errors
+    /// Will return `Err` if two keys that should be different end looking the same after
+    /// flattening, if the row index column or a constant column has the same name as a key, or if
+    /// there are more headers than the maximum number of columns.
+    fn collected_headers(&self, collector: HeaderCollector) -> Result<Vec<String>, error::Error> {
+        let mut headers = match &self.header_allowlist {
+            Some(allowlist) => {
+                collector.check_collisions()?;
+                allowlist.clone()
+            }
+            None => collector.into_headers(self.header_strategy, &self.header_order)?,
+        };
+        for (name, _) in &self.constant_columns {
+            if headers.contains(name) {
+                return Err(Error::ColumnNameClash { name: name.clone() });
+            }
+            headers.push(name.clone());
+        }
+        if let Some(name) = &self.row_index_column {
+            if headers.contains(name) {
+                return Err(Error::ColumnNameClash { name: name.clone() });
+            }
+            headers.insert(0, name.clone());
+        }
+        if let Some(limit) = self.max_columns {
+            if headers.len() > limit {
+                return Err(Error::TooManyColumns {
+                    count: headers.len(),
+                    limit,
+                });
+            }
+        }
+        Ok(headers)
+    }
+
+    /// Flattens the objects in a top level document of the input and pushes them to `flat_maps`,
+    /// together with the values in `metadata`.
+    fn flatten_document(
+        &self,
+        document: &Value,
+        flat_maps: &mut impl FlatMapSink,
+        collector: &mut HeaderCollector,
+        metadata: &Map<String, Value>,
+    ) -> Result<(), error::Error> {
+        let mut flatten = |obj: &Value, _| self.flatten_object(obj);
+        self.flatten_document_with(document, flat_maps, collector, metadata, &mut flatten)
+    }
+
+    /// Like `flatten_document`, but using `flatten` to flatten each object that has to be
+    /// converted, in order.
+    fn flatten_document_with(
+        &self,
+        document: &Value,
+        flat_maps: &mut impl FlatMapSink,
+        collector: &mut HeaderCollector,
+        metadata: &Map<String, Value>,
+        flatten: &mut ObjectFlattener,
+    ) -> Result<(), error::Error> {
+        for obj in self.select_rows(document)? {
+            if self.keep_reading(flat_maps.len()).is_break() {
+                break;
+            }
+            self.check_deadline(flat_maps.len())?;
+            if let Some(limit) = self.max_objects {
+                if flat_maps.len() >= limit {
+                    return Err(Error::TooManyObjects { limit });
+                }
+            }
+            let index = collector.values;
+            collector.values += 1;
+            if !obj.is_object() {
+                match self.scalar_element_handling {
+                    ScalarElement::Error => return Err(Error::NotAnObject { index }),
+                    ScalarElement::Skip => {}
+                    ScalarElement::EmptyRow => {
+                        for name in metadata.keys() {
+                            collector.insert(name.clone(), name.clone());
+                        }
+                        flat_maps.push(metadata.clone(), index)?
+                    }
+                }
+                continue;
+            }
+            if let Some(validator) = &self.object_validator {
+                validator.get()(obj).map_err(|message| Error::ValidationFailed {
+                    object_index: index,
+                    message,
+                })?;
+            }
+            let map = self.collect(flatten(obj, index)?, metadata, collector)?;
+            if let Some(limit) = self.max_fields_per_object {
+                if map.len() - metadata.len() > limit {
+                    return Err(Error::TooManyFieldsInObject {
+                        object_index: index,
+                        count: map.len(),
+                    });
+                }
+            }
+            flat_maps.push(map, index)?;
+        }
+        Ok(())
+    }
+
+    /// Returns a function that flattens the objects of the array slice that have to be converted,
+    /// in order. With the `rayon` feature, if there are several threads, they are flattened in
+    /// parallel in advance, in chunks of `PARALLEL_CHUNK_SIZE` objects, so the limits and the
+    /// deadline checked before converting each object stop the conversion at most one chunk later
+    /// than without it.
+    #[cfg_attr(not(feature = "rayon"), allow(unused_variables))]
+    fn object_flattener<'a>(&'a self, objects: &'a [Value]) -> Box<ObjectFlattener<'a>> {
+        #[cfg(feature = "rayon")]
+        if rayon::current_num_threads() > 1 {
+            use rayon::prelude::*;
+
+            // Errors selecting the rows are reported later, when the documents are processed
+            let mut pending = objects
+                .iter()
+                .flat_map(|document| self.select_rows(document).unwrap_or_default())
+                .filter(|obj| obj.is_object());
+            let mut flattened = std::collections::VecDeque::new();
+            return Box::new(move |obj, _| {
+                if flattened.is_empty() {
+                    let chunk: Vec<&Value> = pending.by_ref().take(PARALLEL_CHUNK_SIZE).collect();
+                    let results: Vec<_> = chunk
+                        .par_iter()
+                        .map(|obj| self.flatten_object(obj))
+                        .collect();
+                    flattened.extend(chunk.into_iter().zip(results));
+                }
+                match flattened.pop_front() {
+                    Some((next, result)) if std::ptr::eq(next, obj) => result,
+                    // Not the object flattened in advance, so it is flattened here instead
+                    _ => self.flatten_object(obj),
+                }
+            });
+        }
+        #[cfg_attr(feature = "rayon", allow(unreachable_code))]
+        Box::new(|obj, _| self.flatten_object(obj))
+    }
+
+    /// Flattens the JSON objects in `reader` and pushes them to `flat_maps`, one after the other,
+    /// together with the values in `metadata`. `on_error` is called with the position and the
+    /// error of each value skipped.
+    fn flatten_reader_into(
+        &self,
+        reader: impl Read,
+        flat_maps: &mut impl FlatMapSink,
+        collector: &mut HeaderCollector,
+        metadata: &Map<String, Value>,
+        on_error: &mut ErrorSink,
+    ) -> Result<(), error::Error> {
+        self.for_each_input(reader, Framing::Documents, on_error, |document| {
+            self.flatten_document(&document, flat_maps, collector, metadata)?;
+            Ok(self.keep_reading(flat_maps.len()))
+        })
+    }
+
+    /// Whether the rest of the input has to be read after flattening `objects` objects, which is
+    /// not the case if they are all the rows to write and the headers only come from them.
+    fn keep_reading(&self, objects: usize) -> ControlFlow<()> {
+        match self.max_rows {
+            Some(limit) if self.headers_from_written_rows && objects >= limit => {
+                ControlFlow::Break(())
+            }
+            _ => ControlFlow::Continue(()),
+        }
+    }
+
+    /// Calls `f` with each top level JSON document in the reader, split according to `framing`,
+    /// until it breaks. With `set_skip_invalid` the documents that can not be parsed are skipped,
+    /// calling `on_error` with their position and error.
+    fn for_each_input(
+        &self,
+        reader: impl Read,
+        framing: Framing,
+        on_error: &mut ErrorSink,
+        f: impl FnMut(Value) -> Result<ControlFlow<()>, error::Error>,
+    ) -> Result<(), error::Error> {
+        let on_error = self.skip_invalid.then_some(on_error);
+        match framing {
+            Framing::Documents => {
+                for_each_document(reader, self.reject_duplicate_keys, on_error, f)
+            }
+            Framing::Lines => for_each_line(reader, self.reject_duplicate_keys, on_error, f),
+        }
+    }
+
+    /// Flattens the objects in the array slice and returns the flattened objects, with the keys
+    /// transformed as they would be in the headers, without building a CSV. Useful to check which
+    /// keys a configuration produces. The objects are in the order of the input, and include the
+    /// columns added by `set_coalesce`.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `headers_for_array`.
+    pub fn flatten_array(
+        &self,
+        objects: &[Value],
+    ) -> Result<Vec<Map<String, Value>>, error::Error> {
+        let mut flat_maps = FlatMaps::in_memory();
+        let mut collector = HeaderCollector::default();
+        let mut flatten = self.object_flattener(objects);
+        for document in objects {
+            self.flatten_document_with(
+                document,
+                &mut flat_maps,
+                &mut collector,
+                &Map::new(),
+                &mut flatten,
+            )?;
+        }
+        collector.check_collisions()?;
+        match flat_maps {
+            FlatMaps::InMemory(maps) => Ok(maps),
+            _ => unreachable!("The flattened objects are kept in memory"),
+        }
+    }
+
+    /// Flattens the objects in the array slice and returns the headers of the CSV that would result
+    /// from converting them, without writing anything. The schema can be used with `with_schema`
+    /// to convert other inputs with exactly the same columns.
+    ///
+    /// # Errors
+    /// Will return `Err` if `objects` does not contain actual JSON objects, or if two objects have
+    /// keys that should be different but end looking the same after flattening.
+    pub fn headers_for_array(&self, objects: &[Value]) -> Result<Schema, error::Error> {
+        let mut counter = FlatMaps::Discarded(0);
+        let mut collector = HeaderCollector::default();
+        for document in objects {
+            self.flatten_document(document, &mut counter, &mut collector, &Map::new())?;
+        }
+        Ok(Schema::new(self.collected_headers(collector)?))
+    }
+
+    /// Creates a builder that computes a schema from several inputs, which can then be used with
+    /// `with_schema` to convert each of them with the same columns.
+    #[must_use]
+    pub fn schema_builder(&self) -> SchemaBuilder<'_> {
+        SchemaBuilder::new(self)
+    }
+
+    /// Flattens the objects in the array slice and returns the headers of the CSV that would
+    /// result from converting them, exactly as a conversion would write them, without writing
+    /// anything. Like `headers_for_array`, but returning just the list of headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `headers_for_array`.
+    pub fn headers_from_array(&self, objects: &[Value]) -> Result<Vec<String>, error::Error> {
+        self.headers_for_array(objects).map(Schema::into_headers)
+    }
+
+    /// Flattens the JSON objects in the reader and returns the headers of the CSV that would
+    /// result from converting them, exactly as a conversion would write them, without writing
+    /// anything. Like `headers_for_reader`, but returning just the list of headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `headers_for_reader`.
+    pub fn headers_from_reader(&self, reader: impl Read) -> Result<Vec<String>, error::Error> {
+        self.headers_for_reader(reader).map(Schema::into_headers)
+    }
+
+    /// Computes the headers that would result from converting each of the array slices and
+    /// compares them, reporting which columns were added, removed or kept in `new` compared to
+    /// `old`. Useful to detect unexpected changes in the schema of a feed.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `headers_for_array`, for any of the slices.
+    pub fn header_diff(&self, old: &[Value], new: &[Value]) -> Result<HeaderDiff, error::Error> {
+        let old = self.headers_for_array(old)?;
+        let new = self.headers_for_array(new)?;
+        Ok(old.diff(&new))
+    }
+
+    /// Flattens the JSON objects in the reader and returns the headers of the CSV that would
+    /// result from converting them, without writing anything. The schema can be used with
+    /// `with_schema` to convert other inputs with exactly the same columns.
+    ///
+    /// # Errors
+    /// Will return `Err` if parsing the input fails or if the JSONs there are not objects. It will
+    /// also report an error if two objects have keys that should be different but end looking the
+    /// same after flattening.
+    pub fn headers_for_reader(&self, reader: impl Read) -> Result<Schema, error::Error> {
+        let mut counter = FlatMaps::Discarded(0);
+        let mut collector = HeaderCollector::default();
+        self.flatten_reader_into(
+            reader,
+            &mut counter,
+            &mut collector,
+            &Map::new(),
+            &mut |_, _| {},
+        )?;
+        Ok(Schema::new(self.collected_headers(collector)?))
+    }
+
+    /// Flattens the JSON objects in the reader, like `convert_from_reader` would, and returns the
+    /// headers, the number of rows and the colliding keys of the CSV that would result, without
+    /// writing anything. Collisions are reported instead of failing, so all of them are found.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `headers_for_reader`, except for collisions.
+    pub fn dry_run(&self, reader: impl Read) -> Result<DryRunReport, error::Error> {
+        let mut counter = FlatMaps::Discarded(0);
+        let mut collector = HeaderCollector::default();
+        self.flatten_reader_into(
+            reader,
+            &mut counter,
+            &mut collector,
+            &Map::new(),
+            &mut |_, _| {},
+        )?;
+        let collisions = collector.take_collisions();
+        Ok(DryRunReport {
+            headers: self.collected_headers(collector)?,
+            rows: counter.len(),
+            collisions,
+        })
+    }
+
+    /// Flattens each one of the objects in the array slice and transforms each of them into a CSV
+    /// row.
+    ///
+    /// The headers of the CSV are the union of all the keys that result from flattening the
+    /// objects in the input. If `set_spill_to_temp_file` was enabled, the flattened objects are
+    /// stored in a temporary file until the headers are known, instead of in memory.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Error::NotAnObject` if `objects` does not contain actual JSON objects, unless
+    /// `set_scalar_element_handling` says otherwise. It will also report an error if two objects
+    /// have keys that should be different but end looking the same after flattening, if the
+    /// deadline is exceeded, and if writing the CSV or to the temporary file fails.
+    pub fn convert_from_array(
+        &self,
+        objects: &[Value],
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        self.convert_array_with(objects, csv_writer, &mut *self.object_flattener(objects))
+    }
+
+    /// Like `convert_from_array`, but for objects that are already flat, i.e. whose values are
+    /// all scalars, so they are converted without flattening them. This is faster for large
+    /// inputs. The keys are used as they are, except for `set_header_case_style`, and the rest of
+    /// the settings apply as usual.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Error::NestedValueInFlatInput` if a value of an object is an object or an
+    /// array, even an empty one, in which case `convert_from_array` has to be used instead. It
+    /// will also return an error in the same cases as `convert_from_array`.
+    pub fn convert_from_flat_array(
+        &self,
+        objects: &[Value],
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        let mut flatten = |obj: &Value, index| self.flat_object(obj, index);
+        self.convert_array_with(objects, csv_writer, &mut flatten)
+    }
+
+    /// Converts the objects in the array slice into rows with the columns in `headers`, like
+    /// `convert_from_array` with a schema with those headers, but without writing the headers, so
+    /// the rows can be appended to a CSV whose headers were already written, e.g. to concatenate
+    /// the output of several conversions. The keys of the objects that are not in `headers` are
+    /// dropped, unless `set_reject_unknown_keys` is set. The row count and the trailer statistics
+    /// are still written after the rows, if requested.
+    ///
+    /// Returns the number of rows written.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`.
+    pub fn convert_from_array_append(
+        &self,
+        objects: &[Value],
+        csv_writer: csv::Writer<impl Write>,
+        headers: &[String],
+    ) -> Result<usize, error::Error> {
+        let json2csv = self.clone().with_schema(Schema::new(headers.to_vec()));
+        let mut collector = HeaderCollector::default();
+        let mut row_writer = RowWriter::new(&json2csv, headers, csv_writer);
+        row_writer.headers_written = true;
+        for document in objects {
+            json2csv.flatten_document(document, &mut row_writer, &mut collector, &Map::new())?;
+            collector.check_collisions()?;
+        }
+        row_writer.finish()
+    }
+
+    /// Flattens a single JSON object and writes it as a CSV with its headers and one row. Works
+    /// like `convert_from_array` with a slice with just the object.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Error::NotAnObject` if `object` is not a JSON object, whatever
+    /// `set_scalar_element_handling` says, and `Err` in the same cases as `convert_from_array`.
+    pub fn convert_from_object(
+        &self,
+        object: &Value,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        if !object.is_object() {
+            return Err(Error::NotAnObject { index: 0 });
+        }
+        self.convert_from_array(std::slice::from_ref(object), csv_writer)
+    }
+
+    /// Works like `convert_from_array`, but takes the ownership of the objects, and drops each of
+    /// them as soon as it is flattened. This reduces the memory needed to convert big inputs that
+    /// are not needed afterwards. The output is the same as with `convert_from_array`.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`.
+    pub fn convert_from_owned_array(
+        &self,
+        objects: Vec<Value>,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        // Flattening the objects in parallel in advance would keep all of them in memory
+        let mut flatten = |obj: &Value, _| self.flatten_object(obj);
+        self.convert_array_with(objects, csv_writer, &mut flatten)
+    }
+
+    /// Converts the top level documents, using `flatten` to flatten each object that has to be
+    /// converted, in order.
+    fn convert_array_with(
+        &self,
+        documents: impl IntoIterator<Item = impl Borrow<Value>>,
+        csv_writer: csv::Writer<impl Write>,
+        flatten: &mut ObjectFlattener,
+    ) -> Result<usize, error::Error> {
+        if let OutputShape::Long { .. } = self.output_shape {
+            let mut collector = HeaderCollector::default();
+            let mut long_writer = LongWriter::new(self, csv_writer);
+            for document in documents {
+                self.flatten_document_with(
+                    document.borrow(),
+                    &mut long_writer,
+                    &mut collector,
+                    &Map::new(),
+                    flatten,
+                )?;
+                collector.check_collisions()?;
+            }
+            return long_writer.finish();
+        }
+
+        if let Some(schema) = &self.schema {
+            let mut collector = HeaderCollector::default();
+            let mut row_writer = RowWriter::new(self, schema.headers(), csv_writer);
+            for document in documents {
+                self.flatten_document_with(
+                    document.borrow(),
+                    &mut row_writer,
+                    &mut collector,
+                    &Map::new(),
+                    flatten,
+                )?;
+                collector.check_collisions()?;
+            }
+            return row_writer.finish();
+        }
+
+        // We have to flatten the JSON object since there is no other way to convert nested objects to CSV
+        let mut flat_maps = if self.spill_to_temp_file {
+            FlatMaps::temp_file(self.keep_temp_file.as_deref())?
+        } else {
+            FlatMaps::in_memory()
+        };
+        let mut collector = HeaderCollector::default();
+
+        for document in documents {
+            self.flatten_document_with(
+                document.borrow(),
+                &mut flat_maps,
+                &mut collector,
+                &Map::new(),
+                flatten,
+            )?;
+        }
+
+        let headers = self.collected_headers(collector)?;
+        self.write_csv(&headers, flat_maps, csv_writer)
+    }
+
+    /// Flattens the JSON objects in the file, transforming each of them into a CSV row.
+    ///
+    /// The headers of the CSV are the union of all the keys that result from flattening the objects
+    /// in the input. The file must contain JSON objects one immediately after the other or
+    /// separated by whitespace, or a single JSON array of objects, whose elements are parsed one
+    /// at a time so the whole array is never in memory. Note that it uses a temporary file to
+    /// store the flattened input, which is automatically deleted when lo longer necessary.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` if parsing the file fails, and `Error::NotAnObject` if the JSONs there,
+    /// or the elements of a top level array, are not objects, unless `set_scalar_element_handling`
+    /// says otherwise. It will also report an error if two objects have keys that should be
+    /// different but end looking the same after flattening, if the deadline is exceeded, and if
+    /// writing the CSV or to the temporary file fails.
+    pub fn convert_from_reader(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        self.convert_from_reader_with_report(reader, csv_writer)
+            .map(|report| report.rows)
+    }
+
+    /// Works like `convert_from_reader` with a schema with the headers provided, so the rows are
+    /// written as soon as the objects are flattened, without a temporary file. The keys of the
+    /// objects that are not in `headers` are dropped, unless `set_reject_unknown_keys` is set.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub fn convert_from_reader_with_headers(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+        headers: &[String],
+    ) -> Result<usize, error::Error> {
+        self.clone()
+            .with_schema(Schema::new(headers.to_vec()))
+            .convert_from_reader(reader, csv_writer)
+    }
+
+    /// Works like `convert_from_reader`, but returns a report of the conversion, with the number
+    /// of rows written, the errors of the values skipped because of `set_skip_invalid` and the
+    /// columns dropped because of `HeaderStrategy::Intersection`.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub fn convert_from_reader_with_report(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<ConversionReport, error::Error> {
+        let mut skipped = Vec::new();
+        let report = self.write_from_reader(
+            reader,
+            Framing::Documents,
+            csv_writer,
+            &mut |_, err| skipped.push(err),
+            &mut |_| {},
+        )?;
+        Ok(ConversionReport { skipped, ..report })
+    }
+
+    /// Works like `convert_from_reader`, but calls `on_error` with the position in the input and
+    /// the error of each value skipped because of `set_skip_invalid` as soon as it is found, while
+    /// the conversion continues, e.g. to log or count them. The values skipped do not affect the
+    /// rest of the output.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub fn convert_from_reader_with_error_sink(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+        mut on_error: impl FnMut(usize, error::Error),
+    ) -> Result<usize, error::Error> {
+        let framing = Framing::Documents;
+        self.write_from_reader(reader, framing, csv_writer, &mut on_error, &mut |_| {})
+            .map(|report| report.rows)
+    }
+
+    /// Works like `convert_from_reader`, but for JSON Lines input, where each line has exactly one
+    /// JSON object. Each line is parsed on its own, so a line with an invalid object, or with more
+    /// than one, is an error that points to its line, `Error::ParsingLine`, and with
+    /// `set_skip_invalid` only that line is skipped. The lines that are empty or only have
+    /// whitespace are ignored.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub fn convert_from_jsonl_reader(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        self.convert_from_jsonl_reader_with_error_sink(reader, csv_writer, |_, _| {})
+    }
+
+    /// Works like `convert_from_jsonl_reader`, but calls `on_error` with the line number, starting
+    /// at 1, and the error of each line skipped because of `set_skip_invalid` as soon as it is
+    /// found, like `convert_from_reader_with_error_sink` does.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub fn convert_from_jsonl_reader_with_error_sink(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+        mut on_error: impl FnMut(usize, error::Error),
+    ) -> Result<usize, error::Error> {
+        let framing = Framing::Lines;
+        self.write_from_reader(reader, framing, csv_writer, &mut on_error, &mut |_| {})
+            .map(|report| report.rows)
+    }
+
+    /// Works like `convert_from_reader`, but calls `progress` with the number of objects
+    /// flattened so far each time an object of the input is flattened, e.g. to drive a progress
+    /// bar. Unless there is a schema or the output is long, the rows are written once all the
+    /// objects are flattened, so the last call happens before the rows are written.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`.
+    pub fn convert_from_reader_with_progress(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+        mut progress: impl FnMut(usize),
+    ) -> Result<usize, error::Error> {
+        let framing = Framing::Documents;
+        self.write_from_reader(reader, framing, csv_writer, &mut |_, _| {}, &mut progress)
+            .map(|report| report.rows)
+    }
+
+    /// Converts the JSON objects in the reader, split according to `framing`, and returns a report
+    /// with the number of rows written and the columns dropped, but not the values skipped.
+    /// `on_error` is called with the position and the error of each value skipped instead.
+    fn write_from_reader(
+        &self,
+        reader: impl Read,
+        framing: Framing,
+        csv_writer: csv::Writer<impl Write>,
+        on_error: &mut ErrorSink,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<ConversionReport, error::Error> {
+        let readers = [(Map::new(), reader)];
+        self.write_from_readers(readers, false, framing, csv_writer, on_error, progress)
+    }
+
+    /// Like `write_from_reader`, but converts the JSON objects of all the readers, one after the
+    /// other, adding the metadata of each reader to its rows. If `labeled` is set the errors are
+    /// wrapped in `Error::Input` with the position of the reader they come from.
+    fn write_from_readers(
+        &self,
+        readers: impl IntoIterator<Item = (Map<String, Value>, impl Read)>,
+        labeled: bool,
+        framing: Framing,
+        csv_writer: csv::Writer<impl Write>,
+        on_error: &mut ErrorSink,
+        progress: &mut dyn FnMut(usize),
+    ) -> Result<ConversionReport, error::Error> {
+        let rows_only = |rows| ConversionReport {
+            rows,
+            ..Default::default()
+        };
+        if let OutputShape::Long { .. } = self.output_shape {
+            let mut collector = HeaderCollector::default();
+            let mut long_writer = LongWriter::new(self, csv_writer);
+            let mut sink = WithProgress::new(&mut long_writer, progress);
+            self.flatten_readers_into(
+                readers,
+                labeled,
+                framing,
+                &mut sink,
+                &mut collector,
+                on_error,
+            )?;
+            return long_writer.finish().map(rows_only);
+        }
+
+        if let Some(schema) = &self.schema {
+            let mut collector = HeaderCollector::default();
+            let mut row_writer = RowWriter::new(self, schema.headers(), csv_writer);
+            let mut sink = WithProgress::new(&mut row_writer, progress);
+            self.flatten_readers_into(
+                readers,
+                labeled,
+                framing,
+                &mut sink,
+                &mut collector,
+                on_error,
+            )?;
+            return row_writer.finish().map(rows_only);
+        }
+
+        // We have to flatten the JSON objects into a file because it can potentially be a really big
+        // stream. We cannot directly convert into CSV because we cannot be sure about all the objects
+        // resulting in the same headers.
+        let mut flat_maps = FlatMaps::temp_file(self.keep_temp_file.as_deref())?;
+        let mut collector = HeaderCollector::default();
+
+        let mut sink = WithProgress::new(&mut flat_maps, progress);
+        self.flatten_readers_into(
+            readers,
+            labeled,
+            framing,
+            &mut sink,
+            &mut collector,
+            on_error,
+        )?;
+
+        let dropped_columns = match (&self.header_allowlist, self.header_strategy) {
+            (None, HeaderStrategy::Intersection) => collector.uncommon_headers(),
+            _ => Vec::new(),
+        };
+        let headers = self.collected_headers(collector)?;
+        Ok(ConversionReport {
+            dropped_columns,
+            ..rows_only(self.write_csv(&headers, flat_maps, csv_writer)?)
+        })
+    }
+
+    /// Flattens the JSON objects in each reader, split according to `framing`, and pushes them to
+    /// `sink`, together with the metadata of the reader. If `labeled` is set the errors, including
+    /// the ones passed to `on_error`, are wrapped in `Error::Input` with the position of the
+    /// reader.
+    fn flatten_readers_into(
+        &self,
+        readers: impl IntoIterator<Item = (Map<String, Value>, impl Read)>,
+        labeled: bool,
+        framing: Framing,
+        sink: &mut impl FlatMapSink,
+        collector: &mut HeaderCollector,
+        on_error: &mut ErrorSink,
+    ) -> Result<(), error::Error> {
+        // Rows written as soon as their object is flattened can not wait for the headers to be
+        // computed to check the collisions
+        let writes_rows = self.schema.is_some() || self.output_shape != OutputShape::Wide;
+        for (index, (metadata, reader)) in readers.into_iter().enumerate() {
+            let input_error = |source| {
+                if labeled {
+                    Error::Input {
+                        index,
+                        source: Box::new(source),
+                    }
+                } else {
+                    source
+                }
+            };
+            let mut on_reader_error = |position, err| on_error(position, input_error(err));
+            // The positions in the errors wrapped with the reader are relative to the reader
+            if labeled {
+                collector.values = 0;
+            }
+            self.for_each_input(reader, framing, &mut on_reader_error, |document| {
+                self.flatten_document(&document, sink, collector, &metadata)?;
+                if writes_rows {
+                    collector.check_collisions()?;
+                }
+                Ok(self.keep_reading(sink.len()))
+            })
+            .map_err(input_error)?;
+            if self.keep_reading(sink.len()).is_break() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Flattens the JSON objects in the reader, transforming each of them into a CSV row, like
+    /// `convert_from_reader` does but without using a temporary file. Since the reader can seek,
+    /// it is read twice: first to compute the headers and then, after seeking back to the
+    /// position where it started, to write the rows. The output is the same as with
+    /// `convert_from_reader`, so this is just a way to avoid duplicating big input files on disk.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`, and if seeking fails.
+    pub fn convert_from_seekable(
+        &self,
+        mut reader: impl Read + Seek,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        // Schemas and the long shape already write the rows in a single pass
+        if self.schema.is_some() || self.output_shape != OutputShape::Wide {
+            return self.convert_from_reader(reader, csv_writer);
+        }
+
+        let start = reader.stream_position()?;
+        let mut counter = FlatMaps::Discarded(0);
+        let mut collector = HeaderCollector::default();
+        let readers = [(Map::new(), &mut reader)];
+        let framing = Framing::Documents;
+        self.flatten_readers_into(
+            readers,
+            false,
+            framing,
+            &mut counter,
+            &mut collector,
+            &mut |_, _| {},
+        )?;
+        let headers = self.collected_headers(collector)?;
+        // If we could not extract headers there is nothing to write to the CSV file
+        if headers.is_empty() {
+            return Ok(0);
+        }
+
+        // The rows are written like `write_csv` does, but flattening the objects again
+        reader.seek(SeekFrom::Start(start))?;
+        let mut row_writer = RowWriter::new(self, &headers, csv_writer);
+        let mut collector = HeaderCollector::default();
+        let readers = [(Map::new(), reader)];
+        self.flatten_readers_into(
+            readers,
+            false,
+            framing,
+            &mut row_writer,
+            &mut collector,
+            &mut |_, _| {},
+        )?;
+        row_writer.finish()
+    }
+
+    /// Flattens the JSON objects in the readers, transforming each of them into a CSV row, as if
+    /// the readers were a single stream read one after the other. Each reader comes with a label,
+    /// like the name of the file it reads.
+    ///
+    /// It works like `convert_from_reader`, with the same settings, e.g. a schema or the long
+    /// shape, but the objects of all the readers are part of the same CSV output, whose headers
+    /// are computed from all of them. Each reader must contain whole JSON objects, or a single
+    /// JSON array of objects. The values skipped because of `set_skip_invalid` are reported by
+    /// `convert_from_readers_with_report`. The metadata
+    /// columns configured with `set_metadata_columns` are added to the headers like the keys of
+    /// the objects, with the label of the reader as the source.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Error::Input` with the position of the reader if converting its objects fails,
+    /// like reading or parsing it, or if the name of a metadata column is the same as one of their
+    /// flattened keys, which is `Error::ColumnNameClash`. It will also return `Err` in the same
+    /// cases as `convert_from_reader`.
+    pub fn convert_from_readers(
+        &self,
+        readers: impl IntoIterator<Item = (String, impl Read)>,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        self.convert_from_readers_with_report(readers, csv_writer)
+            .map(|report| report.rows)
+    }
+
+    /// Works like `convert_from_readers`, but returns a report of the conversion, like
+    /// `convert_from_reader_with_report` does. The errors of the values skipped are wrapped in
+    /// `Error::Input` with the position of the reader they come from.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_readers`.
+    pub fn convert_from_readers_with_report(
+        &self,
+        readers: impl IntoIterator<Item = (String, impl Read)>,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<ConversionReport, error::Error> {
+        let timestamp = utc_timestamp(SystemTime::now());
+        let readers = readers
+            .into_iter()
+            .map(|(label, reader)| (self.metadata(label, &timestamp), reader));
+        let mut skipped = Vec::new();
+        let report = self.write_from_readers(
+            readers,
+            true,
+            Framing::Documents,
+            csv_writer,
+            &mut |_, err| skipped.push(err),
+            &mut |_| {},
+        )?;
+        Ok(ConversionReport { skipped, ..report })
+    }
+
+    /// The values of the metadata columns of the rows that come from `source`.
+    fn metadata(&self, source: String, timestamp: &str) -> Map<String, Value> {
+        let mut metadata = Map::new();
+        if let Some(column) = &self.metadata_columns.source {
+            metadata.insert(column.clone(), Value::from(source));
+        }
+        if let Some(column) = &self.metadata_columns.timestamp {
+            metadata.insert(column.clone(), Value::from(timestamp));
+        }
+        metadata
+    }
+
+    /// Flattens the JSON objects in the files, transforming each of them into a CSV row.
+    ///
+    /// It works like `convert_from_readers`, with the path of each file as its label, so the
+    /// objects of all the files are part of the same CSV output. The metadata columns configured
+    /// with `set_metadata_columns` are added to the headers like the keys of the objects.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Error::Input` with the position of the file if opening it fails, and in the
+    /// same cases as `convert_from_readers`.
+    pub fn convert_from_files(
+        &self,
+        paths: &[impl AsRef<Path>],
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        self.convert_from_files_with_report(paths, csv_writer)
+            .map(|report| report.rows)
+    }
+
+    /// Works like `convert_from_files`, but returns a report of the conversion, like
+    /// `convert_from_readers_with_report` does.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_files`.
+    pub fn convert_from_files_with_report(
+        &self,
+        paths: &[impl AsRef<Path>],
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<ConversionReport, error::Error> {
+        let readers = paths
+            .iter()
+            .enumerate()
+            .map(|(index, path)| {
+                let path = path.as_ref();
+                let file = File::open(path).map_err(|err| Error::Input {
+                    index,
+                    source: Box::new(err.into()),
+                })?;
+                Ok((path.display().to_string(), file))
+            })
+            .collect::<Result<Vec<_>, error::Error>>()?;
+        self.convert_from_readers_with_report(readers, csv_writer)
+    }
+
+    /// Flattens the JSON objects in the reader and writes the resulting CSV to the file at `path`,
+    /// which is created or truncated. It works like `convert_from_reader` with a CSV writer
+    /// created by `csv_writer`, and the metadata columns configured with `set_metadata_columns`
+    /// are added like `convert_from_files` does, with `path` as the source.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`, if the name of a metadata
+    /// column is the same as a flattened key, and if creating the file fails.
+    pub fn convert_from_reader_to_path(
+        &self,
+        reader: impl Read,
+        path: impl AsRef<Path>,
+    ) -> Result<usize, error::Error> {
+        let path = path.as_ref();
+        let csv_writer = self.csv_writer(File::create(path)?);
+        let timestamp = utc_timestamp(SystemTime::now());
+        let metadata = self.metadata(path.display().to_string(), &timestamp);
+        let report = self.write_from_readers(
+            [(metadata, reader)],
+            false,
+            Framing::Documents,
+            csv_writer,
+            &mut |_, _| {},
+            &mut |_| {},
+        )?;
+        Ok(report.rows)
+    }
+
+    /// Splits the JSON documents in the reader into tables, writing one CSV file per table in the
+    /// directory `dir`, which is created if needed. Returns the paths of the files written.
+    ///
+    /// Each key of the documents whose value is an array of objects is a table, and each one of
+    /// the objects in those arrays is a row of the CSV file named after the key. For example, for
+    /// `{"orders": [{"id": 1}, {"id": 2}], "customers": [{"id": 3}], "date": "2022-03-04"}` the
+    /// files `orders.csv` and `customers.csv` are written. The keys whose value is an empty array
+    /// are ignored, and the rest of the keys of each document are converted into a row of the
+    /// file `scalars.csv`, which is only written if there is any such key. The input is read like
+    /// `convert_from_reader` does, so it can be a top level array and the invalid documents are
+    /// skipped with `set_skip_invalid`. The root pointer, if any, selects the objects that are
+    /// split inside each document, the deadline and the maximum number of objects apply to
+    /// them, and the rest of the configuration is applied to the conversion of each table.
+    ///
+    /// # Errors
+    /// Will return `Err` if parsing the input fails or if the JSONs there are not objects, or if
+    /// a table name can not be used as a file name, including the name `scalars`. It will also
+    /// return `Err` if the deadline or the maximum number of objects is exceeded, if the
+    /// conversion of a table fails, or if writing to the files or to the temporary files fails.
+    pub fn convert_nested_arrays_to_dir(
+        &self,
+        reader: impl Read,
+        dir: impl AsRef<Path>,
+    ) -> Result<Vec<PathBuf>, error::Error> {
+        const SCALARS: &str = "scalars";
+
+        let mut tables = BTreeMap::<String, BufWriter<File>>::new();
+        let mut scalars: Option<BufWriter<File>> = None;
+
+        let mut index = 0;
+        self.for_each_input(reader, Framing::Documents, &mut |_, _| {}, |document| {
+            for obj in self.select_rows(&document)? {
+                self.check_deadline(index)?;
+                if let Some(limit) = self.max_objects {
+                    if index >= limit {
+                        return Err(Error::TooManyObjects { limit });
+                    }
+                }
+                let obj = obj.as_object().ok_or(Error::NotAnObject { index })?;
+                index += 1;
+
+                let mut rest = Map::new();
+                for (key, value) in obj {
+                    match value {
+                        // An empty array has no rows to convert
+                        Value::Array(rows) if rows.is_empty() => {}
+                        Value::Array(rows) if rows.iter().all(Value::is_object) => {
+                            if key == SCALARS || !is_valid_file_stem(key) {
+                                return Err(Error::InvalidTableName { name: key.clone() });
+                            }
+                            let table = match tables.entry(key.clone()) {
+                                Entry::Occupied(entry) => entry.into_mut(),
+                                Entry::Vacant(entry) => entry.insert(BufWriter::new(tempfile()?)),
+                            };
+                            for row in rows {
+                                serde_json::to_writer(&mut *table, row)?;
+                            }
+                        }
+                        _ => {
+                            rest.insert(key.clone(), value.clone());
+                        }
+                    }
+                }
+
+                if !rest.is_empty() {
+                    let file = match &mut scalars {
+                        Some(file) => file,
+                        None => scalars.insert(BufWriter::new(tempfile()?)),
+                    };
+                    serde_json::to_writer(file, &rest)?;
+                }
+            }
+            Ok(ControlFlow::Continue(()))
+        })?;
+
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+
+        let mut json2csv = self.clone();
+        json2csv.root_pointer = None;
+
+        let mut paths = Vec::new();
+        let tables = tables
+            .into_iter()
+            .chain(scalars.map(|file| (SCALARS.to_string(), file)));
+        for (name, mut table) in tables {
+            table.seek(SeekFrom::Start(0))?;
+            let table = BufReader::new(table.into_inner()?);
+
+            let path = dir.join(format!("{}.csv", name));
+            let csv_writer = json2csv.csv_writer(File::create(&path)?);
+            json2csv.convert_from_reader(table, csv_writer)?;
+            paths.push(path);
+        }
+        Ok(paths)
+    }
+
+    /// Flattens the objects in the array slice and returns an iterator over the rows of the CSV
+    /// that would result from converting them, as lists of fields, so they can be written to
+    /// something other than a CSV writer. The first row is the headers, and there are no rows if
+    /// there are no headers. The rows are always in the wide shape, without trailers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`, except for the errors writing
+    /// the CSV.
+    pub fn rows_from_array(&self, objects: &[Value]) -> Result<Rows<'_>, error::Error> {
+        let mut flat_maps = if self.spill_to_temp_file {
+            FlatMaps::temp_file(self.keep_temp_file.as_deref())?
+        } else {
+            FlatMaps::in_memory()
+        };
+        let mut collector = HeaderCollector::default();
+        for document in objects {
+            self.flatten_document(document, &mut flat_maps, &mut collector, &Map::new())?;
+        }
+        self.rows(flat_maps, collector)
+    }
+
+    /// Flattens the JSON objects in the reader and returns an iterator over the rows of the CSV
+    /// that would result from converting them. Like `rows_from_array`, but the flattened objects
+    /// are stored in a temporary file, like `convert_from_reader` does.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`, except for the errors writing
+    /// the CSV. Reading the temporary file can fail while iterating.
+    pub fn rows_from_reader(&self, reader: impl Read) -> Result<Rows<'_>, error::Error> {
+        let mut flat_maps = FlatMaps::temp_file(self.keep_temp_file.as_deref())?;
+        let mut collector = HeaderCollector::default();
+        let on_error = &mut |_, _| {};
+        self.flatten_reader_into(
+            reader,
+            &mut flat_maps,
+            &mut collector,
+            &Map::new(),
+            on_error,
+        )?;
+        self.rows(flat_maps, collector)
+    }
+
+    /// Creates the iterator over the rows of the flattened objects, sorting them if requested.
+    fn rows(
+        &self,
+        flat_maps: FlatMaps,
+        collector: HeaderCollector,
+    ) -> Result<Rows<'_>, error::Error> {
+        let headers = match &self.schema {
+            Some(schema) => {
+                collector.check_collisions()?;
+                schema.headers().to_vec()
+            }
+            None => self.collected_headers(collector)?,
+        };
+        let maps = if self.sort_by_columns.is_empty() {
+            flat_maps.into_iter()?
+        } else {
+            let mut maps = flat_maps.into_iter()?.collect::<Result<Vec<_>, _>>()?;
+            maps.sort_by(|a, b| compare_rows(&self.sort_by_columns, a, b));
+            Box::new(maps.into_iter().map(Ok))
+        };
+        Ok(Rows {
+            json2csv: self,
+            deduplicator: Deduplicator::new(self, &headers),
+            headers,
+            headers_returned: false,
+            rows: 0,
+            maps,
+        })
+    }
+
+    /// Flattens the objects in the array slice and returns them in a transposed, human friendly
+    /// format meant for display: for each object there is a block with one `header,value` line
+    /// per header, and blocks are separated by a blank line. This is useful for inspecting objects
+    /// that result in hundreds of columns in a terminal.
+    ///
+    /// For example, `[{"a": 1, "b": {"c": 2}}, {"a": 3}]` results in:
+    ///
+    /// ```csv
+    /// a,1
+    /// b.c,2
+    ///
+    /// a,3
+    /// b.c,
+    /// ```
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`, and if the output is not valid
+    /// UTF-8, which can only happen with a non ASCII quote or escape character.
+    pub fn to_transposed_string_from_array(
+        &self,
+        objects: &[Value],
+    ) -> Result<String, error::Error> {
+        let mut flat_maps = FlatMaps::in_memory();
+        let mut collector = HeaderCollector::default();
+        for document in objects {
+            self.flatten_document(document, &mut flat_maps, &mut collector, &Map::new())?;
+        }
+        let headers = self.collected_headers(collector)?;
+
+        if headers.is_empty() {
+            return Ok(String::new());
+        }
+
+        let mut output = Vec::<u8>::new();
+        for (i, map) in flat_maps.into_iter()?.enumerate() {
+            if i > 0 {
+                output.extend_from_slice(self.terminator.as_bytes());
+            }
+            // This output is meant for display, so it never starts with the byte order mark
+            let mut csv_writer = self.writer_builder().from_writer(&mut output);
+            for (header, value) in headers.iter().zip(self.build_record(&headers, map?, i)) {
+                csv_writer.write_record([header, &value])?;
+            }
+            csv_writer.flush()?;
+        }
+        Ok(String::from_utf8(output).map_err(|_| invalid_utf8())?)
+    }
+
+    /// Flattens the objects in the array slice and writes the resulting CSV to a `fmt::Write`
+    /// target, like a `String` or a `fmt::Formatter`, instead of to an `io::Write` one. It works
+    /// like `convert_from_array` with a CSV writer created by `csv_writer`.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`, and if writing to the target
+    /// fails or the output is not valid UTF-8, which can only happen with a non ASCII quote
+    /// character.
+    pub fn convert_from_array_to_fmt(
+        &self,
+        objects: &[Value],
+        output: &mut impl fmt::Write,
+    ) -> Result<usize, error::Error> {
+        let mut adapter = FmtWriter::new(output);
+        let csv_writer = self.csv_writer(&mut adapter);
+        let rows = self.convert_from_array(objects, csv_writer)?;
+        adapter.finish()?;
+        Ok(rows)
+    }
+
+    /// Flattens the objects in the array slice and appends the resulting CSV to `output`. It works
+    /// like `convert_from_array` with a CSV writer created by `csv_writer`. Unlike
+    /// `convert_from_array_to_fmt`, the CSV is written to a byte buffer that is validated once at
+    /// the end. The output of JSON strings is always valid UTF-8 as long as the quote and escape
+    /// characters are ASCII, so the validation only fails if they are not. `output` is left as it
+    /// was if the conversion fails.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`, and if the output is not valid
+    /// UTF-8, which can only happen with a non ASCII quote or escape character.
+    pub fn convert_from_array_into_string(
+        &self,
+        objects: &[Value],
+        output: &mut String,
+    ) -> Result<usize, error::Error> {
+        let mut buffer = Vec::new();
+        let rows = self.convert_from_array(objects, self.csv_writer(&mut buffer))?;
+        output.push_str(&String::from_utf8(buffer).map_err(|_| invalid_utf8())?);
+        Ok(rows)
+    }
+
+    /// Flattens the objects in the array slice and returns the resulting CSV as a string. It works
+    /// like `convert_from_array_into_string` with an empty string.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array_into_string`.
+    pub fn convert_from_array_to_string(&self, objects: &[Value]) -> Result<String, error::Error> {
+        let mut output = String::new();
+        self.convert_from_array_into_string(objects, &mut output)?;
+        Ok(output)
+    }
+
+    /// Flattens the JSON objects in the reader and returns the resulting CSV as a string. It works
+    /// like `convert_from_reader` with a CSV writer created by `csv_writer`, writing to a byte
+    /// buffer that is validated once at the end, like `convert_from_array_into_string` does.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`, and if the output is not
+    /// valid UTF-8, which can only happen with a non ASCII quote or escape character.
+    pub fn convert_from_reader_to_string(&self, reader: impl Read) -> Result<String, error::Error> {
+        let mut buffer = Vec::new();
+        self.convert_from_reader(reader, self.csv_writer(&mut buffer))?;
+        Ok(String::from_utf8(buffer).map_err(|_| invalid_utf8())?)
+    }
+
+    /// Flattens the objects in the array slice and writes the resulting CSV as a new entry of a
+    /// ZIP archive, named `entry_name`, using the default options of the `zip` crate. The entry is
+    /// finished when the next one is started or the archive is finished, so the same archive can
+    /// be used to store the output of several conversions. It works like `convert_from_array`
+    /// with a CSV writer created by `csv_writer`. Requires the `zip` feature.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`, and if starting the entry or
+    /// writing to it fails.
+    #[cfg(feature = "zip")]
+    pub fn convert_from_array_to_zip<W: Write + Seek>(
+        &self,
+        objects: &[Value],
+        zip: &mut zip::ZipWriter<W>,
+        entry_name: &str,
+    ) -> Result<usize, error::Error> {
+        zip.start_file(entry_name, zip::write::SimpleFileOptions::default())?;
+        let csv_writer = self.csv_writer(zip);
+        self.convert_from_array(objects, csv_writer)
+    }
+
+    /// Flattens the JSON objects in the gzip compressed reader, transforming each of them into a
+    /// CSV row. It works like `convert_from_reader` with the decompressed input, which can have
+    /// several gzip members one after the other, like the output of concatenating gzip files.
+    /// Requires the `gzip` feature.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`, and if the input is not valid
+    /// gzip.
+    #[cfg(feature = "gzip")]
+    pub fn convert_from_gzip_reader(
+        &self,
+        reader: impl Read,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        let reader = flate2::read::MultiGzDecoder::new(BufReader::new(reader));
+        self.convert_from_reader(reader, csv_writer)
+    }
+
+    /// Flattens the JSON objects in the reader and writes the resulting CSV compressed with gzip,
+    /// with the default compression level. It works like `convert_from_reader` with a CSV writer
+    /// created by `csv_writer`. Requires the `gzip` feature.
+    ///
+    /// Returns the number of rows written, not counting the headers.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_reader`, and if finishing the
+    /// compressed output fails.
+    #[cfg(feature = "gzip")]
+    pub fn convert_from_reader_to_gzip(
+        &self,
+        reader: impl Read,
+        writer: impl Write,
+    ) -> Result<usize, error::Error> {
+        let mut encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+        let rows = self.convert_from_reader(reader, self.csv_writer(&mut encoder))?;
+        encoder.finish()?;
+        Ok(rows)
+    }
+
+    /// Flattens the objects in the array slice and writes a data dictionary of the CSV that would
+    /// result from converting them, as a Markdown table. It has one row per column of the CSV,
+    /// with its name, the JSON type of its non empty values, the fill rate, i.e. the percentage of
+    /// rows with a non empty value, and the first non empty value as an example. The type is
+    /// `mixed` if the column has values of several types, and `empty` if it has none.
+    ///
+    /// For example, `[{"a": 1, "b": {"c": "x"}}, {"a": 2}]` results in:
+    ///
+    /// ```markdown
+    /// | Column | Type | Fill rate | Example |
+    /// | --- | --- | --- | --- |
+    /// | a | number | 100.00% | 1 |
+    /// | b.c | string | 50.00% | x |
+    /// ```
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `convert_from_array`, and if writing to `writer`
+    /// fails.
+    pub fn write_data_dictionary_from_array(
+        &self,
+        objects: &[Value],
+        mut writer: impl Write,
+    ) -> Result<(), error::Error> {
+        let mut flat_maps = FlatMaps::in_memory();
+        let mut collector = HeaderCollector::default();
+        for document in objects {
+            self.flatten_document(document, &mut flat_maps, &mut collector, &Map::new())?;
+        }
+        let headers = self.collected_headers(collector)?;
+
+        let mut stats = ColumnStats::new(headers.len());
+        let mut types: Vec<Option<&str>> = vec![None; headers.len()];
+        let mut examples: Vec<Option<String>> = vec![None; headers.len()];
+        for map in flat_maps.into_iter()? {
+            let mut map = map?;
+            stats.add_row(&headers, &map);
+            for (column, header) in headers.iter().enumerate() {
+                let value = match map.remove(header) {
+                    Some(value) if !is_empty_value(&value) => value,
+                    _ => continue,
+                };
+                types[column] = match types[column] {
+                    Some(seen) if seen != json_type(&value) => Some("mixed"),
+                    Some(seen) => Some(seen),
+                    None => Some(json_type(&value)),
+                };
+                examples[column].get_or_insert_with(|| self.format_cell(value));
+            }
+        }
+
+        writeln!(writer, "| Column | Type | Fill rate | Example |")?;
+        writeln!(writer, "| --- | --- | --- | --- |")?;
+        for (column, header) in headers.iter().enumerate() {
+            writeln!(
+                writer,
+                "| {} | {} | {:.2}% | {} |",
+                markdown_cell(header),
+                types[column].unwrap_or("empty"),
+                stats.fill_rate(column),
+                markdown_cell(examples[column].as_deref().unwrap_or_default()),
+            )?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Builds the CSV record of a flattened object, which is the row at position `row` of the
+    /// output.
+    fn build_record(
+        &self,
+        headers: &[String],
+        mut map: Map<String, Value>,
+        row: usize,
+    ) -> Vec<String> {
+        let mut record: Vec<String> = vec![];
+        for header in headers {
+            if self.row_index_column.as_ref() == Some(header) {
+                record.push((self.row_index_start + row).to_string());
+            } else if let Some(val) = map.remove(header) {
+                record.push(self.format_column_cell(header, val));
+            } else if let Some((_, value)) = self
+                .constant_columns
+                .iter()
+                .find(|(name, _)| name == header)
+            {
+                record.push(value.clone());
+            } else if let Some(missing_value_fn) = &self.missing_value_fn {
+                record.push(missing_value_fn.get()(header));
+            } else {
+                record.push(self.missing_representation.clone());
+            }
+        }
+        record
+    }
+
+    /// Converts a flattened value of the column `header` into the content of a CSV cell, with the
+    /// transform of the column if there is one.
+    fn format_column_cell(&self, header: &str, val: Value) -> String {
+        match self.column_transforms.get(header) {
+            Some(transform) => transform.get()(&val),
+            None => self.format_cell(val),
+        }
+    }
+
+    /// Converts a flattened value into the content of a CSV cell.
+    fn format_cell(&self, val: Value) -> String {
+        match val {
+            Value::String(s) if s.is_empty() && self.empty_string_as_null => {
+                self.null_representation.clone()
+            }
+            Value::String(s) => s,
+            Value::Bool(true) => self.bool_representation.0.clone(),
+            Value::Bool(false) => self.bool_representation.1.clone(),
+            Value::Number(number) => match (number.as_f64(), self.float_precision) {
+                (Some(float), Some(precision)) if number.is_f64() => {
+                    format!("{:.*}", precision, float)
+                }
+                _ => number.to_string(),
+            },
+            Value::Null => self.null_representation.clone(),
+            Value::Array(ref arr)
+                if !arr.is_empty() && self.complex_value_mode == ComplexValueMode::JsonString =>
+            {
+                val.to_string()
+            }
+            Value::Object(ref obj)
+                if !obj.is_empty() && self.complex_value_mode == ComplexValueMode::JsonString =>
+            {
+                val.to_string()
+            }
+            // Any array or object here should be empty, because it would have been flattened
+            // otherwise. In addition, to reach this for arrays and objects the flattener must
+            // have been set to preserve them when empty. Makes no sense to add them to the CSV
+            // output, so we replace them with the configured text, empty by default.
+            Value::Array(_) | Value::Object(_) => self.empty_container_representation.clone(),
+        }
+    }
+
+    /// Writes the headers of the CSV.
+    fn write_headers<I>(
+        &self,
+        csv_writer: &mut csv::Writer<impl Write>,
+        headers: I,
+    ) -> csv::Result<()>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        csv_writer.write_record(headers)
+    }
+
+    /// The names written in the header row for `headers`, which are the headers themselves unless
+    /// the schema renames some of them.
+    fn header_names<'h>(&self, headers: &'h [String]) -> Cow<'h, [String]> {
+        match &self.schema {
+            Some(schema) => headers
+                .iter()
+                .map(|header| schema.name(header).to_string())
+                .collect(),
+            None => Cow::Borrowed(headers),
+        }
+    }
+
+    /// Writes the headers and one row per flattened object, plus the trailer if requested.
+    fn write_csv(
+        &self,
+        headers: &[String],
+        flat_maps: FlatMaps,
+        csv_writer: csv::Writer<impl Write>,
+    ) -> Result<usize, error::Error> {
+        // If we could not extract headers there is nothing to write to the CSV file
+        if headers.is_empty() {
+            return Ok(0);
+        }
+
+        let mut row_writer = RowWriter::new(self, headers, csv_writer);
+        for map in flat_maps.into_iter()? {
+            row_writer.add(map?)?;
+        }
+        row_writer.finish()
+    }
+}
+
+/// Iterator over the rows of a CSV output, including the headers, as lists of fields. It is
+/// created with `Json2Csv::rows_from_array` or `Json2Csv::rows_from_reader`.
+pub struct Rows<'a> {
+    json2csv: &'a Json2Csv,
+    headers: Vec<String>,
+    headers_returned: bool,
+    /// Number of rows returned so far.
+    rows: usize,
+    /// Rows already returned, if duplicated rows are skipped.
+    deduplicator: Option<Deduplicator>,
+    maps: FlatMapsIter,
+}
+
+impl Iterator for Rows<'_> {
+    type Item = Result<Vec<String>, error::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // If there are no headers there is nothing to write to the CSV file
+        if self.headers.is_empty() {
+            return None;
+        }
+        if !self.headers_returned {
+            self.headers_returned = true;
+            return Some(Ok(self.json2csv.header_names(&self.headers).into_owned()));
+        }
+        if matches!(self.json2csv.max_rows, Some(limit) if self.rows >= limit) {
+            return None;
+        }
+        loop {
+            let record = match self.maps.next()? {
+                Ok(map) => self.json2csv.build_record(&self.headers, map, self.rows),
+                Err(err) => return Some(Err(err)),
+            };
+            if let Some(deduplicator) = &mut self.deduplicator {
+                if !deduplicator.is_new(&record) {
+                    continue;
+                }
+            }
+            self.rows += 1;
+            return Some(Ok(record));
+        }
+    }
+}
+
+/// Destination of the flattened objects.
+trait FlatMapSink {
+    /// Number of objects pushed so far.
+    fn len(&self) -> usize;
+
+    /// Adds the flattened object, which is at position `index` of the input.
+    fn push(&mut self, map: Map<String, Value>, index: usize) -> Result<(), error::Error>;
+}
+
+/// Writes the flattened objects as CSV rows, as soon as they are pushed or, if the rows have to be
+/// sorted, when all of them have been pushed. The headers are written with the first row.
+struct RowWriter<'a, W: Write> {
+    json2csv: &'a Json2Csv,
+    headers: &'a [String],
+    /// Keys allowed in the objects, if the keys that are not in the headers are an error.
+    known_keys: Option<HashSet<&'a str>>,
+    csv_writer: csv::Writer<W>,
+    rows: usize,
+    /// Number of rows already written, which is less than `rows` while sorting or if there are
+    /// duplicated rows.
+    rows_written: usize,
+    /// Rows already written, if duplicated rows are skipped.
+    deduplicator: Option<Deduplicator>,
+    /// Rows waiting to be sorted.
+    pending: Option<Vec<Map<String, Value>>>,
+    headers_written: bool,
+    stats: ColumnStats,
+}
+
+impl<'a, W: Write> RowWriter<'a, W> {
+    fn new(json2csv: &'a Json2Csv, headers: &'a [String], csv_writer: csv::Writer<W>) -> Self {
+        RowWriter {
+            json2csv,
+            headers,
+            known_keys: (json2csv.reject_unknown_keys && json2csv.schema.is_some())
+                .then(|| headers.iter().map(String::as_str).collect()),
+            csv_writer,
+            rows: 0,
+            rows_written: 0,
+            deduplicator: Deduplicator::new(json2csv, headers),
+            pending: if json2csv.sort_by_columns.is_empty() {
+                None
+            } else {
+                Some(Vec::new())
+            },
+            headers_written: false,
+            stats: ColumnStats::new(headers.len()),
+        }
+    }
+
+    /// Flushes the rows and writes the row count and the trailer statistics if requested. If no
+    /// row was written the output is left empty, unless the headers come from a schema or an
+    /// allowlist and `headers_without_rows` is set, or there were rows but `set_max_rows` left
+    /// them out, in which case the headers and the trailer are written. Returns the number of rows
+    /// written.
+    fn finish(mut self) -> Result<usize, error::Error> {
+        if let Some(mut pending) = self.pending.take() {
+            pending.sort_by(|a, b| compare_rows(&self.json2csv.sort_by_columns, a, b));
+            for map in pending {
+                self.write_row(map)?;
+            }
+        }
+        let fixed_headers =
+            self.json2csv.schema.is_some() || self.json2csv.header_allowlist.is_some();
+        if !self.headers_written
+            && (self.rows > 0 || fixed_headers && self.json2csv.headers_without_rows)
+            && !self.headers.is_empty()
+        {
+            self.write_headers()?;
+        }
+
+        let has_trailer =
+            self.json2csv.append_row_count.is_some() || self.json2csv.emit_trailer_stats;
+        if !has_trailer || !self.headers_written {
+            self.csv_writer.flush()?;
+            return Ok(self.rows_written);
+        }
+
+        let mut writer = self.csv_writer.into_inner().map_err(|e| e.into_error())?;
+        let terminator = self.json2csv.terminator.as_bytes();
+        if let Some(format) = &self.json2csv.append_row_count {
+            write!(
+                writer,
+                "{}",
+                format.replace("{}", &self.rows_written.to_string())
+            )?;
+            writer.write_all(terminator)?;
+        }
+        if self.json2csv.emit_trailer_stats {
+            self.stats.write(
+                &self.json2csv.header_names(self.headers),
+                &mut writer,
+                terminator,
+            )?;
+        }
+        writer.flush()?;
+        Ok(self.rows_written)
+    }
+
+    fn write_headers(&mut self) -> Result<(), error::Error> {
+        self.json2csv.write_headers(
+            &mut self.csv_writer,
+            &*self.json2csv.header_names(self.headers),
+        )?;
+        self.headers_written = true;
+        Ok(())
+    }
+
+    /// Writes the flattened object as a row, or keeps it if the rows have to be sorted. If the
+    /// deadline is exceeded the rows written until then are flushed before returning the error.
+    fn add(&mut self, map: Map<String, Value>) -> Result<(), error::Error> {
+        if let Err(err) = self.json2csv.check_deadline(self.rows) {
+            self.csv_writer.flush()?;
+            return Err(err);
+        }
+        match &mut self.pending {
+            Some(pending) => pending.push(map),
+            None => self.write_row(map)?,
+        }
+        self.rows += 1;
+        Ok(())
+    }
+
+    fn write_row(&mut self, map: Map<String, Value>) -> Result<(), error::Error> {
+        if matches!(self.json2csv.max_rows, Some(limit) if self.rows_written >= limit) {
+            return Ok(());
+        }
+        if !self.headers_written {
+            self.write_headers()?;
+        }
+        // With deduplication the statistics can only be updated once the row is known to be new
+        let pending_stats = match (&self.deduplicator, self.json2csv.emit_trailer_stats) {
+            (None, true) => {
+                self.stats.add_row(self.headers, &map);
+                None
+            }
+            (Some(_), true) => Some(map.clone()),
+            (_, false) => None,
+        };
+        let record = self
+            .json2csv
+            .build_record(self.headers, map, self.rows_written);
+        if let Some(deduplicator) = &mut self.deduplicator {
+            if !deduplicator.is_new(&record) {
+                return Ok(());
+            }
+        }
+        if let Some(map) = pending_stats {
+            self.stats.add_row(self.headers, &map);
+        }
+        self.csv_writer.write_record(record)?;
+        self.rows_written += 1;
+        Ok(())
+    }
+}
+
+impl<W: Write> FlatMapSink for RowWriter<'_, W> {
+    fn len(&self) -> usize {
+        self.rows
+    }
+
+    /// Like `add`, but first checks that the object has no unknown keys, if they are an error.
+    fn push(&mut self, map: Map<String, Value>, index: usize) -> Result<(), error::Error> {
+        if let Some(known_keys) = &self.known_keys {
+            if let Some(key) = map.keys().find(|key| !known_keys.contains(key.as_str())) {
+                return Err(Error::UnknownKey {
+                    object_index: index,
+                    key: key.clone(),
+                });
+            }
+        }
+        self.add(map)
+    }
+}
+
+/// Writes the flattened objects in the long shape, with one row per key, as soon as they are
+/// pushed. The headers are written with the first row.
+struct LongWriter<'a, W: Write> {
+    json2csv: &'a Json2Csv,
+    csv_writer: csv::Writer<W>,
+    objects: usize,
+    rows: usize,
+    headers_written: bool,
+}
+
+impl<'a, W: Write> LongWriter<'a, W> {
+    fn new(json2csv: &'a Json2Csv, csv_writer: csv::Writer<W>) -> Self {
+        LongWriter {
+            json2csv,
+            csv_writer,
+            objects: 0,
+            rows: 0,
+            headers_written: false,
+        }
+    }
+
+    /// Flushes the rows. Returns the number of rows written.
+    fn finish(mut self) -> Result<usize, error::Error> {
+        self.csv_writer.flush()?;
+        Ok(self.rows)
+    }
+}
+
+impl<W: Write> FlatMapSink for LongWriter<'_, W> {
+    fn len(&self) -> usize {
+        self.objects
+    }
+
+    /// Writes one row per key of the flattened object. If the deadline is exceeded the rows
+    /// written until then are flushed before returning the error.
+    fn push(&mut self, map: Map<String, Value>, _index: usize) -> Result<(), error::Error> {
+        if let Err(err) = self.json2csv.check_deadline(self.objects) {
+            self.csv_writer.flush()?;
+            return Err(err);
+        }
+        if matches!(self.json2csv.max_rows, Some(limit) if self.objects >= limit) {
+            return Ok(());
+        }
+        let (with_types, skip_empty) = match self.json2csv.output_shape {
+            OutputShape::Long {
+                with_types,
+                skip_empty,
+            } => (with_types, skip_empty),
+            OutputShape::Wide => unreachable!("Only used to write the long shape"),
+        };
+
+        let row_id = self.objects.to_string();
+        for (key, value) in map {
+            if skip_empty && is_empty_value(&value) {
+                continue;
+            }
+            if !self.headers_written {
+                let headers = ["row_id", "key", "value", "type"];
+                self.json2csv.write_headers(
+                    &mut self.csv_writer,
+                    &headers[..if with_types { 4 } else { 3 }],
+                )?;
+                self.headers_written = true;
+            }
+            let value_type = json_type(&value);
+            let cell = self.json2csv.format_column_cell(&key, value);
+            if with_types {
+                self.csv_writer
+                    .write_record([&row_id, &key, &cell, value_type])?;
+            } else {
+                self.csv_writer.write_record([&row_id, &key, &cell])?;
+            }
+            self.rows += 1;
+        }
+        self.objects += 1;
+        Ok(())
+    }
+}
+
+/// Writer that writes the UTF-8 byte order mark before the first bytes written to it, if
+/// requested, so it is never quoted together with the first header and empty outputs stay empty.
+struct BomWriter<W: Write> {
+    writer: W,
+    /// If `true` the mark has to be written before the next bytes.
+    pending: bool,
+}
+
+impl<W: Write> BomWriter<W> {
+    fn new(writer: W, write_bom: bool) -> Self {
+        BomWriter {
+            writer,
+            pending: write_bom,
+        }
+    }
+}
+
+impl<W: Write> Write for BomWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self.pending && !buf.is_empty() {
+            self.writer.write_all(UTF8_BOM)?;
+            self.pending = false;
+        }
+        self.writer.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Adapter to write UTF-8 bytes to a `fmt::Write` target. Characters split between two writes are
+/// kept until the rest of their bytes arrive.
+struct FmtWriter<'a, W: fmt::Write> {
+    output: &'a mut W,
+    /// Bytes of an incomplete character at the end of the last write.
+    pending: Vec<u8>,
+}
+
+impl<'a, W: fmt::Write> FmtWriter<'a, W> {
+    fn new(output: &'a mut W) -> Self {
+        FmtWriter {
+            output,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Fails if the output ends with an incomplete character.
+    fn finish(self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            return Err(invalid_utf8());
+        }
+        Ok(())
+    }
+}
+
+impl<W: fmt::Write> Write for FmtWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid_up_to = match std::str::from_utf8(&self.pending) {
+            Ok(_) => self.pending.len(),
+            // An error with length means that the bytes are not valid, no matter what comes next
+            Err(err) if err.error_len().is_some() => return Err(invalid_utf8()),
+            Err(err) => err.valid_up_to(),
+        };
+        let valid = std::str::from_utf8(&self.pending[..valid_up_to])
+            .expect("We just checked that these bytes are valid UTF-8");
+        self.output
+            .write_str(valid)
+            .map_err(|_| std::io::Error::other("Writing to the fmt::Write target failed"))?;
+        self.pending.drain(..valid_up_to);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+fn invalid_utf8() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        "The CSV output is not valid UTF-8",
+    )
+}
+
+/// Function that validates an object of the input.
+type ObjectValidator = dyn Fn(&Value) -> Result<(), String> + Send + Sync;
+
+/// Function that writes the values of a column.
+type ColumnTransform = dyn Fn(&Value) -> String + Send + Sync;
+
+/// Function that writes the missing value of the column with the header given.
+type MissingValueFn = dyn Fn(&str) -> String + Send + Sync;
+
+/// Function that flattens the objects that have to be converted, in order, given with their
+/// position in the input.
+type ObjectFlattener<'a> = dyn FnMut(&Value, usize) -> Result<FlatObject, error::Error> + 'a;
+
+/// Function called with the position and the error of each value of the input skipped.
+type ErrorSink<'a> = dyn FnMut(usize, error::Error) + 'a;
+
+/// Iterator over the flattened objects stored in `FlatMaps`.
+type FlatMapsIter = Box<dyn Iterator<Item = Result<Map<String, Value>, error::Error>>>;
+
+/// Storage for the flattened objects while the headers of the CSV are being computed.
+enum FlatMaps {
+    /// The objects are not stored, only counted.
+    Discarded(usize),
+    InMemory(Vec<Map<String, Value>>),
+    /// The objects are stored one after the other in a temporary file.
+    TempFile {
+        file: BufWriter<File>,
+        len: usize,
+    },
+}
+
+impl FlatMaps {
+    fn in_memory() -> Self {
+        FlatMaps::InMemory(Vec::new())
+    }
+
+    /// Uses an anonymous temporary file, or the file at `keep_path` if provided, which is not
+    /// deleted afterwards.
+    fn temp_file(keep_path: Option<&Path>) -> Result<Self, error::Error> {
+        Ok(FlatMaps::TempFile {
+            file: BufWriter::new(open_temp_file(keep_path)?),
+            len: 0,
+        })
+    }
+
+    /// Returns the stored objects in the same order they were pushed.
+    fn into_iter(self) -> Result<FlatMapsIter, error::Error> {
+        match self {
+            FlatMaps::Discarded(_) => Ok(Box::new(std::iter::empty())),
+            FlatMaps::InMemory(maps) => Ok(Box::new(maps.into_iter().map(Ok))),
+            FlatMaps::TempFile { mut file, len: _ } => {
+                file.seek(SeekFrom::Start(0))?;
+                let file = BufReader::new(file.into_inner()?);
+                Ok(Box::new(
+                    Deserializer::from_reader(file)
+                        .into_iter::<Value>()
+                        .map(|obj| match obj? {
+                            Value::Object(map) => Ok(map),
+                            _ => unreachable!(
+                                "Flattening a JSON object always produces a JSON object"
+                            ),
+                        }),
+                ))
+            }
+        }
+    }
+}
+
+impl FlatMapSink for FlatMaps {
+    fn len(&self) -> usize {
+        match self {
+            FlatMaps::Discarded(len) => *len,
+            FlatMaps::InMemory(maps) => maps.len(),
+            FlatMaps::TempFile { file: _, len } => *len,
+        }
+    }
+
+    fn push(&mut self, map: Map<String, Value>, _index: usize) -> Result<(), error::Error> {
+        match self {
+            FlatMaps::Discarded(len) => *len += 1,
+            FlatMaps::InMemory(maps) => maps.push(map),
+            FlatMaps::TempFile { file, len } => {
+                serde_json::to_writer(file, &map)?;
+                *len += 1;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A flattened object, with its keys transformed to the format requested by the user.
+struct FlatObject {
+    map: Map<String, Value>,
+    /// The keys with our magic separators and the keys they were transformed into.
+    keys: Vec<(String, String)>,
+    /// The columns added by coalescing keys.
+    targets: Vec<String>,
+}
+
+/// Keys found while flattening the input, used to compute the headers of the CSV.
+#[derive(Default)]
+struct HeaderCollector {
+    /// The keys with our magic separators, by the key with the separators that the user requested
+    /// that they end looking like.
+    orig_headers: BTreeMap<String, BTreeSet<String>>,
+    /// The keys with the separators that the user requested that more than one key with our magic
+    /// separators end looking like.
+    colliding_headers: BTreeSet<String>,
+    /// The keys with the separators that the user requested, with the order in which they were
+    /// first found.
+    headers: BTreeMap<String, usize>,
+    /// The keys present in all the objects seen so far, if any.
+    common_headers: Option<BTreeSet<String>>,
+    /// Number of values of the input that had to be converted seen so far, objects or not, which
+    /// is the position of the next one in the errors.
+    values: usize,
+}
+
+impl HeaderCollector {
+    fn insert(&mut self, orig_key: String, key: String) {
+        let orig_keys = self.orig_headers.entry(key.clone()).or_default();
+        if orig_keys.insert(orig_key) && orig_keys.len() > 1 {
+            self.colliding_headers.insert(key.clone());
+        }
+        let next = self.headers.len();
+        self.headers.entry(key).or_insert(next);
+    }
+
+    /// Updates the keys present in all the objects with the keys of a flattened object.
+    /// Keys with a null value are ignored unless `null_counts_as_present` is `true`.
+    fn finish_object(&mut self, map: &Map<String, Value>, null_counts_as_present: bool) {
+        let present = |value: &Value| null_counts_as_present || !value.is_null();
+        match &mut self.common_headers {
+            Some(common_headers) => {
+                common_headers.retain(|key| map.get(key).is_some_and(present));
+            }
+            None => {
+                let keys = map.iter().filter(|(_, value)| present(value));
+                self.common_headers = Some(keys.map(|(key, _)| key.clone()).collect());
+            }
+        }
+    }
+
+    /// The keys that are not present in all the objects, sorted alphabetically.
+    fn uncommon_headers(&self) -> Vec<String> {
+        let common_headers = self.common_headers.as_ref();
+        self.headers
+            .keys()
+            .filter(|header| !common_headers.is_some_and(|common| common.contains(*header)))
+            .cloned()
+            .collect()
+    }
+
+    /// Checks that there are no collisions between the flattened keys collected so far.
+    /// Removes and returns the colliding keys, so the headers can be computed anyway.
+    fn take_collisions(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.colliding_headers)
+            .into_iter()
+            .collect()
+    }
+
+    fn check_collisions(&self) -> Result<(), error::Error> {
+        if !self.colliding_headers.is_empty() {
+            return Err(Error::FlattenedKeysCollision {
+                keys: self.colliding_headers.iter().cloned().collect(),
+            });
+        }
+        Ok(())
+    }
+
+    /// The headers are the union or the intersection of the keys of the flattened objects,
+    /// in the requested order.
+    ///
+    /// # Errors
+    /// Will return `Err` if two keys that should be different end looking the same after
+    /// flattening.
+    fn into_headers(
+        self,
+        strategy: HeaderStrategy,
+        order: &HeaderOrder,
+    ) -> Result<Vec<String>, error::Error> {
+        // Check that there are no collisions between flattened keys in different objects
+        self.check_collisions()?;
+        let mut headers: Vec<(String, usize)> = match strategy {
+            HeaderStrategy::Union => self.headers.into_iter().collect(),
+            HeaderStrategy::Intersection => {
+                let common_headers = self.common_headers.unwrap_or_default();
+                let mut headers = self.headers;
+                headers.retain(|header, _| common_headers.contains(header));
+                headers.into_iter().collect()
+            }
+        };
+        match order {
+            // They are already sorted alphabetically
+            HeaderOrder::Alphabetical => {}
+            HeaderOrder::FirstSeen => headers.sort_by_key(|(_, first_seen)| *first_seen),
+            HeaderOrder::Natural => headers.sort_by(|(a, _), (b, _)| natural_cmp(a, b)),
+            HeaderOrder::Custom(compare) => headers.sort_by(|(a, _), (b, _)| compare.get()(a, b)),
+        }
+        Ok(headers.into_iter().map(|(header, _)| header).collect())
+    }
+}
+
+/// Rows already written, to skip the ones that are written again.
+struct Deduplicator {
+    seen: HashSet<Vec<String>>,
+    /// Position of the row index column, which is not compared.
+    row_index: Option<usize>,
+}
+
+impl Deduplicator {
+    /// Creates a deduplicator for the rows with the headers given, if the rows have to be
+    /// deduplicated.
+    fn new(json2csv: &Json2Csv, headers: &[String]) -> Option<Self> {
+        json2csv.deduplicate_rows.then(|| Deduplicator {
+            seen: HashSet::new(),
+            row_index: json2csv
+                .row_index_column
+                .as_ref()
+                .and_then(|column| headers.iter().position(|header| header == column)),
+        })
+    }
+
+    /// Returns `true` if no row with the same fields was seen before, remembering it.
+    fn is_new(&mut self, record: &[String]) -> bool {
+        let mut record = record.to_vec();
+        if let Some(row_index) = self.row_index {
+            record.remove(row_index);
+        }
+        self.seen.insert(record)
+    }
+}
+
+/// Sink that reports the number of objects pushed to another sink each time one is pushed.
+struct WithProgress<'a, S> {
+    sink: &'a mut S,
+    progress: &'a mut dyn FnMut(usize),
+}
+
+impl<'a, S: FlatMapSink> WithProgress<'a, S> {
+    fn new(sink: &'a mut S, progress: &'a mut dyn FnMut(usize)) -> Self {
+        WithProgress { sink, progress }
+    }
+}
+
+impl<S: FlatMapSink> FlatMapSink for WithProgress<'_, S> {
+    fn len(&self) -> usize {
+        self.sink.len()
+    }
+
+    fn push(&mut self, map: Map<String, Value>, index: usize) -> Result<(), error::Error> {
+        self.sink.push(map, index)?;
+        (self.progress)(self.sink.len());
+        Ok(())
+    }
+}
+
+/// Statistics about the columns of the CSV output, written as a trailer if requested.
+struct ColumnStats {
+    /// Number of rows written.
+    rows: usize,
+    /// Number of rows with a non empty value, for each column.
+    filled: Vec<usize>,
+}
+
+impl ColumnStats {
+    fn new(columns: usize) -> Self {
+        ColumnStats {
+            rows: 0,
+            filled: vec![0; columns],
+        }
+    }
+
+    fn add_row(&mut self, headers: &[String], map: &Map<String, Value>) {
+        self.rows += 1;
+        for (filled, header) in self.filled.iter_mut().zip(headers) {
+            if map.get(header).is_some_and(|val| !is_empty_value(val)) {
+                *filled += 1;
+            }
+        }
+    }
+
+    /// Writes the statistics as comment lines, each one ending with `terminator`.
+    fn write(
+        &self,
+        headers: &[String],
+        writer: &mut impl Write,
+        terminator: &[u8],
+    ) -> std::io::Result<()> {
+        let mut lines = vec![
+            format!("# rows: {}", self.rows),
+            format!("# columns: {}", headers.len()),
+        ];
+        for (column, header) in headers.iter().enumerate() {
+            lines.push(format!(
+                "# fill rate {}: {:.2}%",
+                header,
+                self.fill_rate(column)
+            ));
+        }
+        for line in lines {
+            writer.write_all(line.as_bytes())?;
+            writer.write_all(terminator)?;
+        }
+        Ok(())
+    }
+
+    /// Percentage of rows with a non empty value in the column.
+    fn fill_rate(&self, column: usize) -> f64 {
+        if self.rows == 0 {
+            0.0
+        } else {
+            self.filled[column] as f64 * 100.0 / self.rows as f64
+        }
+    }
+}
+
+/// Compares two flattened objects by the given columns. Missing values go last in any direction.
+fn compare_rows(
+    columns: &[(String, SortDir)],
+    a: &Map<String, Value>,
+    b: &Map<String, Value>,
+) -> Ordering {
+    fn present<'a>(map: &'a Map<String, Value>, column: &str) -> Option<&'a Value> {
+        map.get(column).filter(|value| !is_empty_value(value))
+    }
+
+    columns
+        .iter()
+        .map(
+            |(column, dir)| match (present(a, column), present(b, column)) {
+                (Some(a), Some(b)) => match dir {
+                    SortDir::Ascending => compare_values(a, b),
+                    SortDir::Descending => compare_values(b, a),
+                },
+                (Some(_), None) => Ordering::Less,
+                (None, Some(_)) => Ordering::Greater,
+                (None, None) => Ordering::Equal,
+            },
+        )
+        .find(|ordering| ordering.is_ne())
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Compares two non empty flattened values. Booleans go before numbers and numbers before strings.
+fn compare_values(a: &Value, b: &Value) -> Ordering {
+    let rank = |value: &Value| match value {
+        Value::Bool(_) => 0,
+        Value::Number(_) => 1,
+        _ => 2,
+    };
+
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+        },
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        _ => rank(a).cmp(&rank(b)),
+    }
+}
+
+/// How the top level JSON documents of a reader are separated.
+#[derive(Clone, Copy)]
+enum Framing {
+    /// One after the other or separated by whitespace, or as the elements of a single array.
+    Documents,
+    /// One per line, in JSON Lines format.
+    Lines,
+}
+
+/// Calls `f` with each top level JSON document in the reader, parsing them one at a time, until
+/// it breaks. If the input is a JSON array, its elements are the documents. If
+/// `reject_duplicate_keys` is set, the objects with repeated keys are an error. If `on_error` is
+/// provided, the documents that can not be parsed or have repeated keys are skipped, calling it
+/// with their position and error, and parsing resumes in the next line or the next document,
+/// respectively.
+fn for_each_document(
+    reader: impl Read,
+    reject_duplicate_keys: bool,
+    mut on_error: Option<&mut ErrorSink>,
+    mut f: impl FnMut(Value) -> Result<ControlFlow<()>, error::Error>,
+) -> Result<(), error::Error> {
+    let mut reader = BufReader::new(reader);
+    // Position of the document being parsed
+    let mut index = 0;
+    // The rest of the line where a document could not be parsed
+    let mut skipped = Vec::new();
+    if !starts_with_array(&mut reader)? {
+        loop {
+            let mut documents = Deserializer::from_reader(&mut reader).into_iter::<Document>();
+            let err = loop {
+                match documents.next() {
+                    Some(Ok(document)) => {
+                        index += 1;
+                        match document.into_value(reject_duplicate_keys) {
+                            Ok(document) => {
+                                if f(document)?.is_break() {
+                                    return Ok(());
+                                }
+                            }
+                            Err(err) => match &mut on_error {
+                                Some(on_error) => on_error(index - 1, err),
+                                None => return Err(err),
+                            },
+                        }
+                    }
+                    Some(Err(err)) => break err,
+                    None => return Ok(()),
+                }
+            };
+            match &mut on_error {
+                // Errors reading the input can not be skipped
+                Some(on_error) if !err.is_io() => {
+                    on_error(index, parsing_error(err, index));
+                    index += 1;
+                    skipped.clear();
+                    reader.read_until(b'\n', &mut skipped)?;
+                }
+                _ => return Err(parsing_error(err, index)),
+            }
+        }
+    }
+
+    let mut deserializer = Deserializer::from_reader(reader);
+    let mut error = None;
+    let mut stopped = false;
+    let mut count_and_call = |document| {
+        index += 1;
+        f(document)
+    };
+    let result = serde::Deserializer::deserialize_seq(
+        &mut deserializer,
+        ArrayElementsVisitor {
+            f: &mut count_and_call,
+            error: &mut error,
+            stopped: &mut stopped,
+            reject_duplicate_keys,
+        },
+    );
+    if let Some(error) = error {
+        return Err(error);
+    }
+    if stopped {
+        return Ok(());
+    }
+    result
+        .and_then(|()| deserializer.end())
+        .map_err(|err| parsing_error(err, index))
+}
+
+/// Calls `f` with the JSON document in each line of the reader, parsing them one at a time, until
+/// it breaks. The lines that are empty or only have whitespace are ignored. If
+/// `reject_duplicate_keys` is set, the objects with repeated keys are an error. If `on_error` is
+/// provided, the lines that can not be parsed or have repeated keys are skipped, calling it with
+/// their line number and error.
+fn for_each_line(
+    reader: impl Read,
+    reject_duplicate_keys: bool,
+    mut on_error: Option<&mut ErrorSink>,
+    mut f: impl FnMut(Value) -> Result<ControlFlow<()>, error::Error>,
+) -> Result<(), error::Error> {
+    let mut reader = BufReader::new(reader);
+    let mut line = Vec::new();
+    let mut line_number = 0;
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(());
+        }
+        line_number += 1;
+        if line.iter().all(u8::is_ascii_whitespace) {
+            continue;
+        }
+        let document = serde_json::from_slice::<Document>(&line)
+            .map_err(|source| Error::ParsingLine {
+                line: line_number,
+                source,
+            })
+            .and_then(|document| document.into_value(reject_duplicate_keys));
+        match (document, &mut on_error) {
+            (Ok(document), _) => {
+                if f(document)?.is_break() {
+                    return Ok(());
+                }
+            }
+            (Err(err), Some(on_error)) => on_error(line_number, err),
+            (Err(err), None) => return Err(err),
+        }
+    }
+}
+
+/// Adds the position of the document that could not be parsed to the errors parsing the input.
+/// Errors reading the input are returned as they are.
+fn parsing_error(err: serde_json::Error, index: usize) -> error::Error {
+    if err.is_io() {
+        return err.into();
+    }
+    Error::ParsingDocument { index, source: err }
+}
+
+/// Checks if the first character of the reader that is not whitespace is `[`. The whitespace is
+/// consumed.
+fn starts_with_array(reader: &mut impl BufRead) -> std::io::Result<bool> {
+    loop {
+        let buf = reader.fill_buf()?;
+        if buf.is_empty() {
+            return Ok(false);
+        }
+        match buf.iter().position(|byte| !byte.is_ascii_whitespace()) {
+            Some(pos) => {
+                let is_array = buf[pos] == b'[';
+                reader.consume(pos);
+                return Ok(is_array);
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Visits the elements of a JSON array one by one, calling `f` with each of them. If `f` fails,
+/// or an element has repeated keys and `reject_duplicate_keys` is set, the error is stored in
+/// `error` and the visit is aborted.
+struct ArrayElementsVisitor<'a, F> {
+    f: &'a mut F,
+    error: &'a mut Option<error::Error>,
+    /// Set if `f` breaks, since then the rest of the array is not read.
+    stopped: &'a mut bool,
+    reject_duplicate_keys: bool,
+}
+
+impl<'de, F> Visitor<'de> for ArrayElementsVisitor<'_, F>
+where
+    F: FnMut(Value) -> Result<ControlFlow<()>, error::Error>,
+{
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a JSON array")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(element) = seq.next_element::<Document>()? {
+            let result = element
+                .into_value(self.reject_duplicate_keys)
+                .and_then(&mut *self.f);
+            match result {
+                Ok(ControlFlow::Continue(())) => {}
+                Ok(ControlFlow::Break(())) => {
+                    *self.stopped = true;
+                    return Err(serde::de::Error::custom("Reading the array was stopped"));
+                }
+                Err(err) => {
+                    *self.error = Some(err);
+                    return Err(serde::de::Error::custom(
+                        "Converting the array element failed",
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Opens an anonymous temporary file, or the file at `keep_path` if provided, truncating it.
+fn open_temp_file(keep_path: Option<&Path>) -> std::io::Result<File> {
+    match keep_path {
+        Some(path) => File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path),
+        None => tempfile(),
+    }
+}
+
+/// Returns `true` if `name` can be used as the name of a file, without the extension, in any
+/// directory.
+fn is_valid_file_stem(name: &str) -> bool {
+    !name.is_empty()
+        && name != "."
+        && name != ".."
+        && !name.contains(|c: char| c == '/' || c == '\\' || c.is_control())
+}
+
+/// Formats `time` as an RFC 3339 UTC timestamp with second precision, e.g.
+/// `2022-03-04T05:06:07Z`.
+fn utc_timestamp(time: SystemTime) -> String {
+    let secs = time
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Converts the days since the epoch into a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+/// Returns `true` for the values that result in an empty CSV field.
+fn is_empty_value(val: &Value) -> bool {
+    match val {
+        Value::Null => true,
+        Value::String(s) => s.is_empty(),
+        Value::Array(a) => a.is_empty(),
+        Value::Object(o) => o.is_empty(),
+        Value::Bool(_) | Value::Number(_) => false,
+    }
+}
+
+/// Escapes the text so it can be used as the content of a Markdown table cell.
+fn markdown_cell(text: &str) -> String {
+    text.replace('|', "\\|").replace(['\r', '\n'], " ")
+}
+
+/// Name of the JSON type of a value.
+fn json_type(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "bool",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
     use error::Error;
     use flatten_json_object::{ArrayFormatting, Flattener};
     use rstest::rstest;
     use std::str;
 
-    struct ExecutionResult {
-        input: Vec<Value>,
-        output: String,
+    struct ExecutionResult {
+        input: Vec<Value>,
+        output: String,
+    }
+
+    fn execute_expect_err(input: &str, flattener: &Flattener) -> Vec<error::Error> {
+        execute_with_expect_err(input, &Json2Csv::new(flattener.clone()))
+    }
+
+    fn execute_with_expect_err(input: &str, json2csv: &Json2Csv) -> Vec<error::Error> {
+        let mut output_from_file = Vec::<u8>::new();
+        let csv_writer_from_file = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_file);
+
+        let result_from_file = json2csv
+            .clone()
+            .convert_from_reader(input.as_bytes(), csv_writer_from_file);
+
+        let input_from_array: Result<Vec<_>, _> =
+            Deserializer::from_str(input).into_iter::<Value>().collect();
+        let input_from_array = input_from_array.unwrap();
+
+        let mut output_from_array = Vec::<u8>::new();
+        let csv_writer_from_array = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_array);
+        let result_from_array = json2csv
+            .clone()
+            .convert_from_array(&input_from_array, csv_writer_from_array);
+
+        let mut output_from_spilled_array = Vec::<u8>::new();
+        let csv_writer_from_spilled_array = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_spilled_array);
+        let result_from_spilled_array = json2csv
+            .clone()
+            .set_spill_to_temp_file(true)
+            .convert_from_array(&input_from_array, csv_writer_from_spilled_array);
+
+        // We expect all of them to produce the same error
+        let error_from_file = result_from_file.err().unwrap();
+        let error_from_array = result_from_array.err().unwrap();
+        let error_from_spilled_array = result_from_spilled_array.err().unwrap();
+
+        vec![error_from_file, error_from_array, error_from_spilled_array]
+    }
+
+    fn execute(input: &str, flattener: &Flattener) -> ExecutionResult {
+        execute_with(input, &Json2Csv::new(flattener.clone()))
+    }
+
+    fn execute_with(input: &str, json2csv: &Json2Csv) -> ExecutionResult {
+        let mut output_from_file = Vec::<u8>::new();
+        let csv_writer_from_file = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_file);
+        json2csv
+            .clone()
+            .convert_from_reader(input.as_bytes(), csv_writer_from_file)
+            .unwrap();
+
+        let input_from_array: Result<Vec<_>, _> =
+            Deserializer::from_str(input).into_iter::<Value>().collect();
+        let input_from_array = input_from_array.unwrap();
+
+        let mut output_from_array = Vec::<u8>::new();
+        let csv_writer_from_array = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_array);
+        json2csv
+            .clone()
+            .convert_from_array(&input_from_array, csv_writer_from_array)
+            .unwrap();
+
+        let mut output_from_spilled_array = Vec::<u8>::new();
+        let csv_writer_from_spilled_array = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_spilled_array);
+        json2csv
+            .clone()
+            .set_spill_to_temp_file(true)
+            .convert_from_array(&input_from_array, csv_writer_from_spilled_array)
+            .unwrap();
+
+        let mut output_from_owned_array = Vec::<u8>::new();
+        let csv_writer_from_owned_array = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_owned_array);
+        json2csv
+            .clone()
+            .convert_from_owned_array(input_from_array.clone(), csv_writer_from_owned_array)
+            .unwrap();
+
+        let mut output_from_seekable = Vec::<u8>::new();
+        let csv_writer_from_seekable = csv::WriterBuilder::new()
+            .delimiter(b',')
+            .from_writer(&mut output_from_seekable);
+        json2csv
+            .clone()
+            .convert_from_seekable(std::io::Cursor::new(input), csv_writer_from_seekable)
+            .unwrap();
+
+        let output_from_file = str::from_utf8(&output_from_file).unwrap();
+        let output_from_array = str::from_utf8(&output_from_array).unwrap();
+        let output_from_spilled_array = str::from_utf8(&output_from_spilled_array).unwrap();
+        let output_from_owned_array = str::from_utf8(&output_from_owned_array).unwrap();
+        let output_from_seekable = str::from_utf8(&output_from_seekable).unwrap();
+
+        assert_eq!(output_from_file, output_from_array);
+        assert_eq!(output_from_array, output_from_spilled_array);
+        assert_eq!(output_from_array, output_from_owned_array);
+        assert_eq!(output_from_array, output_from_seekable);
+
+        ExecutionResult {
+            input: input_from_array,
+            output: output_from_array.to_string(),
+        }
+    }
+
+    #[rstest]
+    #[case::nesting_and_array(r#"{"a": {"b": 1}}{"c": [2]}"#, &["a.b,c.0", "1,", ",2"])]
+    #[case::spaces_end(r#"{"a": {"b": 1}}{"c": [2]}   "#, &["a.b,c.0", "1,", ",2"])]
+    #[case::spaces_begin(r#"      {"a": {"b": 1}}{"c": [2]}"#, &["a.b,c.0", "1,", ",2"])]
+    #[case::key_repeats_consistently(r#"{"a": 3}{"a": 4}{"a": 5}"#, &["a", "3", "4", "5"])]
+    #[case::reordering(r#"{"b": 3, "a": 1}{"a": 4, "b": 2}"#, &["a,b", "1,3", "4,2"])]
+    #[case::reordering_with_empty_array(r#"{"b": 3, "a": 1, "c": 0}{"c": [], "a": 4, "b": 2}"#, &["a,b,c", "1,3,0", "4,2,"])]
+    #[case::reordering_with_empty_object(r#"{"b": 3, "a": 1, "c": 0}{"c": {}, "a": 4, "b": 2}"#, &["a,b,c", "1,3,0", "4,2,"])]
+    #[case::reordering_with_missing(r#"{"b": 3, "a": 1, "c": 0}{"a": 4, "b": 2}"#, &["a,b,c", "1,3,0", "4,2,"])]
+    fn simple_input(
+        #[case] input: &str,
+        #[case] expected: &[&str],
+        #[values(true, false)] preserve_empty_arrays: bool,
+        #[values(true, false)] preserve_empty_objects: bool,
+    ) {
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Plain)
+            .set_preserve_empty_arrays(preserve_empty_arrays)
+            .set_preserve_empty_objects(preserve_empty_objects);
+        let result = execute(input, &flattener);
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn booleans_with_missing_keys() {
+        let result = execute(
+            r#"{"a": true, "b": 1} {"a": false} {"b": 2}"#,
+            &Flattener::new(),
+        );
+        assert_eq!(result.output, "a,b\ntrue,1\nfalse,\n,2\n");
+    }
+
+    #[rstest]
+    #[case::key_separator(Flattener::new(), r#"{"a": {"b␝c": 1}}"#, "b␝c")]
+    #[case::array_start(
+        Flattener::new().set_array_formatting(ArrayFormatting::Surrounded {
+            start: "[".to_string(),
+            end: "]".to_string(),
+        }),
+        r#"{"a": [{"␞": 1}]}"#,
+        "␞"
+    )]
+    fn error_on_reserved_characters(
+        #[case] flattener: Flattener,
+        #[case] input: &str,
+        #[case] expected_key: &str,
+    ) {
+        for error in execute_expect_err(input, &flattener) {
+            assert!(
+                matches!(error, Error::ReservedCharacterInInput { ref key } if key == expected_key),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn custom_internal_sentinels() {
+        let flattener = Flattener::new().set_array_formatting(ArrayFormatting::Surrounded {
+            start: "[".to_string(),
+            end: "]".to_string(),
+        });
+        let json2csv = Json2Csv::new(flattener).set_internal_sentinels(
+            "\u{1d}".to_string(),
+            "\u{1e}".to_string(),
+            "\u{1f}".to_string(),
+        );
+        let result = execute_with(r#"{"a␝": [{"b␞": 1}]}"#, &json2csv);
+        assert_eq!(result.output, "a␝[0].b␞\n1\n");
+
+        for error in execute_with_expect_err(r#"{"a": {"b": 1}} {"a.b": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+        for error in execute_with_expect_err(r#"{"a\u001f": 1}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::ReservedCharacterInInput { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::contained("|", "||", "#", "||")]
+    #[case::equal("|", "#", "|", "|")]
+    #[case::empty("|", "", "#", "")]
+    fn error_on_invalid_internal_sentinels(
+        #[case] key_separator: &str,
+        #[case] array_start: &str,
+        #[case] array_end: &str,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_internal_sentinels(
+            key_separator.to_string(),
+            array_start.to_string(),
+            array_end.to_string(),
+        );
+        for error in execute_with_expect_err(r#"{"a": {"b": 1}}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::InvalidSeparatorConfig { ref separator } if separator == expected),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn error_on_invalid_internal_sentinels_from_plan() {
+        let plan = ConversionPlan {
+            internal_sentinels: (String::new(), "[".to_string(), "]".to_string()),
+            ..Json2Csv::new(Flattener::new()).to_plan().unwrap()
+        };
+        let json2csv = Json2Csv::from_plan(plan).unwrap();
+        for error in execute_with_expect_err(r#"{"a": 1}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::InvalidSeparatorConfig { ref separator } if separator.is_empty()),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::key_separator("␝", "[", "]")]
+    #[case::array_start(".", "␞", "]")]
+    #[case::array_end(".", "[", "x␟")]
+    #[case::separator_with_array_sentinel("␟", "[", "]")]
+    fn error_on_separator_with_internal_sentinels(
+        #[case] key_separator: &str,
+        #[case] start: &str,
+        #[case] end: &str,
+    ) {
+        let flattener = Flattener::new()
+            .set_key_separator(key_separator)
+            .set_array_formatting(ArrayFormatting::Surrounded {
+                start: start.to_string(),
+                end: end.to_string(),
+            });
+        for error in execute_expect_err(r#"{"a": {"b": [1]}}"#, &flattener) {
+            assert!(
+                matches!(error, Error::InvalidSeparatorConfig { ref separator } if separator.contains(['␝', '␞', '␟'])),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+
+        let json2csv = Json2Csv::new(flattener).set_internal_sentinels(
+            "\u{1d}".to_string(),
+            "\u{1e}".to_string(),
+            "\u{1f}".to_string(),
+        );
+        let result = execute_with(r#"{"a": {"b": [1]}}"#, &json2csv);
+        assert_eq!(
+            result.output,
+            format!("a{key_separator}b{start}0{end}\n1\n")
+        );
+    }
+
+    #[test]
+    fn unused_reserved_characters_are_allowed() {
+        let result = execute(r#"{"a": {"b␞c␟": 1}}"#, &Flattener::new());
+        assert_eq!(result.output, "a.b␞c␟\n1\n");
+    }
+
+    #[test]
+    fn duplicated_keys_last_wins() {
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Plain)
+            .set_preserve_empty_arrays(true)
+            .set_preserve_empty_objects(true);
+        let result = execute(
+            r#"{"a": [1,2,3], "a": {"b": 2}, "c": 1, "c": 2}"#,
+            &flattener,
+        );
+        let expected = &["a.b,c", "2,2"];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::objects(r#"{"a": 1} {"b": {"c": 1, "c": 2}, "b": 3}"#)]
+    #[case::top_level_array(r#"[{"a": 1}, {"b": {"c": 1, "c": 2}, "b": 3}]"#)]
+    fn error_on_duplicate_keys(#[case] input: &str) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_reject_duplicate_keys(true);
+        let result =
+            json2csv.convert_from_reader(input.as_bytes(), csv::Writer::from_writer(Vec::new()));
+        assert!(
+            matches!(result, Err(Error::DuplicateKey { ref key }) if key == "b"),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn skip_objects_with_duplicate_keys() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_reject_duplicate_keys(true)
+            .set_skip_invalid(true);
+        let input = r#"{"a": 1} {"a": 2, "a": 3} {"a": 4}"#;
+        let mut output = Vec::new();
+        let report = json2csv
+            .convert_from_reader_with_report(
+                input.as_bytes(),
+                csv::Writer::from_writer(&mut output),
+            )
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), "a\n1\n4\n");
+        assert_eq!(report.rows, 2);
+        assert!(
+            matches!(report.skipped[..], [Error::DuplicateKey { ref key }] if key == "a"),
+            "Unexpected report: {:?}",
+            report
+        );
+    }
+
+    /// We use internal separators that later are replaced by the user provided ones.
+    /// This checks that the replacement does not make the headers and the data be in a different order.
+    #[test]
+    fn no_reordering_on_non_default_separators() {
+        let flattener = Flattener::new()
+            .set_key_separator("]")
+            .set_array_formatting(ArrayFormatting::Surrounded {
+                start: ".".to_string(),
+                end: "".to_string(),
+            })
+            .set_preserve_empty_arrays(true)
+            .set_preserve_empty_objects(true);
+        let result = execute(r#"{"a": [1,2,3]} {"a": {"b": 2}}"#, &flattener);
+        let expected = &["a.0,a.1,a.2,a]b", "1,2,3,", ",,,2"];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    /// An error must be reported when flattening makes two keys in an object look the same.
+    #[rstest]
+    #[case::in_one_object(r#"{"a": {"b": 1}, "a.b": 2}"#)]
+    #[case::in_different_objects(r#"{"a": {"b": 1}}{"a.b": 2}"#)]
+    fn error_on_collision(#[case] input: &str) {
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Plain)
+            .set_preserve_empty_arrays(false)
+            .set_preserve_empty_objects(false);
+        for err in execute_expect_err(input, &flattener) {
+            assert!(
+                matches!(err, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    /// An error must be reported when flattening makes two keys in an object look the same, even
+    /// when it's due to array formatting.
+    #[rstest]
+    #[case::in_one_object(r#"{"a[0]": 1, "a": [2]}"#, "[", "]")]
+    #[case::in_different_objects(r#"{"a[0]": 1} {"a": [2]}"#, "[", "]")]
+    fn error_on_collision_array_formatting(
+        #[case] input: &str,
+        #[case] start: &str,
+        #[case] end: &str,
+    ) {
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Surrounded {
+                start: start.to_string(),
+                end: end.to_string(),
+            })
+            .set_preserve_empty_arrays(false)
+            .set_preserve_empty_objects(false);
+        for err in execute_expect_err(input, &flattener) {
+            assert!(
+                matches!(err, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    /// In all those cases there are no headers after flattening the input, so the resulting CSV is
+    /// empty.
+    #[rstest]
+    #[case::empty_string("")]
+    #[case::empty_json_doc("{}")]
+    #[case::multiple_empty_json_docs("{}{}{}{}")]
+    #[case::empty_array(r#"{"a": []}"#)]
+    #[case::empty_obj(r#"{"b": {}}"#)]
+    #[case::empty_array_obj_and_json_doc(r#"{"a": []} {"b": {}} {}"#)]
+    fn empty_csv_when_no_headers(#[case] input: &str) {
+        let expected = "";
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Plain)
+            .set_preserve_empty_arrays(false)
+            .set_preserve_empty_objects(false);
+        let result = execute(input, &flattener);
+        assert_eq!(result.output, expected);
+    }
+
+    #[rstest]
+    #[case::empty_array(r#"{"a": []}"#)]
+    #[case::empty_array_extra_obj(r#"{"a": []} {} {}"#)]
+    #[case::empty_obj(r#"{"a": {}}"#)]
+    #[case::empty_obj_extra_obj(r#"{"a": {}} {}"#)]
+    fn preserved_empty(#[case] input: &str) {
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Plain)
+            .set_preserve_empty_arrays(true)
+            .set_preserve_empty_objects(true);
+        let result = execute(input, &flattener);
+
+        let mut expected = vec!["a"];
+
+        // Extend the vector with as many rows as objects has the input
+        expected.extend(vec![r#""""#; result.input.len()]);
+
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::empty_array(r#"{"a": [], "b": 3}"#, &["b", "3"])]
+    #[case::empty_array_extra_obj(r#"{"a": [], "b": 3} {} {}"#, &["b", "3", r#""""#, r#""""#])]
+    #[case::empty_obj(r#"{"a": {}, "b": 3}"#, &["b", "3"])]
+    #[case::empty_obj_extra_obj(r#"{"a": {}} {} {"b": 3} {}"#, &["b", r#""""#, r#""""#, "3", r#""""#])]
+    #[case::empty_obj_extra_obj(r#"{"a": {}} {} {"b": 3} {"c": 4}"#, &["b,c", ",", ",", "3,", ",4"])]
+    fn not_preserved_empty(#[case] input: &str, #[case] expected: &[&str]) {
+        let flattener = Flattener::new()
+            .set_key_separator(".")
+            .set_array_formatting(ArrayFormatting::Plain)
+            .set_preserve_empty_arrays(false)
+            .set_preserve_empty_objects(false);
+        let result = execute(input, &flattener);
+
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn trailer_stats() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_emit_trailer_stats(true);
+        let result = execute_with(
+            r#"{"a": 1, "b": {"c": null}} {"a": 2, "b": {"c": "x"}} {"a": 3}"#,
+            &json2csv,
+        );
+        let expected = &[
+            "a,b.c",
+            "1,",
+            "2,x",
+            "3,",
+            "# rows: 3",
+            "# columns: 2",
+            "# fill rate a: 100.00%",
+            "# fill rate b.c: 33.33%",
+        ];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::spaces(r#"{"user%20name": 1}"#, &["user name", "1"])]
+    #[case::nested(r#"{"a%41": {"b%2Fc": 1}}"#, &["aA.b/c", "1"])]
+    #[case::invalid_escape_kept(r#"{"100%": 1, "%zz": 2}"#, &["%zz,100%", "2,1"])]
+    fn percent_decoded_keys(#[case] input: &str, #[case] expected: &[&str]) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_key_decoder(KeyDecoder::PercentDecode);
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    /// Decoding a key can reveal the key separator, which can make it collide with a nested key.
+    #[rstest]
+    #[case::in_one_object(r#"{"user%2Ename": 1, "user": {"name": 2}}"#)]
+    #[case::in_different_objects(r#"{"user%2Ename": 1} {"user": {"name": 2}}"#)]
+    #[case::decoded_keys_equal(r#"{"a%2Eb": 1, "a.b": 2}"#)]
+    fn error_on_collision_after_decoding(#[case] input: &str) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_key_decoder(KeyDecoder::PercentDecode);
+        for err in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(err, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn error_on_invalid_utf8_after_decoding() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_key_decoder(KeyDecoder::PercentDecode);
+        for err in execute_with_expect_err(r#"{"a%FF": 1}"#, &json2csv) {
+            assert!(
+                matches!(err, Error::KeyDecoding { ref key } if key == "a%FF"),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn custom_key_decoder() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_key_decoder(KeyDecoder::custom(|key| key.to_uppercase()));
+        let result = execute_with(r#"{"a": {"b": 1}, "c": 2}"#, &json2csv);
+        assert_eq!(result.output, "A.B,C\n1,2\n");
+    }
+
+    fn json_file(content: &str) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+        file
+    }
+
+    #[test]
+    fn metadata_columns_from_files() {
+        let first = json_file(r#"{"a": 1} {"a": 2}"#);
+        let second = json_file(r#"{"b": {"c": 3}}"#);
+
+        let mut output = Vec::<u8>::new();
+        Json2Csv::new(Flattener::new())
+            .set_metadata_columns(MetadataColumns {
+                source: Some("__source_file".to_string()),
+                timestamp: Some("__ingested_at".to_string()),
+            })
+            .convert_from_files(
+                &[first.path(), second.path()],
+                csv::Writer::from_writer(&mut output),
+            )
+            .unwrap();
+
+        let mut reader = csv::Reader::from_reader(output.as_slice());
+        let headers = reader.headers().unwrap().clone();
+        assert_eq!(headers, vec!["__ingested_at", "__source_file", "a", "b.c"]);
+
+        let rows: Vec<_> = reader.records().map(Result::unwrap).collect();
+        let sources: Vec<_> = rows.iter().map(|row| row[1].to_string()).collect();
+        let first_path = first.path().display().to_string();
+        let second_path = second.path().display().to_string();
+        assert_eq!(sources, vec![first_path.clone(), first_path, second_path]);
+        assert_eq!((&rows[0][2], &rows[0][3]), ("1", ""));
+        assert_eq!((&rows[2][2], &rows[2][3]), ("", "3"));
+
+        // All the rows share the same timestamp
+        assert!(rows.iter().all(|row| row[0] == rows[0][0]));
+        assert!(rows[0][0].ends_with('Z'));
+    }
+
+    #[test]
+    fn files_with_settings() {
+        let first = json_file("{\"a\": 1}\n{\"a\": \n{\"b\": {\"c\": 2}}\n");
+        let second = json_file(r#"{"a": 3, "d": 4}"#);
+        let paths = [first.path(), second.path()];
+        let read = |path: &Path| std::fs::read(path).unwrap();
+
+        // The files are converted like the readers with their content
+        for json2csv in [
+            Json2Csv::new(Flattener::new())
+                .with_schema(Schema::new(vec!["d".to_string(), "a".to_string()]))
+                .set_skip_invalid(true),
+            Json2Csv::new(Flattener::new())
+                .set_output_shape(OutputShape::Long {
+                    with_types: false,
+                    skip_empty: false,
+                })
+                .set_skip_invalid(true),
+        ] {
+            let mut output = Vec::new();
+            let report = json2csv
+                .convert_from_files_with_report(&paths, csv::Writer::from_writer(&mut output))
+                .unwrap();
+            let mut expected = Vec::new();
+            let readers = paths.map(|path| (path.display().to_string(), read(path)));
+            let expected_report = json2csv
+                .convert_from_readers_with_report(
+                    readers
+                        .iter()
+                        .map(|(label, content)| (label.clone(), content.as_slice())),
+                    csv::Writer::from_writer(&mut expected),
+                )
+                .unwrap();
+            assert_eq!(output, expected);
+            assert_eq!(report.rows, expected_report.rows);
+            assert!(
+                matches!(report.skipped[..], [Error::Input { index: 0, .. }]),
+                "Unexpected report: {:?}",
+                report
+            );
+        }
+    }
+
+    #[test]
+    fn error_opening_file() {
+        let file = json_file(r#"{"a": 1}"#);
+        let missing = file.path().with_extension("missing");
+        let result = Json2Csv::new(Flattener::new()).convert_from_files(
+            &[file.path(), missing.as_path()],
+            csv::Writer::from_writer(std::io::sink()),
+        );
+        assert!(
+            matches!(
+                result,
+                Err(Error::Input { index: 1, ref source }) if matches!(**source, Error::InputOutput(_))
+            ),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn convert_from_reader_to_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.csv");
+        let rows = Json2Csv::new(Flattener::new())
+            .set_metadata_columns(MetadataColumns {
+                source: Some("source".to_string()),
+                timestamp: None,
+            })
+            .convert_from_reader_to_path(r#"{"a": 1} {"b": {"c": 2}}"#.as_bytes(), &path)
+            .unwrap();
+        assert_eq!(rows, 2);
+        let path_name = path.display();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            format!("a,b.c,source\n1,,{path_name}\n,2,{path_name}\n")
+        );
+    }
+
+    #[test]
+    fn error_on_metadata_column_clash() {
+        let file = json_file(r#"{"source": 1}"#);
+        let result = Json2Csv::new(Flattener::new())
+            .set_metadata_columns(MetadataColumns {
+                source: Some("source".to_string()),
+                timestamp: None,
+            })
+            .convert_from_files(&[file.path()], csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(
+                result,
+                Err(Error::Input { index: 0, ref source })
+                    if matches!(**source, Error::ColumnNameClash { ref name } if name == "source")
+            ),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn constant_columns() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .add_constant_column("batch".to_string(), "42".to_string())
+            .add_constant_column("env".to_string(), "prod".to_string());
+        let result = execute_with(r#"{"b": 1} {"a": {"c": 2}}"#, &json2csv);
+        assert_eq!(result.output, "a.c,b,batch,env\n,1,42,prod\n2,,42,prod\n");
+
+        let schema = Schema::new(vec!["env".to_string(), "b".to_string()]);
+        let result = execute_with(r#"{"b": 1}"#, &json2csv.with_schema(schema));
+        assert_eq!(result.output, "env,b\nprod,1\n");
+    }
+
+    #[test]
+    fn column_transforms() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .add_column_transform("country".to_string(), |value| {
+                value.as_str().unwrap_or_default().to_uppercase()
+            })
+            .add_column_transform("user.email".to_string(), |_| "<redacted>".to_string());
+        let input = r#"{"country": "es", "user": {"email": "a@b.c", "id": 1}} {"user": {"id": 2}}"#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(
+            result.output,
+            "country,user.email,user.id\nES,<redacted>,1\n,,2\n"
+        );
+
+        let result = execute_with(
+            input,
+            &json2csv.set_output_shape(OutputShape::Long {
+                with_types: true,
+                skip_empty: false,
+            }),
+        );
+        assert_eq!(
+            result.output,
+            "row_id,key,value,type\n\
+             0,country,ES,string\n\
+             0,user.email,<redacted>,string\n\
+             0,user.id,1,number\n\
+             1,user.id,2,number\n"
+        );
+    }
+
+    #[test]
+    fn error_on_constant_column_clash_with_schema() {
+        let schema = Schema::new(vec!["a".to_string(), "b".to_string()]);
+        let json2csv = Json2Csv::new(Flattener::new())
+            .add_constant_column("a".to_string(), "x".to_string())
+            .with_schema(schema);
+        for error in execute_with_expect_err(r#"{"b": 1} {"a": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::ColumnNameClash { ref name } if name == "a"),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+
+        let json2csv = Json2Csv::new(Flattener::new())
+            .add_constant_column("a".to_string(), "x".to_string())
+            .set_header_denylist(vec!["a".to_string()]);
+        let result = execute_with(r#"{"b": 1} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "b,a\n1,x\n,x\n");
+    }
+
+    #[test]
+    fn error_on_constant_column_clash() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).add_constant_column("a".to_string(), "x".to_string());
+        for error in execute_with_expect_err(r#"{"b": 1} {"a": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::ColumnNameClash { ref name } if name == "a"),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::from_zero(0, "__row,a.c,b\n0,,1\n1,2,\n")]
+    #[case::from_one(1, "__row,a.c,b\n1,,1\n2,2,\n")]
+    fn row_index_column(#[case] start: usize, #[case] expected: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_row_index_column(Some("__row".to_string()))
+            .set_row_index_start(start);
+        let result = execute_with(r#"{"b": 1} {"a": {"c": 2}}"#, &json2csv);
+        assert_eq!(result.output, expected);
+
+        let rows: Vec<_> = json2csv
+            .rows_from_reader(r#"{"b": 1} {"a": {"c": 2}}"#.as_bytes())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected: Vec<Vec<&str>> = expected
+            .lines()
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn row_index_column_after_sorting() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_row_index_column(Some("n".to_string()))
+            .set_sort_by_columns(vec![("a".to_string(), SortDir::Descending)]);
+        let result = execute_with(r#"{"a": 1} {"a": 3} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "n,a\n0,3\n1,2\n2,1\n");
+    }
+
+    #[rstest]
+    #[case::disabled(false, None, "a,b\n1,2\n1,2\n2,\n1,2\n2,\n")]
+    #[case::enabled(true, None, "a,b\n1,2\n2,\n")]
+    #[case::with_row_index(true, Some("n"), "n,a,b\n0,1,2\n1,2,\n")]
+    fn deduplicate_rows(
+        #[case] enabled: bool,
+        #[case] row_index_column: Option<&str>,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_row_index_column(row_index_column.map(str::to_string))
+            .set_deduplicate_rows(enabled);
+        let input = r#"{"a": 1, "b": 2} {"a": 1, "b": 2} {"a": 2} {"b": 2, "a": 1} {"a": 2}"#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected);
+
+        let rows: Vec<_> = json2csv
+            .rows_from_reader(input.as_bytes())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected: Vec<Vec<&str>> = expected
+            .lines()
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[test]
+    fn deduplicated_rows_count() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_deduplicate_rows(true)
+            .set_append_row_count(Some("# rows: {}".to_string()));
+        let result = execute_with(r#"{"a": 1} {"a": 1} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "a\n1\n2\n# rows: 2\n");
+    }
+
+    #[test]
+    fn error_on_row_index_column_clash() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_row_index_column(Some("a.b".to_string()));
+        for error in execute_with_expect_err(r#"{"c": 1} {"a": {"b": 2}}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::ColumnNameClash { ref name } if name == "a.b"),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::epoch(0, "1970-01-01T00:00:00Z")]
+    #[case::leap_day(951_827_696, "2000-02-29T12:34:56Z")]
+    #[case::end_of_year(1_672_531_199, "2022-12-31T23:59:59Z")]
+    fn utc_timestamps(#[case] secs: u64, #[case] expected: &str) {
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        assert_eq!(utc_timestamp(time), expected);
+    }
+
+    #[rstest]
+    #[case::array(r#"{"count": 2, "results": [{"a": 1}, {"a": 2}]} {"results": [{"b": 3}]}"#, "/results", &["a,b", "1,", "2,", ",3"])]
+    #[case::object(r#"{"data": {"result": {"a": 1}}} {"data": {"result": {"a": 2}}}"#, "/data/result", &["a", "1", "2"])]
+    #[case::empty_array(r#"{"results": []} {"results": [{"a": 1}]}"#, "/results", &["a", "1"])]
+    fn root_pointer(#[case] input: &str, #[case] pointer: &str, #[case] expected: &[&str]) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_root_pointer(Some(pointer.to_string()));
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::missing(r#"{"results": [{"a": 1}]} {"other": []}"#)]
+    #[case::scalar(r#"{"results": 3}"#)]
+    fn error_on_unresolved_root_pointer(#[case] input: &str) {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_root_pointer(Some("/results".to_string()));
+        for err in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(err, Error::RootPointer { ref pointer } if pointer == "/results"),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::top_level(r#"{"": 1, "a": 2}"#, ".", "[", "]", &["_empty_,a", "1,2"])]
+    #[case::nested(r#"{"a": {"": 1}, "": {"": 2}}"#, ".", "[", "]", &["_empty_._empty_,a._empty_", "2,1"])]
+    #[case::array(r#"{"": [1], "a": [{"": 2}]}"#, ".", "[", "]", &["_empty_[0],a[0]._empty_", "1,2"])]
+    #[case::array_multichar_separator(r#"{"": [1], "a": {"": 2}}"#, "::", "<", ">", &["_empty_<0>,a::_empty_", "1,2"])]
+    fn empty_keys_replaced(
+        #[case] input: &str,
+        #[case] key_separator: &str,
+        #[case] start: &str,
+        #[case] end: &str,
+        #[case] expected: &[&str],
+    ) {
+        let flattener = Flattener::new()
+            .set_key_separator(key_separator)
+            .set_array_formatting(ArrayFormatting::Surrounded {
+                start: start.to_string(),
+                end: end.to_string(),
+            });
+        let json2csv = Json2Csv::new(flattener)
+            .set_empty_key_handling(EmptyKey::Replace("_empty_".to_string()));
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::top_level(r#"{"": 1}"#, &[r#""""#, "1"])]
+    #[case::nested(r#"{"a": {"": 1}}"#, &["a.", "1"])]
+    #[case::array(r#"{"": [1]}"#, &[".0", "1"])]
+    fn empty_keys_kept(#[case] input: &str, #[case] expected: &[&str]) {
+        let result = execute(input, &Flattener::new());
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::top_level(r#"{"": 1}"#, "")]
+    #[case::nested(r#"{"a": {"b": {"": 1}}}"#, "a.b.")]
+    #[case::array(r#"{"": [1]}"#, ".0")]
+    fn error_on_empty_keys(#[case] input: &str, #[case] expected_key: &str) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_empty_key_handling(EmptyKey::Error);
+        for err in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(err, Error::EmptyKey { ref key } if key == expected_key),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    /// Replacing an empty key can make it collide with a key that was not empty.
+    #[test]
+    fn error_on_collision_after_replacing_empty_keys() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_empty_key_handling(EmptyKey::Replace("x".to_string()));
+        for err in execute_with_expect_err(r#"{"": {"b": 1}} {"x": {"b": 2}}"#, &json2csv) {
+            assert!(
+                matches!(err, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn error_on_deadline_exceeded() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_deadline(Some(Instant::now()));
+        for err in execute_with_expect_err(r#"{"a": 1} {"a": 2}"#, &json2csv) {
+            assert!(
+                matches!(
+                    err,
+                    Error::DeadlineExceeded {
+                        objects_processed: 0
+                    }
+                ),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn deadline_not_exceeded() {
+        let deadline = Instant::now() + std::time::Duration::from_secs(3600);
+        let json2csv = Json2Csv::new(Flattener::new()).set_deadline(Some(deadline));
+        let result = execute_with(r#"{"a": 1} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "a\n1\n2\n");
+    }
+
+    #[test]
+    fn transposed_string() {
+        let input = [
+            serde_json::json!({"a": 1, "b": {"c": "x,y"}}),
+            serde_json::json!({"a": 2}),
+        ];
+        let output = Json2Csv::new(Flattener::new())
+            .to_transposed_string_from_array(&input)
+            .unwrap();
+        assert_eq!(output, "a,1\nb.c,\"x,y\"\n\na,2\nb.c,\n");
+    }
+
+    #[rstest]
+    #[case::crlf(
+        RecordTerminator::Crlf,
+        "a,b.c\r\n1,\r\n,2\r\n",
+        "a,1\r\nb.c,\r\n\r\na,\r\nb.c,2\r\n"
+    )]
+    #[case::semicolon(RecordTerminator::Any(b';'), "a,b.c;1,;,2;", "a,1;b.c,;;a,;b.c,2;")]
+    fn record_terminator(
+        #[case] terminator: RecordTerminator,
+        #[case] expected: &str,
+        #[case] expected_transposed: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_terminator(terminator);
+        let objects = [
+            serde_json::json!({"a": 1}),
+            serde_json::json!({"b": {"c": 2}}),
+        ];
+        assert_eq!(
+            json2csv.convert_from_array_to_string(&objects).unwrap(),
+            expected
+        );
+        assert_eq!(
+            json2csv.to_transposed_string_from_array(&objects).unwrap(),
+            expected_transposed
+        );
+    }
+
+    #[rstest]
+    #[case::always(
+        QuoteStyle::Always,
+        "\"a\",\"b\",\"c\"\n\"1\",\"\",\"\"\n\"\",\"\",\"x\"\n"
+    )]
+    #[case::non_numeric(
+        QuoteStyle::NonNumeric,
+        "\"a\",\"b\",\"c\"\n1,\"\",\"\"\n\"\",\"\",\"x\"\n"
+    )]
+    #[case::necessary(QuoteStyle::Necessary, "a,b,c\n1,,\n,,x\n")]
+    fn quote_style(#[case] style: QuoteStyle, #[case] expected: &str) {
+        let flattener = Flattener::new().set_preserve_empty_arrays(true);
+        let json2csv = Json2Csv::new(flattener).set_quote_style(style);
+        let objects = [
+            serde_json::json!({"a": 1, "b": []}),
+            serde_json::json!({"b": [], "c": "x"}),
+        ];
+        assert_eq!(
+            json2csv.convert_from_array_to_string(&objects).unwrap(),
+            expected
+        );
+
+        let mut output = Vec::new();
+        json2csv
+            .convert_from_array(&objects, json2csv.csv_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), expected);
+    }
+
+    #[test]
+    fn crlf_terminator_end_to_end() {
+        let input = "{\"a\": \"x\\ny\"}\n{\"b\": {\"c\": 2}}\n";
+        let json2csv = Json2Csv::new(Flattener::new()).set_terminator(RecordTerminator::Crlf);
+        let expected = "a,b.c\r\n\"x\ny\",\r\n,2\r\n";
+
+        let output = json2csv
+            .convert_from_reader_to_string(input.as_bytes())
+            .unwrap();
+        assert_eq!(output, expected);
+
+        let objects: Vec<Value> = Deserializer::from_str(input)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let output = json2csv.convert_from_array_to_string(&objects).unwrap();
+        assert_eq!(output, expected);
+
+        let output = json2csv
+            .clone()
+            .with_schema(Schema::new(vec!["b.c".to_string(), "a".to_string()]))
+            .convert_from_reader_to_string(input.as_bytes())
+            .unwrap();
+        assert_eq!(output, "b.c,a\r\n,\"x\ny\"\r\n2,\r\n");
+
+        let output = json2csv
+            .clone()
+            .set_output_shape(OutputShape::Long {
+                with_types: false,
+                skip_empty: false,
+            })
+            .convert_from_reader_to_string(input.as_bytes())
+            .unwrap();
+        assert_eq!(output, "row_id,key,value\r\n0,a,\"x\ny\"\r\n1,b.c,2\r\n");
+
+        let output = json2csv
+            .set_append_row_count(Some("# {} rows".to_string()))
+            .set_emit_trailer_stats(true)
+            .convert_from_array_to_string(&objects)
+            .unwrap();
+        let trailer = "# 2 rows\r\n# rows: 2\r\n# columns: 2\r\n\
+                       # fill rate a: 50.00%\r\n# fill rate b.c: 50.00%\r\n";
+        assert_eq!(output, expected.to_string() + trailer);
+    }
+
+    #[test]
+    fn transposed_string_without_headers() {
+        let input = [serde_json::json!({}), serde_json::json!({"a": []})];
+        let output = Json2Csv::new(Flattener::new())
+            .to_transposed_string_from_array(&input)
+            .unwrap();
+        assert_eq!(output, "");
+    }
+
+    #[test]
+    fn error_on_invalid_utf8_in_transposed_string() {
+        let result = Json2Csv::new(Flattener::new())
+            .set_quote(0xff)
+            .to_transposed_string_from_array(&[serde_json::json!({"a": ","})]);
+        assert!(
+            matches!(result, Err(Error::InputOutput(ref err)) if err.kind() == std::io::ErrorKind::InvalidData),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[rstest]
+    #[case::over(r#"{"a": 1} {"a": 2} {"a": 3}"#, 2)]
+    #[case::zero(r#"{"a": 1}"#, 0)]
+    fn error_on_too_many_objects(#[case] input: &str, #[case] max_objects: usize) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_max_objects(Some(max_objects));
+        for err in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(err, Error::TooManyObjects { limit } if limit == max_objects),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn max_objects_not_exceeded() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_max_objects(Some(2));
+        let result = execute_with(r#"{"a": 1} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "a\n1\n2\n");
+    }
+
+    /// The rest of the stream must not be read once the limit is exceeded, so the invalid JSON at
+    /// the end is never parsed.
+    #[test]
+    fn max_objects_stops_reading() {
+        let input = r#"{"a": 1} {"a": 2} {"a": 3} {"a": "#;
+        let result = Json2Csv::new(Flattener::new())
+            .set_max_objects(Some(2))
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(result, Err(Error::TooManyObjects { limit: 2 })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[rstest]
+    #[case::all_headers(Some(2), false, "a,b\n1,\n2,\n")]
+    #[case::written_headers(Some(2), true, "a\n1\n2\n")]
+    #[case::zero(Some(0), false, "a,b\n")]
+    #[case::zero_written_headers(Some(0), true, "")]
+    #[case::over(Some(5), true, "a,b\n1,\n2,\n,3\n")]
+    #[case::unlimited(None, true, "a,b\n1,\n2,\n,3\n")]
+    fn max_rows(
+        #[case] limit: Option<usize>,
+        #[case] headers_from_written_rows: bool,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_max_rows(limit)
+            .set_headers_from_written_rows(headers_from_written_rows);
+        let input = r#"{"a": 1} {"a": 2} {"b": 3}"#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected);
+
+        let rows: Vec<_> = json2csv
+            .rows_from_reader(input.as_bytes())
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let expected: Vec<Vec<&str>> = expected
+            .lines()
+            .map(|line| line.split(',').collect())
+            .collect();
+        assert_eq!(rows, expected);
+    }
+
+    #[rstest]
+    #[case::documents(r#"{"a": 1} {"a": 2} {"a": "#)]
+    #[case::array(r#"[{"a": 1}, {"a": 2}, {"a": "#)]
+    fn max_rows_stops_reading(#[case] input: &str) {
+        let mut output = Vec::<u8>::new();
+        let rows = Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(2))
+            .set_headers_from_written_rows(true)
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(std::str::from_utf8(&output).unwrap(), "a\n1\n2\n");
+    }
+
+    #[test]
+    fn max_rows_after_sorting_and_deduplicating() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(2))
+            .set_deduplicate_rows(true)
+            .set_sort_by_columns(vec![("a".to_string(), SortDir::Descending)])
+            .set_append_row_count(Some("# rows: {}".to_string()));
+        let result = execute_with(r#"{"a": 1} {"a": 3} {"a": 3} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "a\n3\n2\n# rows: 2\n");
+    }
+
+    #[test]
+    fn max_rows_in_long_shape() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(1))
+            .set_output_shape(OutputShape::Long {
+                with_types: false,
+                skip_empty: false,
+            });
+        let result = execute_with(r#"{"a": 1, "b": 2} {"a": 3}"#, &json2csv);
+        assert_eq!(result.output, "row_id,key,value\n0,a,1\n0,b,2\n");
+    }
+
+    #[test]
+    fn schema_from_array_and_reader() {
+        let input = r#"{"b": 1, "a": {"c": 2}} {"d": [3]}"#;
+        let json2csv = Json2Csv::new(Flattener::new());
+        let documents: Vec<Value> = Deserializer::from_str(input)
+            .into_iter::<Value>()
+            .map(Result::unwrap)
+            .collect();
+
+        let schema = json2csv.headers_for_array(&documents).unwrap();
+        assert_eq!(schema.headers(), ["a.c", "b", "d.0"]);
+        assert_eq!(
+            json2csv.headers_for_reader(input.as_bytes()).unwrap(),
+            schema
+        );
+    }
+
+    #[test]
+    fn convert_with_schema() {
+        let schema = Schema::new(vec!["b".to_string(), "a.c".to_string(), "e".to_string()]);
+        let json2csv = Json2Csv::new(Flattener::new()).with_schema(schema);
+        let result = execute_with(r#"{"b": 1, "a": {"c": 2}} {"d": [3], "b": 4}"#, &json2csv);
+        assert_eq!(result.output, "b,a.c,e\n1,2,\n4,,\n");
+    }
+
+    #[test]
+    fn convert_with_renamed_schema_headers() {
+        let schema = Schema::new(vec!["b".to_string(), "a.c".to_string()])
+            .rename("a.c".to_string(), "c".to_string());
+        let json2csv = Json2Csv::new(Flattener::new())
+            .with_schema(schema)
+            .set_emit_trailer_stats(true);
+        let input = r#"{"b": 1, "a": {"c": 2}} {"b": 4}"#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(
+            result.output,
+            "b,c\n1,2\n4,\n# rows: 2\n# columns: 2\n# fill rate b: 100.00%\n# fill rate c: 50.00%\n"
+        );
+
+        let documents = [serde_json::json!({"a": {"c": 2}})];
+        let rows = json2csv.rows_from_array(&documents).unwrap();
+        let rows = rows.collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(rows, [vec!["b", "c"], vec!["", "2"]]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_schema() {
+        let schema = Schema::new(vec!["a".to_string(), "b".to_string()])
+            .rename("b".to_string(), "B".to_string());
+        let serialized = serde_json::to_string(&schema).unwrap();
+        assert_eq!(serde_json::from_str::<Schema>(&serialized).unwrap(), schema);
+
+        let deserialized: Schema = serde_json::from_str(r#"{"headers": ["a"]}"#).unwrap();
+        assert_eq!(deserialized, Schema::new(vec!["a".to_string()]));
+    }
+
+    #[test]
+    fn schema_reused_across_inputs() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let schema = json2csv
+            .headers_for_reader(r#"{"a": 1} {"b": 2}"#.as_bytes())
+            .unwrap();
+        let result = execute_with(r#"{"b": 3}"#, &json2csv.with_schema(schema));
+        assert_eq!(result.output, "a,b\n,3\n");
+    }
+
+    #[rstest]
+    #[case::in_one_object(r#"{"a": {"b": 1}, "a.b": 2}"#)]
+    #[case::in_different_objects(r#"{"a": {"b": 1}}{"a.b": 2}"#)]
+    fn error_on_collision_with_schema(#[case] input: &str) {
+        let schema = Schema::new(vec!["a.b".to_string()]);
+        let json2csv = Json2Csv::new(Flattener::new()).with_schema(schema);
+        for err in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(err, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {}",
+                err
+            );
+        }
+    }
+
+    #[test]
+    fn custom_quote() {
+        let input = [serde_json::json!({"a": "it's, ok", "b": "plain", "c": "\"double\""})];
+        let json2csv = Json2Csv::new(Flattener::new()).set_quote(b'\'');
+
+        let mut output = Vec::<u8>::new();
+        let csv_writer = json2csv.csv_writer(&mut output);
+        json2csv
+            .clone()
+            .convert_from_array(&input, csv_writer)
+            .unwrap();
+        assert_eq!(
+            str::from_utf8(&output).unwrap(),
+            "a,b,c\n'it''s, ok',plain,\"double\"\n"
+        );
+
+        let transposed = json2csv.to_transposed_string_from_array(&input).unwrap();
+        assert_eq!(transposed, "a,'it''s, ok'\nb,plain\nc,\"double\"\n");
+    }
+
+    #[test]
+    fn append_row_count() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_append_row_count(Some("# rows: {}".to_string()));
+        let result = execute_with(r#"{"a": 1} {"a": 2} {"b": 3}"#, &json2csv);
+        assert_eq!(result.output, "a,b\n1,\n2,\n,3\n# rows: 3\n");
+    }
+
+    #[test]
+    fn append_row_count_and_trailer_stats() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_append_row_count(Some("TOTAL {} ROWS".to_string()))
+            .set_emit_trailer_stats(true);
+        let result = execute_with(r#"{"a": 1} {"a": 2}"#, &json2csv);
+        let expected = &[
+            "a",
+            "1",
+            "2",
+            "TOTAL 2 ROWS",
+            "# rows: 2",
+            "# columns: 1",
+            "# fill rate a: 100.00%",
+        ];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn nested_arrays_to_dir() {
+        let input = r#"
+            {"orders": [{"id": 1, "items": [2, 3]}], "customers": [{"id": 4}], "date": "a"}
+            {"orders": [{"id": 5}, {"id": 6}], "empty": [], "total": {"amount": 7}}
+        "#;
+        let dir = tempfile::tempdir().unwrap();
+        let paths = Json2Csv::new(Flattener::new())
+            .convert_nested_arrays_to_dir(input.as_bytes(), dir.path())
+            .unwrap();
+
+        let names: Vec<_> = paths
+            .iter()
+            .map(|path| path.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, ["customers.csv", "orders.csv", "scalars.csv"]);
+
+        let read = |name: &str| std::fs::read_to_string(dir.path().join(name)).unwrap();
+        assert_eq!(read("customers.csv"), "id\n4\n");
+        assert_eq!(read("orders.csv"), "id,items.0,items.1\n1,2,3\n5,,\n6,,\n");
+        assert_eq!(read("scalars.csv"), "date,total.amount\na,\n,7\n");
+    }
+
+    #[rstest]
+    #[case::array(
+        Json2Csv::new(Flattener::new()),
+        r#"[{"a": [{"b": 1}]}, {"a": [{"b": 2}]}]"#
+    )]
+    #[case::skip_invalid(
+        Json2Csv::new(Flattener::new()).set_skip_invalid(true),
+        "{\"a\": [{\"b\": 1}]}\n{\"a\": ]\n{\"a\": [{\"b\": 2}]}"
+    )]
+    fn nested_arrays_to_dir_like_reader(#[case] json2csv: Json2Csv, #[case] input: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = json2csv
+            .convert_nested_arrays_to_dir(input.as_bytes(), dir.path())
+            .unwrap();
+        assert_eq!(paths, [dir.path().join("a.csv")]);
+        assert_eq!(std::fs::read_to_string(&paths[0]).unwrap(), "b\n1\n2\n");
+    }
+
+    #[test]
+    fn error_on_too_many_objects_when_splitting_nested_arrays() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Json2Csv::new(Flattener::new())
+            .set_max_objects(Some(1))
+            .convert_nested_arrays_to_dir(r#"{"a": [{"b": 1}]} {"c": 2}"#.as_bytes(), dir.path());
+        assert!(
+            matches!(result, Err(Error::TooManyObjects { limit: 1 })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[rstest]
+    #[case::reserved(r#"{"scalars": [{"a": 1}]}"#, "scalars")]
+    #[case::path(r#"{"../a": [{"a": 1}]}"#, "../a")]
+    #[case::empty(r#"{"": [{"a": 1}]}"#, "")]
+    fn error_on_invalid_table_name(#[case] input: &str, #[case] expected_name: &str) {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Json2Csv::new(Flattener::new())
+            .convert_nested_arrays_to_dir(input.as_bytes(), dir.path());
+        assert!(
+            matches!(result, Err(Error::InvalidTableName { ref name }) if name == expected_name),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn error_on_scalar_when_splitting_nested_arrays() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Json2Csv::new(Flattener::new())
+            .convert_nested_arrays_to_dir(r#"{"a": [{"b": 1}]} 2"#.as_bytes(), dir.path());
+        assert!(
+            matches!(result, Err(Error::NotAnObject { index: 1 })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[rstest]
+    #[case::flatten(MixedArray::Flatten, "a.0,a.1.b,a.2,c.0\n1,2,3,4\n")]
+    #[case::json_string(MixedArray::JsonString, "a,c.0\n\"[1,{\"\"b\"\":2},3]\",4\n")]
+    fn mixed_arrays(#[case] mixed_array_handling: MixedArray, #[case] expected: &str) {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_mixed_array_handling(mixed_array_handling);
+        let result = execute_with(r#"{"a": [1, {"b": 2}, 3], "c": [4]}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[rstest]
+    #[case::flatten(ArrayOfObjectsMode::Flatten, "items.0.x,items.1.x,n.0\n1,2,3\n,,\n")]
+    #[case::json_string(
+        ArrayOfObjectsMode::JsonString,
+        "items,n.0\n\"[{\"\"x\"\":1},{\"\"x\"\":2}]\",3\n,\n"
+    )]
+    fn arrays_of_objects(
+        #[case] array_of_objects_mode: ArrayOfObjectsMode,
+        #[case] expected: &str,
+    ) {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_array_of_objects_mode(array_of_objects_mode);
+        let result = execute_with(
+            r#"{"items": [{"x": 1}, {"x": 2}], "n": [3]} {"items": []}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn nested_mixed_array_as_json_string() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_mixed_array_handling(MixedArray::JsonString);
+        let result = execute_with(r#"{"a": [{"b": [{"c": 1}, null]}, {"d": 2}]}"#, &json2csv);
+        assert_eq!(result.output, "a.0.b,a.1.d\n\"[{\"\"c\"\":1},null]\",2\n");
+    }
+
+    #[test]
+    fn error_on_mixed_array() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_mixed_array_handling(MixedArray::Error);
+        let input = r#"{"a": [{"b": 1}]} {"a": [{"b/c": [true, {"d": 2}]}]}"#;
+        for error in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(error, Error::MixedArray { ref path } if path == "/a/0/b~1c"),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn sort_by_columns() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_sort_by_columns(vec![
+            ("a".to_string(), SortDir::Ascending),
+            ("b".to_string(), SortDir::Descending),
+        ]);
+        let input = r#"
+            {"a": 10, "b": "x", "c": 1}
+            {"a": 2, "b": "y", "c": 2}
+            {"b": "z", "c": 3}
+            {"a": 2, "b": "z", "c": 4}
+            {"a": 10, "b": "x", "c": 5}
+            {"a": 2, "c": 6}
+            {"a": 2.5, "b": "x", "c": 7}
+        "#;
+        let result = execute_with(input, &json2csv);
+        let expected = &[
+            "a,b,c", "2,z,4", "2,y,2", "2,,6", "2.5,x,7", "10,x,1", "10,x,5", ",z,3",
+        ];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::ascending(SortDir::Ascending, "a\ntrue\n1\nb\n\"\"\n")]
+    #[case::descending(SortDir::Descending, "a\nb\n1\ntrue\n\"\"\n")]
+    fn sort_by_column_with_mixed_types(#[case] dir: SortDir, #[case] expected: &str) {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_sort_by_columns(vec![("a".to_string(), dir)]);
+        let result = execute_with(r#"{"a": "b"} {"a": null} {"a": 1} {"a": true}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn sort_by_columns_with_schema_and_trailer() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .with_schema(Schema::new(vec!["a".to_string()]))
+            .set_sort_by_columns(vec![("a".to_string(), SortDir::Descending)])
+            .set_append_row_count(Some("# rows: {}".to_string()));
+        let result = execute_with(r#"{"a": 1} {"a": 3} {"a": 2}"#, &json2csv);
+        assert_eq!(result.output, "a\n3\n2\n1\n# rows: 3\n");
+    }
+
+    #[test]
+    fn render_parsing_error_context() {
+        let input = "{\"a\": 1}\n{\"b\": 2, \"c\": x, \"d\": 3, \"e\": 4, \"f\": 5, \"g\": 6}\n";
+        let csv_writer = csv::WriterBuilder::new().from_writer(vec![]);
+        let error = Json2Csv::new(Flattener::new())
+            .convert_from_reader(input.as_bytes(), csv_writer)
+            .unwrap_err();
+        let expected = &[
+            "line 2, column 15:",
+            r#"{"b": 2, "c": x, "d": 3, "e": 4, "f"#,
+            "              ^",
+        ];
+        assert_eq!(error.render_context(input).unwrap(), expected.join("\n"));
+    }
+
+    #[test]
+    fn render_jsonl_error_context() {
+        let input = "{\"a\": 1}\n\n{\"b\": x}\n";
+        let csv_writer = csv::WriterBuilder::new().from_writer(vec![]);
+        let error = Json2Csv::new(Flattener::new())
+            .convert_from_jsonl_reader(input.as_bytes(), csv_writer)
+            .unwrap_err();
+        assert_eq!(
+            error.render_context(input).unwrap(),
+            "line 3, column 7:\n{\"b\": x}\n      ^"
+        );
+    }
+
+    #[rstest]
+    #[case::multibyte("{\"ñ\": ñ}", "line 1, column 8:\n{\"ñ\": ñ}\n      ^")]
+    #[case::eof("{\"a\": ", "line 1, column 6:\n{\"a\": \n     ^")]
+    fn render_parsing_error_context_edge_cases(#[case] input: &str, #[case] expected: &str) {
+        let csv_writer = csv::WriterBuilder::new().from_writer(vec![]);
+        let error = Json2Csv::new(Flattener::new())
+            .convert_from_reader(input.as_bytes(), csv_writer)
+            .unwrap_err();
+        assert_eq!(error.render_context(input).unwrap(), expected);
+    }
+
+    #[test]
+    fn no_context_for_errors_without_position() {
+        let error = Error::TooManyObjects { limit: 1 };
+        assert_eq!(error.render_context("{}"), None);
+    }
+
+    #[rstest]
+    #[case::union(HeaderStrategy::Union, "a,b.c,b.d,e\n1,2,,\n3,4,5,\n6,7,,8\n")]
+    #[case::intersection(HeaderStrategy::Intersection, "a,b.c\n1,2\n3,4\n6,7\n")]
+    fn union_and_intersection_headers(
+        #[case] header_strategy: HeaderStrategy,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_strategy(header_strategy);
+        let input = r#"
+            {"a": 1, "b": {"c": 2}}
+            {"a": 3, "b": {"c": 4, "d": 5}}
+            {"e": 8, "b": {"c": 7}, "a": 6}
+        "#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[rstest]
+    #[case::not_preserved(false, "b\n1\n2\n")]
+    #[case::preserved(true, "a,b\n,1\n,2\n")]
+    fn intersection_with_empty_containers(#[case] preserve: bool, #[case] expected: &str) {
+        let flattener = Flattener::new()
+            .set_preserve_empty_arrays(preserve)
+            .set_preserve_empty_objects(preserve);
+        let json2csv = Json2Csv::new(flattener).set_header_strategy(HeaderStrategy::Intersection);
+        let result = execute_with(r#"{"a": [], "b": 1} {"a": {}, "b": 2}"#, &json2csv);
+        assert_eq!(result.output, expected);
+
+        // A non empty array is flattened into other keys
+        let result = execute_with(r#"{"a": [], "b": 1} {"a": [5], "b": 3}"#, &json2csv);
+        assert_eq!(result.output, "b\n1\n3\n");
+    }
+
+    #[rstest]
+    #[case::union(HeaderStrategy::Union, &[])]
+    #[case::intersection(HeaderStrategy::Intersection, &["b.d", "e"])]
+    fn columns_dropped_by_the_header_strategy(
+        #[case] header_strategy: HeaderStrategy,
+        #[case] expected: &[&str],
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_strategy(header_strategy);
+        let input = r#"
+            {"a": 1, "b": {"c": 2}, "e": null}
+            {"a": 3, "b": {"c": 4, "d": 5}}
+            {"e": 8, "b": {"c": 7}, "a": 6}
+        "#;
+        let report = json2csv
+            .convert_from_reader_with_report(
+                input.as_bytes(),
+                csv::Writer::from_writer(std::io::sink()),
+            )
+            .unwrap();
+        assert_eq!(report.rows, 3);
+        assert_eq!(report.dropped_columns, expected);
+    }
+
+    #[test]
+    fn intersection_without_common_keys() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_header_strategy(HeaderStrategy::Intersection);
+        let result = execute_with(r#"{"a": 1} {"b": 2}"#, &json2csv);
+        assert_eq!(result.output, "");
+    }
+
+    #[test]
+    fn intersection_still_detects_collisions() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_header_strategy(HeaderStrategy::Intersection);
+        for error in execute_with_expect_err(r#"{"a": {"b": 1}} {"a.b": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::long(
+        false,
+        false,
+        "row_id,key,value\n0,a,1\n0,b.c,x\n0,d,\n2,a,true\n2,e,\n"
+    )]
+    #[case::long_skip_empty(false, true, "row_id,key,value\n0,a,1\n0,b.c,x\n2,a,true\n")]
+    #[case::typed(
+        true,
+        false,
+        "row_id,key,value,type\n0,a,1,number\n0,b.c,x,string\n0,d,,null\n2,a,true,bool\n2,e,,string\n"
+    )]
+    #[case::typed_skip_empty(
+        true,
+        true,
+        "row_id,key,value,type\n0,a,1,number\n0,b.c,x,string\n2,a,true,bool\n"
+    )]
+    fn long_output_shape(
+        #[case] with_types: bool,
+        #[case] skip_empty: bool,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+            with_types,
+            skip_empty,
+        });
+        let input = r#"{"a": 1, "b": {"c": "x"}, "d": null} {} {"a": true, "e": ""}"#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn long_output_shape_with_preserved_empty_containers() {
+        let flattener = Flattener::new()
+            .set_preserve_empty_arrays(true)
+            .set_preserve_empty_objects(true);
+        let json2csv = Json2Csv::new(flattener).set_output_shape(OutputShape::Long {
+            with_types: true,
+            skip_empty: false,
+        });
+        let result = execute_with(r#"{"a": [], "b": {}}"#, &json2csv);
+        assert_eq!(
+            result.output,
+            "row_id,key,value,type\n0,a,,array\n0,b,,object\n"
+        );
+    }
+
+    #[test]
+    fn long_output_shape_detects_collisions() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+            with_types: false,
+            skip_empty: false,
+        });
+        for error in execute_with_expect_err(r#"{"a": {"b": 1}} {"a.b": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn validate_objects() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_object_validator(|obj| {
+            match obj.get("age").and_then(Value::as_u64) {
+                Some(age) if age < 150 => Ok(()),
+                _ => Err("Missing or invalid age".to_string()),
+            }
+        });
+        let result = execute_with(r#"{"age": 1} {"age": 2, "name": "b"}"#, &json2csv);
+        assert_eq!(result.output, "age,name\n1,\n2,b\n");
+
+        let input = r#"{"age": 1} {"age": 2} {"age": 300} {"age": "a"}"#;
+        for error in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(
+                    error,
+                    Error::ValidationFailed { object_index: 2, ref message }
+                        if message == "Missing or invalid age"
+                ),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn errors_with_the_position_in_the_input() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_scalar_element_handling(ScalarElement::Skip)
+            .set_object_validator(|obj| match obj.get("a") {
+                Some(_) => Ok(()),
+                None => Err("Missing a".to_string()),
+            });
+        for error in execute_with_expect_err(r#"1 {"a": 1} 2 {"b": 2}"#, &json2csv) {
+            assert!(
+                matches!(
+                    error,
+                    Error::ValidationFailed {
+                        object_index: 3,
+                        ..
+                    }
+                ),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_scalar_element_handling(ScalarElement::Skip)
+            .set_max_fields_per_object(Some(1));
+        for error in execute_with_expect_err(r#"1 {"a": 1} {"a": 1, "b": 2}"#, &json2csv) {
+            assert!(
+                matches!(
+                    error,
+                    Error::TooManyFieldsInObject {
+                        object_index: 2,
+                        ..
+                    }
+                ),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+
+        let json2csv = Json2Csv::new(Flattener::new()).set_deduplicate_rows(true);
+        for error in execute_with_expect_err(r#"{"a": 1} {"a": 1} 2"#, &json2csv) {
+            assert!(
+                matches!(error, Error::NotAnObject { index: 2 }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn validate_objects_with_root_pointer() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_root_pointer(Some("/rows".to_string()))
+            .set_object_validator(|obj| match obj.get("a") {
+                Some(_) => Ok(()),
+                None => Err("Missing a".to_string()),
+            });
+        let input = r#"{"rows": [{"a": 1}, {"a": 2}]} {"rows": [{"a": 3}, {"b": 4}]}"#;
+        for error in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(
+                    error,
+                    Error::ValidationFailed {
+                        object_index: 3,
+                        ..
+                    }
+                ),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn convert_to_fmt_write() {
+        let objects: Vec<Value> =
+            serde_json::from_str(r#"[{"a": "ñ", "b": {"c": 1}}, {"a": "€"}]"#).unwrap();
+        let mut output = String::from("CSV:\n");
+        Json2Csv::new(Flattener::new())
+            .convert_from_array_to_fmt(&objects, &mut output)
+            .unwrap();
+        assert_eq!(output, "CSV:\na,b.c\nñ,1\n€,\n");
+    }
+
+    #[test]
+    fn fmt_writer_keeps_characters_split_between_writes() {
+        let mut output = String::new();
+        let mut adapter = FmtWriter::new(&mut output);
+        let bytes = "a€b".as_bytes();
+        for byte in bytes {
+            adapter.write_all(std::slice::from_ref(byte)).unwrap();
+        }
+        adapter.finish().unwrap();
+        assert_eq!(output, "a€b");
+    }
+
+    #[rstest]
+    #[case::invalid(&[b'a', 0xFF, b'b'])]
+    #[case::incomplete(&[b'a', 0xE2, 0x82])]
+    fn fmt_writer_rejects_invalid_utf8(#[case] bytes: &[u8]) {
+        let mut output = String::new();
+        let mut adapter = FmtWriter::new(&mut output);
+        let result = adapter.write_all(bytes).and_then(|()| adapter.finish());
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn flattened_objects_preserve_key_order() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let obj = serde_json::from_str(r#"{"b": 1, "a": {"d": 2, "c": 3}}"#).unwrap();
+        let map = json2csv.flatten_object(&obj).unwrap().map;
+        assert_eq!(map.keys().collect::<Vec<_>>(), ["b", "a.d", "a.c"]);
+
+        let result = execute(r#"{"b": 1, "a": {"d": 2, "c": 3}}"#, &Flattener::new());
+        assert_eq!(result.output, "a.c,a.d,b\n3,2,1\n");
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[rstest]
+    #[case::big_integer("123456789012345678901234567890")]
+    #[case::big_negative_integer("-98765432109876543210")]
+    #[case::precise_decimal("0.10000000000000000000000000001")]
+    #[case::exponent("1.5e+400")]
+    fn numbers_are_kept_as_they_are(#[case] number: &str) {
+        let input = format!(r#"{{"id": {number}, "nested": {{"amount": [{number}]}}}}"#);
+        let result = execute(&input, &Flattener::new());
+        assert_eq!(
+            result.output,
+            format!("id,nested.amount.0\n{number},{number}\n")
+        );
+    }
+
+    #[cfg(feature = "arbitrary_precision")]
+    #[test]
+    fn error_on_top_level_number_with_arbitrary_precision() {
+        let errors = execute_expect_err("12345678901234567890123", &Flattener::new());
+        for error in errors {
+            assert!(
+                matches!(error, Error::NotAnObject { index: 0 }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn header_diff() {
+        let old: Vec<Value> =
+            serde_json::from_str(r#"[{"a": 1, "b": {"c": 2}}, {"d": 3}]"#).unwrap();
+        let new: Vec<Value> =
+            serde_json::from_str(r#"[{"a": 1, "b": {"e": 2}}, {"d": 3, "f": [4]}]"#).unwrap();
+        let diff = Json2Csv::new(Flattener::new())
+            .header_diff(&old, &new)
+            .unwrap();
+        assert_eq!(
+            diff,
+            HeaderDiff {
+                added: vec!["b.e".to_string(), "f.0".to_string()],
+                removed: vec!["b.c".to_string()],
+                common: vec!["a".to_string(), "d".to_string()],
+            }
+        );
+
+        let diff = Json2Csv::new(Flattener::new())
+            .header_diff(&old, &old)
+            .unwrap();
+        assert!(diff.added.is_empty() && diff.removed.is_empty());
+        assert_eq!(diff.common, ["a", "b.c", "d"]);
+    }
+
+    #[rstest]
+    #[case::doubled(true, None, r#""a ""b"" c""#)]
+    #[case::backslash(false, None, r#""a \"b\" c""#)]
+    #[case::custom_escape(false, Some(b'!'), r#""a !"b!" c""#)]
+    fn quote_escaping(#[case] double_quote: bool, #[case] escape: Option<u8>, #[case] cell: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_double_quote(double_quote)
+            .set_escape(escape);
+        let objects = vec![serde_json::json!({"x": r#"a "b" c"#})];
+        let mut output = String::new();
+        json2csv
+            .convert_from_array_to_fmt(&objects, &mut output)
+            .unwrap();
+        assert_eq!(output, format!("x\n{}\n", cell));
+    }
+
+    #[test]
+    fn convert_from_seekable_starts_at_current_position() {
+        let mut file = json_file(r#"{"skipped": 0} {"a": 1} {"b": {"c": 2}}"#);
+        file.seek(SeekFrom::Start(14)).unwrap();
+
+        let mut output = Vec::<u8>::new();
+        Json2Csv::new(Flattener::new())
+            .convert_from_seekable(file, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), "a,b.c\n1,\n,2\n");
+    }
+
+    #[rstest]
+    #[case::skip(ScalarElement::Skip, "a,b\n1,\n,2\n")]
+    #[case::empty_row(ScalarElement::EmptyRow, "a,b\n1,\n,\n,\n,2\n")]
+    fn scalar_elements(#[case] scalar_element_handling: ScalarElement, #[case] expected: &str) {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_scalar_element_handling(scalar_element_handling);
+        let result = execute_with(r#"{"a": 1} null "x" {"b": 2}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn error_on_scalar_elements() {
+        for error in execute_expect_err(r#"{"a": 1} {"a": 2} 3 {"b": 2}"#, &Flattener::new()) {
+            assert!(
+                matches!(error, Error::NotAnObject { index: 2 }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::string(r#"{"a": 1} "x" {"b": 2}"#)]
+    #[case::array(r#"{"a": 1} [2] {"b": 2}"#)]
+    #[case::top_level_array(r#"[{"a": 1}, 2, {"b": 2}]"#)]
+    fn error_on_value_that_is_not_an_object(#[case] input: &str) {
+        let result = Json2Csv::new(Flattener::new())
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(Vec::new()));
+        assert!(
+            matches!(result, Err(Error::NotAnObject { index: 1 })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn error_on_array_element_that_is_not_an_object() {
+        let objects = [
+            serde_json::json!({"a": 1}),
+            serde_json::json!("x"),
+            serde_json::json!({"b": 2}),
+        ];
+        for json2csv in [
+            Json2Csv::new(Flattener::new()),
+            Json2Csv::new(Flattener::new()).set_spill_to_temp_file(true),
+        ] {
+            let result =
+                json2csv.convert_from_array(&objects, csv::Writer::from_writer(Vec::new()));
+            assert!(
+                matches!(result, Err(Error::NotAnObject { index: 1 })),
+                "Unexpected result: {:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn keep_temp_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("flattened.json");
+        std::fs::write(&path, "old content that is longer than the new one").unwrap();
+
+        let json2csv = Json2Csv::new(Flattener::new()).set_keep_temp_file(Some(path.clone()));
+        let result = execute_with(r#"{"a": 1} {"b": {"c": 2}}"#, &json2csv);
+        assert_eq!(result.output, "a,b.c\n1,\n,2\n");
+
+        let kept: Vec<Value> = Deserializer::from_str(&std::fs::read_to_string(&path).unwrap())
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(
+            kept,
+            [serde_json::json!({"a": 1}), serde_json::json!({"b.c": 2})]
+        );
+    }
+
+    #[rstest]
+    #[case::empty("")]
+    #[case::whitespace("  \n ")]
+    #[case::skipped_scalars("null 1")]
+    fn schema_headers_on_empty_input(#[case] input: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .with_schema(Schema::new(vec!["a".to_string(), "b.c".to_string()]))
+            .set_scalar_element_handling(ScalarElement::Skip);
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, "a,b.c\n");
+    }
+
+    #[test]
+    fn coalesce_keys() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_coalesce(vec![
+            (
+                "email".to_string(),
+                vec!["email".to_string(), "contact.email_address".to_string()],
+            ),
+            (
+                "id".to_string(),
+                vec!["uid".to_string(), "user_id".to_string()],
+            ),
+        ]);
+        let input = r#"
+            {"email": "a@a", "contact": {"email_address": "b@b"}, "uid": 1}
+            {"email": "", "contact": {"email_address": "c@c", "phone": 2}, "user_id": 3}
+            {"contact": {"email_address": "d@d"}, "uid": null, "user_id": 4}
+            {"name": "e", "uid": null}
+            {"name": "f"}
+        "#;
+        let result = execute_with(input, &json2csv);
+        let expected = &[
+            "contact.phone,email,id,name",
+            ",a@a,1,",
+            "2,c@c,3,",
+            ",d@d,4,",
+            ",,,e",
+            ",,,f",
+        ];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn coalesce_into_existing_key() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_coalesce(vec![("a".to_string(), vec!["b".to_string()])]);
+        for error in execute_with_expect_err(r#"{"a": 1, "b": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::ColumnNameClash { ref name } if name == "a"),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[cfg(feature = "zip")]
+    #[test]
+    fn convert_to_zip_entries() {
+        let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+        let first: Vec<Value> = serde_json::from_str(r#"[{"a": 1}, {"b": {"c": 2}}]"#).unwrap();
+        let second: Vec<Value> = serde_json::from_str(r#"[{"d": "x"}]"#).unwrap();
+        Json2Csv::new(Flattener::new())
+            .convert_from_array_to_zip(&first, &mut zip, "first.csv")
+            .unwrap();
+        Json2Csv::new(Flattener::new())
+            .convert_from_array_to_zip(&second, &mut zip, "second.csv")
+            .unwrap();
+        let archive = zip.finish().unwrap();
+
+        let mut archive = zip::ZipArchive::new(archive).unwrap();
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        assert_eq!(read("first.csv"), "a,b.c\n1,\n,2\n");
+        assert_eq!(read("second.csv"), "d\nx\n");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[rstest]
+    #[case::objects(r#"{"a": 1} {"b": {"c": 2}}"#)]
+    #[case::values_split_in_chunks(&r#"{"a": "x"}"#.repeat(2000))]
+    #[case::empty("")]
+    #[case::no_headers("{} {}")]
+    #[case::document_split_in_chunks(&format!(r#"{{"a": "{}"}}"#, "x".repeat(100_000)))]
+    fn convert_from_async_reader(
+        #[case] input: &str,
+        #[values(
+            Json2Csv::new(Flattener::new()),
+            Json2Csv::new(Flattener::new())
+                .set_sort_by_columns(vec![("a".to_string(), SortDir::Ascending)])
+                .set_append_row_count(Some("# {}".to_string())),
+            Json2Csv::new(Flattener::new())
+                .with_schema(Schema::new(vec!["b.c".to_string(), "a".to_string()])),
+            Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+                with_types: true,
+                skip_empty: false,
+            })
+        )]
+        json2csv: Json2Csv,
+    ) {
+        let mut expected = Vec::new();
+        let expected_rows = json2csv
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(&mut expected))
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        let rows = runtime
+            .block_on(json2csv.convert_from_async_reader(input.as_bytes(), &mut output))
+            .unwrap();
+        assert_eq!(output, expected);
+        assert_eq!(rows, expected_rows);
+    }
+
+    #[cfg(feature = "tokio")]
+    #[rstest]
+    #[case::skip_invalid(
+        Json2Csv::new(Flattener::new()).set_skip_invalid(true),
+        "{\"a\": 1}\n{\"a\": \n{\"b\": 2} {\n{\"a\": 3} }\n{\"a\": 4}\n{"
+    )]
+    #[case::skip_repeated_keys(
+        Json2Csv::new(Flattener::new())
+            .set_reject_duplicate_keys(true)
+            .set_skip_invalid(true),
+        r#"{"a": 1, "a": 2} {"a": 3}"#
+    )]
+    #[case::max_rows_stops_reading(
+        Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(2))
+            .set_headers_from_written_rows(true),
+        &format!(r#"{{"a": 1}} {{"a": 2}} {} {{"#, r#"{"b": 3}"#.repeat(2000))
+    )]
+    fn convert_from_async_reader_with_settings(#[case] json2csv: Json2Csv, #[case] input: &str) {
+        let mut expected = Vec::new();
+        json2csv
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(&mut expected))
+            .unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let mut output = Vec::new();
+        runtime
+            .block_on(json2csv.convert_from_async_reader(input.as_bytes(), &mut output))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            String::from_utf8(expected).unwrap()
+        );
+    }
+
+    #[cfg(feature = "tokio")]
+    #[rstest]
+    #[case::invalid_json(
+        r#"{"a": 1} {"#,
+        "Parsing JSON failed: EOF while parsing an object at line 1 column 2"
+    )]
+    #[case::split_scalar(
+        r#"{"a": 1} 12"#,
+        "The value at position 1 of the input is not an object"
+    )]
+    fn convert_from_async_reader_errors(#[case] input: &str, #[case] expected: &str) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let result = runtime.block_on(
+            Json2Csv::new(Flattener::new())
+                .convert_from_async_reader(input.as_bytes(), tokio::io::sink()),
+        );
+        assert_eq!(result.unwrap_err().to_string(), expected);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn convert_gzip() {
+        let input = r#"{"a": 1} {"b": {"c": 2}}"#;
+        let mut compressed = Vec::new();
+        let rows = Json2Csv::new(Flattener::new())
+            .convert_from_reader_to_gzip(input.as_bytes(), &mut compressed)
+            .unwrap();
+        assert_eq!(rows, 2);
+
+        let mut output = String::new();
+        flate2::read::GzDecoder::new(compressed.as_slice())
+            .read_to_string(&mut output)
+            .unwrap();
+        assert_eq!(
+            output,
+            "a,b.c
+1,
+,2
+"
+        );
+
+        // Compressed JSON, in two gzip members
+        let mut compressed = Vec::new();
+        for part in [r#"{"a": 1}"#, r#" {"b": {"c": 2}}"#] {
+            let mut encoder =
+                flate2::write::GzEncoder::new(&mut compressed, flate2::Compression::fast());
+            encoder.write_all(part.as_bytes()).unwrap();
+            encoder.finish().unwrap();
+        }
+        let mut output = Vec::new();
+        Json2Csv::new(Flattener::new())
+            .convert_from_gzip_reader(compressed.as_slice(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(
+            str::from_utf8(&output).unwrap(),
+            "a,b.c
+1,
+,2
+"
+        );
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn convert_invalid_gzip() {
+        let result = Json2Csv::new(Flattener::new()).convert_from_gzip_reader(
+            r#"{"a": 1}"#.as_bytes(),
+            csv::Writer::from_writer(std::io::sink()),
+        );
+        assert!(
+            matches!(result, Err(Error::InputOutput(_))),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    fn configured_json2csv() -> Json2Csv {
+        let flattener = Flattener::new()
+            .set_key_separator("/")
+            .set_array_formatting(ArrayFormatting::Surrounded {
+                start: "[".to_string(),
+                end: "]".to_string(),
+            })
+            .set_preserve_empty_arrays(true);
+        Json2Csv::new(flattener)
+            .set_key_decoder(KeyDecoder::PercentDecode)
+            .set_empty_key_handling(EmptyKey::Replace("_".to_string()))
+            .set_max_objects(Some(10))
+            .set_quote(b'\'')
+            .set_sort_by_columns(vec![("a".to_string(), SortDir::Descending)])
+            .set_header_strategy(HeaderStrategy::Intersection)
+            .set_coalesce(vec![("a".to_string(), vec!["b".to_string()])])
+    }
+
+    #[test]
+    fn replay_conversion_plan() {
+        let json2csv = configured_json2csv();
+        let plan = json2csv.to_plan().unwrap();
+        assert_eq!(
+            Json2Csv::from_plan(plan.clone())
+                .unwrap()
+                .to_plan()
+                .unwrap(),
+            plan
+        );
+
+        let input = r#"{"a": 1, "c": [1]} {"b": 2, "c": []}"#;
+        let result = execute_with(input, &json2csv);
+        let replayed = execute_with(input, &Json2Csv::from_plan(plan).unwrap());
+        assert_eq!(result.output, replayed.output);
+        assert_eq!(result.output, "a\n2\n1\n");
+    }
+
+    #[rstest]
+    #[case::header_order(
+        Json2Csv::new(Flattener::new()).set_header_order(HeaderOrder::custom(|a, b| a.cmp(b))),
+        "header_order"
+    )]
+    #[case::key_decoder(
+        Json2Csv::new(Flattener::new()).set_key_decoder(KeyDecoder::custom(|key| key.to_string())),
+        "key_decoder"
+    )]
+    #[case::object_validator(
+        Json2Csv::new(Flattener::new()).set_object_validator(|_| Ok(())),
+        "object_validator"
+    )]
+    #[case::column_transforms(
+        Json2Csv::new(Flattener::new()).add_column_transform("a".to_string(), |_| String::new()),
+        "column_transforms"
+    )]
+    #[case::missing_value_fn(
+        Json2Csv::new(Flattener::new()).set_missing_value_fn(|_| String::new()),
+        "missing_value_fn"
+    )]
+    fn plans_can_not_record_functions(#[case] json2csv: Json2Csv, #[case] expected: &str) {
+        let result = json2csv.to_plan();
+        assert!(
+            matches!(result, Err(Error::NotRecordable { ref setting }) if setting == expected),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_conversion_plan() {
+        let plan = configured_json2csv().to_plan().unwrap();
+        let serialized = serde_json::to_string(&plan).unwrap();
+        let deserialized: ConversionPlan = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized, plan);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn deserialize_conversion_plan_with_missing_settings() {
+        let deserialized: ConversionPlan = serde_json::from_str(r#"{"max_rows": 5}"#).unwrap();
+        let expected = Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(5))
+            .to_plan()
+            .unwrap();
+        assert_eq!(deserialized, expected);
+        assert_eq!(deserialized.version, ConversionPlan::VERSION);
+    }
+
+    #[test]
+    fn error_on_newer_conversion_plan() {
+        let plan = ConversionPlan {
+            version: ConversionPlan::VERSION + 1,
+            ..ConversionPlan::default()
+        };
+        let result = Json2Csv::from_plan(plan);
+        assert!(
+            matches!(result, Err(Error::UnsupportedPlanVersion { version }) if version == ConversionPlan::VERSION + 1),
+            "Unexpected result: {:?}",
+            result.map(|_| ())
+        );
+    }
+
+    #[test]
+    fn max_fields_per_object() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_max_fields_per_object(Some(101));
+        let array = |len: usize| serde_json::to_string(&vec![0; len]).unwrap();
+
+        let input = format!(r#"{{"a": 1, "b": {}}}"#, array(100));
+        let result = execute_with(&input, &json2csv);
+        assert_eq!(result.output.lines().count(), 2);
+
+        let input = format!(r#"{{"a": 1}} {{"a": 1, "b": {}}}"#, array(1000));
+        for error in execute_with_expect_err(&input, &json2csv) {
+            assert!(
+                matches!(
+                    error,
+                    Error::TooManyFieldsInObject {
+                        object_index: 1,
+                        count: 1001
+                    }
+                ),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn max_columns() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_max_columns(Some(3));
+        let result = execute_with(r#"{"a": 1} {"b": [1, 2]}"#, &json2csv);
+        assert_eq!(result.output, "a,b.0,b.1\n1,,\n,1,2\n");
+
+        for error in execute_with_expect_err(r#"{"a": 1} {"b": [1, 2, 3]}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::TooManyColumns { count: 4, limit: 3 }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn data_dictionary() {
+        let objects: Vec<Value> = serde_json::from_str(
+            r#"[
+                {"a": 1, "b": {"c": "x|y"}, "d": null, "e": true},
+                {"a": 2.5, "e": "yes"},
+                {"a": 3, "b": {"c": "z"}, "f": "line\nbreak"},
+                {}
+            ]"#,
+        )
+        .unwrap();
+        let mut output = Vec::new();
+        Json2Csv::new(Flattener::new())
+            .write_data_dictionary_from_array(&objects, &mut output)
+            .unwrap();
+        let expected = &[
+            "| Column | Type | Fill rate | Example |",
+            "| --- | --- | --- | --- |",
+            "| a | number | 75.00% | 1 |",
+            r"| b.c | string | 50.00% | x\|y |",
+            "| d | empty | 0.00% |  |",
+            "| e | mixed | 50.00% | true |",
+            "| f | string | 25.00% | line break |",
+        ];
+        assert_eq!(str::from_utf8(&output).unwrap(), expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::as_is(CaseStyle::AsIs, "HTTPStatus.0,first name,user_name.Last-Name")]
+    #[case::snake(CaseStyle::Snake, "first_name,http_status.0,user_name.last_name")]
+    #[case::camel(CaseStyle::Camel, "firstName,httpStatus.0,userName.lastName")]
+    #[case::kebab(CaseStyle::Kebab, "first-name,http-status.0,user-name.last-name")]
+    #[case::lower(CaseStyle::Lower, "first name,httpstatus.0,user_name.last-name")]
+    #[case::upper(CaseStyle::Upper, "FIRST NAME,HTTPSTATUS.0,USER_NAME.LAST-NAME")]
+    fn header_case_styles(#[case] header_case_style: CaseStyle, #[case] expected_headers: &str) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_case_style(header_case_style);
+        let result = execute_with(
+            r#"{"user_name": {"Last-Name": "a"}, "first name": "b", "HTTPStatus": [200]}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output.lines().next().unwrap(), expected_headers);
+    }
+
+    #[test]
+    fn header_case_style_keeps_surrounded_array_formatting() {
+        let flattener = Flattener::new()
+            .set_key_separator("::")
+            .set_array_formatting(ArrayFormatting::Surrounded {
+                start: "[".to_string(),
+                end: "]".to_string(),
+            });
+        let json2csv = Json2Csv::new(flattener).set_header_case_style(CaseStyle::Snake);
+        let result = execute_with(r#"{"userList": [{"item2Name": 1}]}"#, &json2csv);
+        assert_eq!(result.output, "user_list[0]::item2_name\n1\n");
+    }
+
+    #[rstest]
+    #[case::same_object(r#"{"userName": 1, "user_name": 2}"#)]
+    #[case::different_objects(r#"{"userName": 1} {"user_name": 2}"#)]
+    fn header_case_style_collisions(#[case] input: &str) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_case_style(CaseStyle::Snake);
+        for error in execute_with_expect_err(input, &json2csv) {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn lowercase_header_collisions() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_case_style(CaseStyle::Lower);
+        for error in
+            execute_with_expect_err(r#"{"UserId": 1} {"userId": 2} {"userid": 3}"#, &json2csv)
+        {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { ref keys } if keys == &["userid"]),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::present(true, "a,b\n1,\n2,3\n")]
+    #[case::absent(false, "a\n1\n2\n")]
+    fn null_counts_as_present_with_intersection(
+        #[case] null_counts_as_present: bool,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_header_strategy(HeaderStrategy::Intersection)
+            .set_null_counts_as_present(null_counts_as_present);
+        let result = execute_with(r#"{"a": 1, "b": null} {"a": 2, "b": 3}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[rstest]
+    #[case::alphabetical(HeaderOrder::Alphabetical, "a,b.c,m,z\n,,,1\n2,,3,\n4,5,,\n")]
+    #[case::first_seen(HeaderOrder::FirstSeen, "z,a,m,b.c\n1,,,\n,2,3,\n,4,,5\n")]
+    fn alphabetical_and_first_seen_header_order(
+        #[case] header_order: HeaderOrder,
+        #[case] expected: &str,
+    ) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_order(header_order);
+        let result = execute_with(
+            r#"{"z": 1} {"a": 2, "m": 3} {"b": {"c": 5}, "a": 4}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn first_seen_header_order_with_intersection() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_header_order(HeaderOrder::FirstSeen)
+            .set_header_strategy(HeaderStrategy::Intersection);
+        let result = execute_with(r#"{"z": 1, "y": 0} {"x": 2} {"x": 3, "z": 4}"#, &json2csv);
+        assert_eq!(result.output, "");
+        let result = execute_with(r#"{"z": 1} {"x": 2, "z": 3} {"x": 4, "z": 5}"#, &json2csv);
+        assert_eq!(result.output, "z\n1\n3\n5\n");
+    }
+
+    #[test]
+    fn first_seen_header_order_detects_collisions() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_order(HeaderOrder::FirstSeen);
+        for error in execute_with_expect_err(r#"{"a": {"b": 1}} {"a.b": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[cfg(feature = "preserve_order")]
+    #[test]
+    fn first_seen_header_order_within_objects() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_order(HeaderOrder::FirstSeen);
+        let result = execute_with(
+            r#"{"b": 1, "a": {"d": 2, "c": 3}} {"e": 4, "b": 5}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output, "b,a.d,a.c,e\n1,2,3,\n5,,,4\n");
+    }
+
+    #[rstest]
+    #[case::simple(r#"{"b": 1} {"a": {"c": [2]}}"#, &["a.c.0", "b"])]
+    #[case::empty("", &[])]
+    fn headers_without_converting(#[case] input: &str, #[case] expected: &[&str]) {
+        let flattener = Flattener::new().set_key_separator("__");
+        let json2csv = Json2Csv::new(flattener.clone());
+        let expected: Vec<String> = expected.iter().map(|h| h.replace('.', "__")).collect();
+
+        let from_reader = json2csv.headers_from_reader(input.as_bytes()).unwrap();
+        let result = execute(input, &flattener);
+        let from_array = json2csv.headers_from_array(&result.input).unwrap();
+
+        assert_eq!(from_reader, expected);
+        assert_eq!(from_array, expected);
+        if !expected.is_empty() {
+            assert_eq!(result.output.lines().next().unwrap(), expected.join(","));
+        }
+    }
+
+    #[rstest]
+    #[case::default(None, "a,b,c\n1,,\n,2,\n")]
+    #[case::null(Some("null"), "a,b,c\n1,null,\n,2,null\n")]
+    #[case::postgres(Some(r"\N"), "a,b,c\n1,\\N,\n,2,\\N\n")]
+    fn null_representation(#[case] representation: Option<&str>, #[case] expected: &str) {
+        let mut json2csv = Json2Csv::new(Flattener::new());
+        if let Some(representation) = representation {
+            json2csv = json2csv.set_null_representation(representation.to_string());
+        }
+        let result = execute_with(r#"{"a": 1, "b": null} {"b": 2, "c": null}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[rstest]
+    #[case::disabled(false, "a,b,c\nnull,,\n,x,null\n")]
+    #[case::enabled(true, "a,b,c\nnull,null,\n,x,null\n")]
+    fn empty_string_as_null(#[case] enabled: bool, #[case] expected: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_null_representation("null".to_string())
+            .set_empty_string_as_null(enabled);
+        let result = execute_with(r#"{"a": null, "b": ""} {"b": "x", "c": null}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn null_representation_in_long_shape() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_null_representation("NULL".to_string())
+            .set_output_shape(OutputShape::Long {
+                with_types: true,
+                skip_empty: false,
+            });
+        let result = execute_with(r#"{"a": null}"#, &json2csv);
+        assert_eq!(result.output, "row_id,key,value,type\n0,a,NULL,null\n");
+    }
+
+    #[test]
+    fn missing_values_by_header() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_missing_representation("<missing>".to_string())
+            .set_missing_value_fn(|header| match header {
+                "count" => "0".to_string(),
+                "active" => "false".to_string(),
+                _ => String::new(),
+            });
+        let input = r#"{"count": 3, "name": "a"} {"active": true} {"name": null}"#;
+        let result = execute_with(input, &json2csv);
+        let expected = &["active,count,name", "false,3,a", "true,0,", "false,0,"];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[test]
+    fn missing_and_empty_container_representations() {
+        let flattener = Flattener::new()
+            .set_preserve_empty_arrays(true)
+            .set_preserve_empty_objects(true);
+        let json2csv = Json2Csv::new(flattener)
+            .set_missing_representation("<missing>".to_string())
+            .set_empty_container_representation("<empty>".to_string())
+            .set_null_representation("<null>".to_string());
+        let input = r#"{"a": [], "b": null, "c": ""} {"a": {}, "d": 1}"#;
+        let result = execute_with(input, &json2csv);
+        let expected = &[
+            "a,b,c,d",
+            "<empty>,<null>,,<missing>",
+            "<empty>,<missing>,<missing>,1",
+        ];
+        assert_eq!(result.output, expected.join("\n") + "\n");
+    }
+
+    #[rstest]
+    #[case::blank(ComplexValueMode::Blank, ["<empty>", "<empty>", "<empty>"])]
+    #[case::json_string(ComplexValueMode::JsonString, ["[1,2]", r#"{"a":true}"#, "<empty>"])]
+    fn complex_values(#[case] complex_value_mode: ComplexValueMode, #[case] expected: [&str; 3]) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_complex_value_mode(complex_value_mode)
+            .set_empty_container_representation("<empty>".to_string());
+        let values = [
+            serde_json::json!([1, 2]),
+            serde_json::json!({"a": true}),
+            serde_json::json!([]),
+        ];
+        let cells = values.map(|value| json2csv.format_cell(value));
+        assert_eq!(cells, expected);
+    }
+
+    #[rstest]
+    #[case::empty("", 0)]
+    #[case::some_rows(r#"{"a": 1} {"b": 2} {"a": 3}"#, 3)]
+    fn number_of_rows_written(#[case] input: &str, #[case] expected: usize) {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let objects: Vec<Value> = Deserializer::from_str(input)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let sink = || csv::Writer::from_writer(std::io::sink());
+
+        let rows = json2csv.clone().convert_from_array(&objects, sink());
+        assert_eq!(rows.unwrap(), expected);
+        let rows = json2csv
+            .clone()
+            .convert_from_reader(input.as_bytes(), sink());
+        assert_eq!(rows.unwrap(), expected);
+        let rows = json2csv
+            .clone()
+            .with_schema(Schema::new(vec!["a".to_string()]))
+            .convert_from_reader(input.as_bytes(), sink());
+        assert_eq!(rows.unwrap(), expected);
+        let rows = json2csv
+            .set_append_row_count(Some("{}".to_string()))
+            .convert_from_seekable(std::io::Cursor::new(input), sink());
+        assert_eq!(rows.unwrap(), expected);
+    }
+
+    #[test]
+    fn number_of_rows_written_in_long_shape() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+            with_types: false,
+            skip_empty: true,
+        });
+        let rows = json2csv.convert_from_reader(
+            r#"{"a": 1, "b": {"c": 2}} {"a": null}"#.as_bytes(),
+            csv::Writer::from_writer(std::io::sink()),
+        );
+        assert_eq!(rows.unwrap(), 2);
+    }
+
+    #[test]
+    fn flatten_array() {
+        let flattener = Flattener::new().set_key_separator("__");
+        let json2csv = Json2Csv::new(flattener);
+        let objects = [
+            serde_json::json!({"a": {"b": 1}}),
+            serde_json::json!({"c": [true]}),
+        ];
+        let maps = json2csv.flatten_array(&objects).unwrap();
+        assert_eq!(
+            maps,
+            [
+                serde_json::json!({"a__b": 1}).as_object().unwrap().clone(),
+                serde_json::json!({"c__0": true})
+                    .as_object()
+                    .unwrap()
+                    .clone(),
+            ]
+        );
+
+        let objects = [
+            serde_json::json!({"a": {"b": 1}}),
+            serde_json::json!({"a__b": 2}),
+        ];
+        assert!(matches!(
+            json2csv.flatten_array(&objects),
+            Err(Error::FlattenedKeysCollision { .. })
+        ));
+    }
+
+    #[test]
+    fn dry_run() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let report = json2csv
+            .dry_run(r#"{"b": 1} {"a": {"c": [2]}} {}"#.as_bytes())
+            .unwrap();
+        assert_eq!(
+            report,
+            DryRunReport {
+                headers: vec!["a.c.0".to_string(), "b".to_string()],
+                rows: 3,
+                collisions: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn dry_run_reports_collisions() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let report = json2csv
+            .dry_run(r#"{"a": {"b": 1}} {"a.b": 2, "c": 3}"#.as_bytes())
+            .unwrap();
+        assert_eq!(report.headers, ["a.b", "c"]);
+        assert_eq!(report.rows, 2);
+        assert_eq!(report.collisions, ["a.b"]);
+    }
+
+    #[rstest]
+    #[case::compact(r#"[{"a": 1}, {"b": {"c": 2}}]"#)]
+    #[case::pretty("\n  [\n    {\"a\": 1},\n    {\"b\": {\"c\": 2}}\n  ]\n")]
+    fn top_level_array_in_reader(#[case] input: &str) {
+        let mut output = Vec::new();
+        let rows = Json2Csv::new(Flattener::new())
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(str::from_utf8(&output).unwrap(), "a,b.c\n1,\n,2\n");
+
+        let headers = Json2Csv::new(Flattener::new())
+            .headers_from_reader(input.as_bytes())
+            .unwrap();
+        assert_eq!(headers, ["a", "b.c"]);
+    }
+
+    #[rstest]
+    #[case::written(true, "a,b\n")]
+    #[case::not_written(false, "")]
+    fn empty_input_with_known_headers(#[case] headers_without_rows: bool, #[case] expected: &str) {
+        let headers = ["a".to_string(), "b".to_string()];
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_headers_without_rows(headers_without_rows);
+        for input in ["", "[]", "null"] {
+            let mut output = Vec::new();
+            let rows = json2csv
+                .clone()
+                .set_scalar_element_handling(ScalarElement::Skip)
+                .convert_from_reader_with_headers(
+                    input.as_bytes(),
+                    csv::Writer::from_writer(&mut output),
+                    &headers,
+                )
+                .unwrap();
+            assert_eq!(rows, 0);
+            assert_eq!(str::from_utf8(&output).unwrap(), expected);
+        }
+
+        let json2csv = json2csv.set_header_allowlist(Some(headers.to_vec()));
+        assert_eq!(
+            json2csv.convert_from_array_to_string(&[]).unwrap(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case::empty_array("[]", "")]
+    #[case::with_schema(r#"[{"a": 1}]"#, "a,b\n1,\n")]
+    fn top_level_array_in_reader_with_schema(#[case] input: &str, #[case] expected: &str) {
+        let schema = Schema::new(vec!["a".to_string(), "b".to_string()]);
+        let mut output = Vec::new();
+        Json2Csv::new(Flattener::new())
+            .with_schema(schema)
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        let expected = if expected.is_empty() {
+            "a,b\n"
+        } else {
+            expected
+        };
+        assert_eq!(str::from_utf8(&output).unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case::trailing_data(r#"[{"a": 1}] {"b": 2}"#)]
+    #[case::unterminated(r#"[{"a": 1}, "#)]
+    fn invalid_top_level_array_in_reader(#[case] input: &str) {
+        let result = Json2Csv::new(Flattener::new())
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(result, Err(Error::ParsingDocument { index: 1, .. })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn top_level_array_elements_stop_on_error() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_max_objects(Some(1));
+        let result = json2csv.convert_from_reader(
+            r#"[{"a": 1}, {"a": 2}, not json"#.as_bytes(),
+            csv::Writer::from_writer(std::io::sink()),
+        );
+        assert!(
+            matches!(result, Err(Error::TooManyObjects { limit: 1 })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    /// The collision error reports the keys that the colliding keys end looking like.
+    #[rstest]
+    #[case::one_key(r#"{"a": {"b": 1}, "a.b": 2}"#, &["a.b"])]
+    #[case::several_keys(r#"{"a": {"b": 1}} {"a.b": 2, "c.d": 3} {"c": {"d": 4}}"#, &["a.b", "c.d"])]
+    fn collision_reports_keys(#[case] input: &str, #[case] expected: &[&str]) {
+        let json2csv = Json2Csv::new(Flattener::new());
+        for error in execute_with_expect_err(input, &json2csv) {
+            match error {
+                Error::FlattenedKeysCollision { keys } => assert_eq!(keys, expected),
+                error => panic!("Unexpected error: {:?}", error),
+            }
+        }
+    }
+
+    #[test]
+    fn collision_message_lists_a_few_keys() {
+        let error = Error::FlattenedKeysCollision {
+            keys: ["a", "b", "c", "d", "e"].map(String::from).to_vec(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "Two objects have keys that should be different but end looking the same after \
+             flattening: 'a', 'b', 'c' and 2 more"
+        );
+    }
+
+    #[rstest]
+    #[case::empty("", "")]
+    #[case::objects(r#"{"a": 1} {"b": {"c": "x,y"}}"#, "a,b.c\n1,\n,\"x,y\"\n")]
+    fn convert_to_string(#[case] input: &str, #[case] expected: &str) {
+        let output = Json2Csv::new(Flattener::new())
+            .convert_from_reader_to_string(input.as_bytes())
+            .unwrap();
+        assert_eq!(output, expected);
+
+        let objects: Vec<Value> = Deserializer::from_str(input)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let output = Json2Csv::new(Flattener::new())
+            .convert_from_array_to_string(&objects)
+            .unwrap();
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn tsv_writer() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let mut output = Vec::new();
+        let objects = [serde_json::json!({"a": "x\ty", "b": {"c": "z,w"}})];
+        json2csv
+            .convert_from_array(&objects, json2csv.tsv_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), "a\tb.c\n\"x\ty\"\tz,w\n");
+    }
+
+    #[test]
+    fn convert_into_existing_string() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let mut output = "# exported\n".to_string();
+        let rows = json2csv
+            .convert_from_array_into_string(&[serde_json::json!({"a": 1})], &mut output)
+            .unwrap();
+        assert_eq!(rows, 1);
+        assert_eq!(output, "# exported\na\n1\n");
+
+        let result = json2csv
+            .set_quote(0xff)
+            .convert_from_array_into_string(&[serde_json::json!({"a": ","})], &mut output);
+        assert!(
+            matches!(result, Err(Error::InputOutput(ref err)) if err.kind() == std::io::ErrorKind::InvalidData),
+            "Unexpected result: {:?}",
+            result
+        );
+        assert_eq!(output, "# exported\na\n1\n");
+    }
+
+    /// The same object can be used for several conversions.
+    #[test]
+    fn reuse_for_several_conversions() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        for (input, expected) in [
+            (r#"{"a": 1}"#, "a\n1\n"),
+            (r#"{"b": {"c": 2}}"#, "b.c\n2\n"),
+        ] {
+            let mut output = Vec::new();
+            json2csv
+                .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(&mut output))
+                .unwrap();
+            assert_eq!(str::from_utf8(&output).unwrap(), expected);
+
+            let objects = [serde_json::from_str(input).unwrap()];
+            let mut output = Vec::new();
+            json2csv
+                .convert_from_array(&objects, csv::Writer::from_writer(&mut output))
+                .unwrap();
+            assert_eq!(str::from_utf8(&output).unwrap(), expected);
+        }
+    }
+
+    #[rstest]
+    #[case::projection(r#"{"a": 1, "b": {"c": 2}, "d": 3}"#, "d,b.c\n3,2\n")]
+    #[case::missing_keys(r#"{"a": 1} {"d": 2}"#, "d,b.c\n,\n2,\n")]
+    #[case::no_objects("", "d,b.c\n")]
+    #[case::collision_outside(r#"{"a": {"b": 1}, "a.b": 2, "d": 3}"#, "d,b.c\n3,\n")]
+    fn header_allowlist(#[case] input: &str, #[case] expected: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_header_allowlist(Some(vec!["d".to_string(), "b.c".to_string()]));
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn header_allowlist_collision_inside() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_header_allowlist(Some(vec!["a.b".to_string()]));
+        for error in execute_with_expect_err(r#"{"a": {"b": 1}} {"a.b": 2}"#, &json2csv) {
+            assert!(
+                matches!(error, Error::FlattenedKeysCollision { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[rstest]
+    #[case::exact(&["b.c"], "a,d.e,debug\n1,3,4\n")]
+    #[case::prefix(&["d*"], "a,b.c\n1,2\n")]
+    #[case::separator_prefix(&["d.*", "a"], "b.c,debug\n2,4\n")]
+    #[case::everything(&["*"], "")]
+    fn header_denylist(#[case] denylist: &[&str], #[case] expected: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_header_denylist(denylist.iter().map(ToString::to_string).collect());
+        let result = execute_with(
+            r#"{"a": 1, "b": {"c": 2}, "d": {"e": 3}, "debug": 4}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output, expected);
+    }
+
+    #[test]
+    fn header_denylist_drops_keys_before_collision_check() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_denylist(vec!["a.*".to_string()]);
+        let result = execute_with(r#"{"a": {"b": 1}, "c": 1} {"a.b": 2, "c": 2}"#, &json2csv);
+        assert_eq!(result.output, "c\n1\n2\n");
+    }
+
+    #[test]
+    fn header_denylist_wins_over_allowlist() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_header_allowlist(Some(vec!["a".to_string(), "b".to_string()]))
+            .set_header_denylist(vec!["b".to_string()]);
+        let result = execute_with(r#"{"a": 1, "b": 2}"#, &json2csv);
+        assert_eq!(result.output, "a,b\n1,\n");
+    }
+
+    #[rstest]
+    #[case::valid("{\"a\": 1}\n{\"b\": 2}\n", "a,b\n1,\n,2\n", 0)]
+    #[case::invalid_line("{\"a\": 1}\nnot json\n{\"b\": 2}\n", "a,b\n1,\n,2\n", 1)]
+    #[case::unterminated_object("{\"a\": 1\n{\"b\": 2}\n{\"c\": 3}", "c\n3\n", 1)]
+    #[case::several_invalid_lines("x\n{\"a\": 1}\n{,}\n]\n", "a\n1\n", 3)]
+    #[case::only_invalid("x", "", 1)]
+    fn skip_invalid(#[case] input: &str, #[case] expected: &str, #[case] skipped: usize) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_skip_invalid(true);
+        let mut output = Vec::new();
+        let report = json2csv
+            .convert_from_reader_with_report(
+                input.as_bytes(),
+                csv::Writer::from_writer(&mut output),
+            )
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), expected);
+        assert_eq!(report.rows, expected.lines().count().saturating_sub(1));
+        assert_eq!(report.skipped.len(), skipped);
+        for error in report.skipped {
+            assert!(
+                matches!(error, Error::ParsingDocument { .. }),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
+
+    #[test]
+    fn position_of_skipped_values() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_skip_invalid(true);
+        let report = json2csv
+            .convert_from_reader_with_report(
+                "x\n{\"a\": 1}\n{,}\n]\n".as_bytes(),
+                csv::Writer::from_writer(std::io::sink()),
+            )
+            .unwrap();
+        let positions: Vec<_> = report
+            .skipped
+            .iter()
+            .map(|error| match error {
+                Error::ParsingDocument { index, .. } => *index,
+                _ => panic!("Unexpected error: {:?}", error),
+            })
+            .collect();
+        assert_eq!(positions, [0, 2, 3]);
+    }
+
+    #[test]
+    fn error_sink() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_skip_invalid(true)
+            .set_reject_duplicate_keys(true);
+        let mut expected = Vec::new();
+        json2csv
+            .convert_from_reader(
+                "{\"a\": 1}\n{\"b\": 2}\n".as_bytes(),
+                csv::Writer::from_writer(&mut expected),
+            )
+            .unwrap();
+
+        let mut errors = Vec::new();
+        let mut output = Vec::new();
+        let rows = json2csv
+            .convert_from_reader_with_error_sink(
+                "{\"a\": 1}\n{,}\n{\"a\": 1, \"a\": 2}\n{\"b\": 2}\n".as_bytes(),
+                csv::Writer::from_writer(&mut output),
+                |index, error| errors.push((index, error)),
+            )
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(output, expected);
+        assert!(
+            matches!(
+                errors[..],
+                [
+                    (1, Error::ParsingDocument { index: 1, .. }),
+                    (2, Error::DuplicateKey { .. })
+                ]
+            ),
+            "Unexpected errors: {:?}",
+            errors
+        );
+    }
+
+    // `serde_json` does not accept non finite numbers, and its values can not hold them, so they
+    // can never reach the CSV
+    #[rstest]
+    #[case::nan(r#"{"a": NaN}"#)]
+    #[case::infinity(r#"{"a": Infinity}"#)]
+    #[case::negative_infinity(r#"{"a": -Infinity}"#)]
+    fn error_on_non_finite_numbers(#[case] input: &str) {
+        let result = Json2Csv::new(Flattener::new())
+            .convert_from_reader(input.as_bytes(), csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(result, Err(Error::ParsingDocument { index: 0, .. })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn jsonl() {
+        let input = "{\"a\": 1}\r\n\n  \n{\"b\": {\"c\": 2}}";
+        let mut output = Vec::new();
+        let rows = Json2Csv::new(Flattener::new())
+            .convert_from_jsonl_reader(input.as_bytes(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(str::from_utf8(&output).unwrap(), "a,b.c\n1,\n,2\n");
+    }
+
+    #[rstest]
+    #[case::invalid("{\"a\": 1}\n{\"a\": x}\n", 2)]
+    #[case::two_objects_in_a_line("{\"a\": 1}\n\n{\"a\": 2} {\"a\": 3}\n", 3)]
+    #[case::object_in_two_lines("{\"a\":\n1}\n", 1)]
+    fn error_on_invalid_jsonl_line(#[case] input: &str, #[case] expected_line: usize) {
+        let result = Json2Csv::new(Flattener::new())
+            .convert_from_jsonl_reader(input.as_bytes(), csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(result, Err(Error::ParsingLine { line, .. }) if line == expected_line),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn skip_invalid_jsonl_lines() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_skip_invalid(true)
+            .set_reject_duplicate_keys(true);
+        let input =
+            "{\"a\": 1}\n{\"a\": 2} x\n{\"a\": 3, \"a\": 4}\n{\"a\": \"\\u00f1\"\n{\"a\": 5}\n";
+        let mut errors = Vec::new();
+        let mut output = Vec::new();
+        let rows = json2csv
+            .convert_from_jsonl_reader_with_error_sink(
+                input.as_bytes(),
+                csv::Writer::from_writer(&mut output),
+                |line, error| errors.push((line, error)),
+            )
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(str::from_utf8(&output).unwrap(), "a\n1\n5\n");
+        assert!(
+            matches!(
+                errors[..],
+                [
+                    (2, Error::ParsingLine { line: 2, .. }),
+                    (3, Error::DuplicateKey { .. }),
+                    (4, Error::ParsingLine { line: 4, .. })
+                ]
+            ),
+            "Unexpected errors: {:?}",
+            errors
+        );
+    }
+
+    #[test]
+    fn single_object() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let mut output = Vec::new();
+        let rows = json2csv
+            .convert_from_object(
+                &serde_json::json!({"b": 1, "a": {"c": [2]}}),
+                csv::Writer::from_writer(&mut output),
+            )
+            .unwrap();
+        assert_eq!(rows, 1);
+        assert_eq!(str::from_utf8(&output).unwrap(), "a.c.0,b\n2,1\n");
+
+        let json2csv = json2csv.set_scalar_element_handling(ScalarElement::EmptyRow);
+        for value in [serde_json::json!([{"a": 1}]), serde_json::json!(1)] {
+            let result =
+                json2csv.convert_from_object(&value, csv::Writer::from_writer(std::io::sink()));
+            assert!(
+                matches!(result, Err(Error::NotAnObject { index: 0 })),
+                "Unexpected result: {:?}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn several_readers() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let readers = vec![
+            (
+                "first".to_string(),
+                r#"{"a": 1} {"b": {"c": 2}}"#.as_bytes(),
+            ),
+            ("empty".to_string(), "".as_bytes()),
+            ("array".to_string(), r#"[{"d": 3}, {"a": 4}]"#.as_bytes()),
+        ];
+        let mut output = Vec::new();
+        let rows = json2csv
+            .convert_from_readers(readers, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(rows, 4);
+        let expected = execute(
+            r#"{"a": 1} {"b": {"c": 2}} {"d": 3} {"a": 4}"#,
+            &Flattener::new(),
+        );
+        assert_eq!(str::from_utf8(&output).unwrap(), expected.output);
+
+        let readers = vec![
+            ("valid".to_string(), r#"{"a": 1}"#.as_bytes()),
+            ("invalid".to_string(), r#"{"a": x}"#.as_bytes()),
+        ];
+        let result =
+            json2csv.convert_from_readers(readers, csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(
+                &result,
+                Err(Error::Input { index: 1, source }) if matches!(**source, Error::ParsingDocument { index: 0, .. })
+            ),
+            "Unexpected result: {:?}",
+            result
+        );
+
+        let readers = vec![
+            ("nested".to_string(), r#"{"a": {"b": 1}}"#.as_bytes()),
+            ("flat".to_string(), r#"{"a.b": 2}"#.as_bytes()),
+        ];
+        let result =
+            json2csv.convert_from_readers(readers, csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(result, Err(Error::FlattenedKeysCollision { .. })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[rstest]
+    #[case::schema(
+        Json2Csv::new(Flattener::new()).with_schema(Schema::new(vec!["b".to_string(), "a".to_string()])),
+        "b,a\n,1\n2,\n"
+    )]
+    #[case::long(
+        Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+            with_types: false,
+            skip_empty: false,
+        }),
+        "row_id,key,value\n0,a,1\n1,b,2\n1,c,3\n"
+    )]
+    #[case::max_rows(
+        Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(1))
+            .set_headers_from_written_rows(true),
+        "a\n1\n"
+    )]
+    fn several_readers_with_settings(#[case] json2csv: Json2Csv, #[case] expected: &str) {
+        let readers = vec![
+            ("first".to_string(), r#"{"a": 1}"#.as_bytes()),
+            ("second".to_string(), r#"{"b": 2, "c": 3}"#.as_bytes()),
+        ];
+        let mut output = Vec::new();
+        json2csv
+            .convert_from_readers(readers, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), expected);
+    }
+
+    #[test]
+    fn several_readers_with_report() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_skip_invalid(true);
+        let readers = vec![
+            ("first".to_string(), "{\"a\": 1}\n{\"a\": x}\n".as_bytes()),
+            ("second".to_string(), r#"{"a": 2}"#.as_bytes()),
+        ];
+        let mut output = Vec::new();
+        let report = json2csv
+            .convert_from_readers_with_report(readers, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), "a\n1\n2\n");
+        assert_eq!(report.rows, 2);
+        assert!(
+            matches!(
+                report.skipped.as_slice(),
+                [Error::Input { index: 0, source }] if matches!(**source, Error::ParsingDocument { index: 1, .. })
+            ),
+            "Unexpected report: {:?}",
+            report
+        );
+    }
+
+    /// `execute_with` checks that `convert_from_seekable` writes the same as `convert_from_reader`
+    #[rstest]
+    #[case::intersection_rejecting_unknown_keys(
+        Json2Csv::new(Flattener::new())
+            .set_header_strategy(HeaderStrategy::Intersection)
+            .set_reject_unknown_keys(true),
+        r#"{"a": 1, "b": 2} {"a": 3}"#,
+        "a\n1\n3\n"
+    )]
+    #[case::allowlist_without_rows(
+        Json2Csv::new(Flattener::new()).set_header_allowlist(Some(vec!["a".to_string()])),
+        "",
+        "a\n"
+    )]
+    #[case::allowlist_without_headers_without_rows(
+        Json2Csv::new(Flattener::new())
+            .set_header_allowlist(Some(vec!["a".to_string()]))
+            .set_headers_without_rows(false),
+        "",
+        ""
+    )]
+    #[case::no_headers(Json2Csv::new(Flattener::new()), "{} {}", "")]
+    #[case::row_count_without_headers(
+        Json2Csv::new(Flattener::new()).set_append_row_count(Some("# rows: {}".to_string())),
+        "{}",
+        ""
+    )]
+    #[case::row_count_without_rows(
+        Json2Csv::new(Flattener::new())
+            .set_header_allowlist(Some(vec!["a".to_string()]))
+            .set_append_row_count(Some("# rows: {}".to_string())),
+        "",
+        "a\n# rows: 0\n"
+    )]
+    #[case::trailer_stats_without_rows(
+        Json2Csv::new(Flattener::new())
+            .with_schema(Schema::new(vec!["a".to_string()]))
+            .set_emit_trailer_stats(true),
+        "",
+        "a\n# rows: 0\n# columns: 1\n# fill rate a: 0.00%\n"
+    )]
+    #[case::row_count_with_rows_left_out(
+        Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(0))
+            .set_append_row_count(Some("# rows: {}".to_string())),
+        r#"{"a": 1}"#,
+        "a\n# rows: 0\n"
+    )]
+    fn seekable_like_reader(
+        #[case] json2csv: Json2Csv,
+        #[case] input: &str,
+        #[case] expected: &str,
+    ) {
+        let result = execute_with(input, &json2csv);
+        assert_eq!(result.output, expected);
     }
 
-    fn execute_expect_err(input: &str, flattener: &Flattener) -> Vec<error::Error> {
-        let mut output_from_file = Vec::<u8>::new();
-        let csv_writer_from_file = csv::WriterBuilder::new()
-            .delimiter(b',')
-            .from_writer(&mut output_from_file);
+    #[test]
+    fn readers_with_source_column() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_metadata_columns(MetadataColumns {
+            source: Some("source".to_string()),
+            timestamp: None,
+        });
+        let readers = vec![
+            ("a.json".to_string(), r#"{"a": 1} {"b": 2}"#.as_bytes()),
+            ("b.json".to_string(), r#"{"a": 3}"#.as_bytes()),
+        ];
+        let mut output = Vec::new();
+        json2csv
+            .convert_from_readers(readers, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        let expected = &["a,b,source", "1,,a.json", ",2,a.json", "3,,b.json"];
+        assert_eq!(str::from_utf8(&output).unwrap(), expected.join("\n") + "\n");
 
-        let result_from_file = Json2Csv::new(flattener.clone())
-            .convert_from_reader(input.as_bytes(), csv_writer_from_file);
+        let readers = vec![
+            ("a.json".to_string(), r#"{"a": 1}"#.as_bytes()),
+            ("b.json".to_string(), r#"{"source": 1}"#.as_bytes()),
+        ];
+        let result =
+            json2csv.convert_from_readers(readers, csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(
+                &result,
+                Err(Error::Input { index: 1, source }) if matches!(&**source, Error::ColumnNameClash { name } if name == "source")
+            ),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
 
-        let input_from_array: Result<Vec<_>, _> =
-            Deserializer::from_str(input).into_iter::<Value>().collect();
-        let input_from_array = input_from_array.unwrap();
+    #[test]
+    fn source_column_with_header_settings() {
+        let metadata_columns = MetadataColumns {
+            source: Some("source".to_string()),
+            timestamp: None,
+        };
+        let readers = || {
+            vec![
+                ("x.json".to_string(), r#"{"z": 1}"#.as_bytes()),
+                ("y.json".to_string(), r#"{"b": 2}"#.as_bytes()),
+            ]
+        };
 
-        let mut output_from_array = Vec::<u8>::new();
-        let csv_writer_from_array = csv::WriterBuilder::new()
-            .delimiter(b',')
-            .from_writer(&mut output_from_array);
-        let result_from_array = Json2Csv::new(flattener.clone())
-            .convert_from_array(&input_from_array, csv_writer_from_array);
+        let mut output = Vec::new();
+        Json2Csv::new(Flattener::new())
+            .set_metadata_columns(metadata_columns.clone())
+            .convert_from_readers(readers(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(
+            str::from_utf8(&output).unwrap(),
+            "b,source,z\n,x.json,1\n2,y.json,\n"
+        );
 
-        // We expect both to produce the same error
-        let error_from_file = result_from_file.err().unwrap();
-        let error_from_array = result_from_array.err().unwrap();
+        let mut output = Vec::new();
+        Json2Csv::new(Flattener::new())
+            .set_metadata_columns(metadata_columns.clone())
+            .set_header_order(HeaderOrder::FirstSeen)
+            .convert_from_readers(readers(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(
+            str::from_utf8(&output).unwrap(),
+            "z,source,b\n1,x.json,\n,y.json,2\n"
+        );
 
-        vec![error_from_file, error_from_array]
+        let result = Json2Csv::new(Flattener::new())
+            .set_metadata_columns(metadata_columns)
+            .set_max_columns(Some(2))
+            .convert_from_readers(readers(), csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(result, Err(Error::TooManyColumns { count: 3, limit: 2 })),
+            "Unexpected result: {:?}",
+            result
+        );
     }
 
-    fn execute(input: &str, flattener: &Flattener) -> ExecutionResult {
-        let mut output_from_file = Vec::<u8>::new();
-        let csv_writer_from_file = csv::WriterBuilder::new()
-            .delimiter(b',')
-            .from_writer(&mut output_from_file);
-        Json2Csv::new(flattener.clone())
-            .convert_from_reader(input.as_bytes(), csv_writer_from_file)
+    #[test]
+    fn append_rows() {
+        let headers = ["b".to_string(), "a.c".to_string()];
+        let mut output = Vec::new();
+        let mut csv_writer = csv::Writer::from_writer(&mut output);
+        csv_writer.write_record(&headers).unwrap();
+
+        let json2csv = Json2Csv::new(Flattener::new());
+        let rows = json2csv
+            .convert_from_array_append(
+                &[serde_json::json!({"a": {"c": 1}, "b": 2})],
+                csv_writer,
+                &headers,
+            )
             .unwrap();
+        assert_eq!(rows, 1);
+        let rows = json2csv
+            .convert_from_array_append(
+                &[serde_json::json!({"b": 3, "d": 4}), serde_json::json!({})],
+                csv::Writer::from_writer(&mut output),
+                &headers,
+            )
+            .unwrap();
+        assert_eq!(rows, 2);
+        assert_eq!(str::from_utf8(&output).unwrap(), "b,a.c\n2,1\n3,\n,\n");
 
-        let input_from_array: Result<Vec<_>, _> =
-            Deserializer::from_str(input).into_iter::<Value>().collect();
-        let input_from_array = input_from_array.unwrap();
+        let result = json2csv
+            .set_reject_unknown_keys(true)
+            .convert_from_array_append(
+                &[serde_json::json!({"b": 3, "d": 4})],
+                csv::Writer::from_writer(std::io::sink()),
+                &headers,
+            );
+        assert!(
+            matches!(&result, Err(Error::UnknownKey { object_index: 0, key }) if key == "d"),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
 
-        let mut output_from_array = Vec::<u8>::new();
-        let csv_writer_from_array = csv::WriterBuilder::new()
-            .delimiter(b',')
-            .from_writer(&mut output_from_array);
-        Json2Csv::new(flattener.clone())
-            .convert_from_array(&input_from_array, csv_writer_from_array)
+    #[test]
+    fn skip_invalid_disabled() {
+        let mut output = Vec::new();
+        let result = Json2Csv::new(Flattener::new()).convert_from_reader_with_report(
+            "{\"a\": 1}\nnot json\n".as_bytes(),
+            csv::Writer::from_writer(&mut output),
+        );
+        assert!(
+            matches!(result, Err(Error::ParsingDocument { index: 1, .. })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[rstest]
+    #[case::wide(Json2Csv::new(Flattener::new()))]
+    #[case::schema(Json2Csv::new(Flattener::new()).with_schema(Schema::new(vec!["a".to_string()])))]
+    #[case::long(Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+        with_types: false,
+        skip_empty: false,
+    }))]
+    fn convert_with_progress(#[case] json2csv: Json2Csv) {
+        let input = r#"{"a": 1} {"a": 2} {"a": 3}"#;
+        let mut calls = Vec::new();
+        let rows = json2csv
+            .convert_from_reader_with_progress(
+                input.as_bytes(),
+                csv::Writer::from_writer(std::io::sink()),
+                |objects| calls.push(objects),
+            )
             .unwrap();
+        assert_eq!(calls, [1, 2, 3]);
+        assert_eq!(rows, 3);
+    }
 
-        let output_from_file = str::from_utf8(&output_from_file).unwrap();
-        let output_from_array = str::from_utf8(&output_from_array).unwrap();
+    #[rstest]
+    #[case::known_keys(r#"{"a": 1} {"b": {"c": 2}}"#, false, "a,b.c\n1,\n,2\n")]
+    #[case::unknown_keys_dropped(r#"{"a": 1, "d": 3} {"b": {"c": 2}}"#, false, "a,b.c\n1,\n,2\n")]
+    #[case::no_objects("", false, "a,b.c\n")]
+    #[case::known_keys_rejecting(r#"{"a": 1} {"b": {"c": 2}}"#, true, "a,b.c\n1,\n,2\n")]
+    fn convert_with_headers(#[case] input: &str, #[case] reject: bool, #[case] expected: &str) {
+        let mut output = Vec::new();
+        let rows = Json2Csv::new(Flattener::new())
+            .set_reject_unknown_keys(reject)
+            .convert_from_reader_with_headers(
+                input.as_bytes(),
+                csv::Writer::from_writer(&mut output),
+                &["a".to_string(), "b.c".to_string()],
+            )
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), expected);
+        assert_eq!(rows, expected.lines().count() - 1);
+    }
 
-        assert_eq!(output_from_file, output_from_array);
+    #[test]
+    fn reject_unknown_keys() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .with_schema(Schema::new(vec!["a".to_string()]))
+            .set_reject_unknown_keys(true);
+        for error in execute_with_expect_err(r#"{"a": 1} {"a": 2, "b": {"c": 3}}"#, &json2csv) {
+            assert!(
+                matches!(
+                    &error,
+                    Error::UnknownKey { object_index: 1, key } if key == "b.c"
+                ),
+                "Unexpected error: {:?}",
+                error
+            );
+        }
+    }
 
-        ExecutionResult {
-            input: input_from_array,
-            output: output_from_array.to_string(),
+    #[test]
+    fn reject_unknown_keys_reports_input_position() {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .with_schema(Schema::new(vec!["a".to_string()]))
+            .set_scalar_element_handling(ScalarElement::Skip)
+            .set_reject_unknown_keys(true);
+        for error in execute_with_expect_err(r#"{"a": 1} 2 {"b": 3}"#, &json2csv) {
+            assert!(
+                matches!(&error, Error::UnknownKey { object_index: 2, key } if key == "b"),
+                "Unexpected error: {:?}",
+                error
+            );
         }
     }
 
     #[rstest]
-    #[case::nesting_and_array(r#"{"a": {"b": 1}}{"c": [2]}"#, &["a.b,c.0", "1,", ",2"])]
-    #[case::spaces_end(r#"{"a": {"b": 1}}{"c": [2]}   "#, &["a.b,c.0", "1,", ",2"])]
-    #[case::spaces_begin(r#"      {"a": {"b": 1}}{"c": [2]}"#, &["a.b,c.0", "1,", ",2"])]
-    #[case::key_repeats_consistently(r#"{"a": 3}{"a": 4}{"a": 5}"#, &["a", "3", "4", "5"])]
-    #[case::reordering(r#"{"b": 3, "a": 1}{"a": 4, "b": 2}"#, &["a,b", "1,3", "4,2"])]
-    #[case::reordering_with_empty_array(r#"{"b": 3, "a": 1, "c": 0}{"c": [], "a": 4, "b": 2}"#, &["a,b,c", "1,3,0", "4,2,"])]
-    #[case::reordering_with_empty_object(r#"{"b": 3, "a": 1, "c": 0}{"c": {}, "a": 4, "b": 2}"#, &["a,b,c", "1,3,0", "4,2,"])]
-    #[case::reordering_with_missing(r#"{"b": 3, "a": 1, "c": 0}{"a": 4, "b": 2}"#, &["a,b,c", "1,3,0", "4,2,"])]
-    fn simple_input(
-        #[case] input: &str,
-        #[case] expected: &[&str],
-        #[values(true, false)] preserve_empty_arrays: bool,
-        #[values(true, false)] preserve_empty_objects: bool,
+    #[case::default(None, "a,b.c,d\ntrue,false,1\n")]
+    #[case::numbers(Some(("1", "0")), "a,b.c,d\n1,0,1\n")]
+    #[case::words(Some(("yes", "no")), "a,b.c,d\nyes,no,1\n")]
+    fn bool_representation(#[case] representation: Option<(&str, &str)>, #[case] expected: &str) {
+        let mut json2csv = Json2Csv::new(Flattener::new());
+        if let Some((true_str, false_str)) = representation {
+            json2csv =
+                json2csv.set_bool_representation(true_str.to_string(), false_str.to_string());
+        }
+        let result = execute_with(r#"{"a": true, "b": {"c": false}, "d": 1}"#, &json2csv);
+        assert_eq!(result.output, expected);
+    }
+
+    #[rstest]
+    #[case::default(("true", "false"), "", "a,b\ntrue,1\nfalse,\n,2\n,3\n")]
+    #[case::numbers(("1", "0"), "", "a,b\n1,1\n0,\n,2\n,3\n")]
+    #[case::missing_like_false(("1", "0"), "0", "a,b\n1,1\n0,0\n0,2\n,3\n")]
+    #[case::missing_apart(("yes", "no"), "n/a", "a,b\nyes,1\nno,n/a\nn/a,2\n,3\n")]
+    fn bool_representation_with_missing_keys(
+        #[case] bools: (&str, &str),
+        #[case] missing: &str,
+        #[case] expected: &str,
     ) {
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Plain)
-            .set_preserve_empty_arrays(preserve_empty_arrays)
-            .set_preserve_empty_objects(preserve_empty_objects);
-        let result = execute(input, &flattener);
-        assert_eq!(result.output, expected.join("\n") + "\n");
+        let (true_str, false_str) = bools;
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_bool_representation(true_str.to_string(), false_str.to_string())
+            .set_missing_representation(missing.to_string());
+        let result = execute_with(
+            r#"{"a": true, "b": 1} {"a": false} {"b": 2} {"a": null, "b": 3}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output, expected);
     }
 
-    #[test]
-    fn duplicated_keys_last_wins() {
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Plain)
-            .set_preserve_empty_arrays(true)
-            .set_preserve_empty_objects(true);
-        let result = execute(
-            r#"{"a": [1,2,3], "a": {"b": 2}, "c": 1, "c": 2}"#,
-            &flattener,
+    #[rstest]
+    #[case::default(None, "a,b,c,d\n1.005,1e-7,1.2345678901234567e+19,-3\n")]
+    #[case::two_decimals(Some(2), "a,b,c,d\n1.00,0.00,12345678901234567168.00,-3\n")]
+    #[case::no_decimals(Some(0), "a,b,c,d\n1,0,12345678901234567168,-3\n")]
+    fn float_precision(#[case] precision: Option<usize>, #[case] expected: &str) {
+        let json2csv = Json2Csv::new(Flattener::new()).set_float_precision(precision);
+        let result = execute_with(
+            r#"{"a": 1.005, "b": 1e-7, "c": 1.2345678901234567e19, "d": -3}"#,
+            &json2csv,
         );
-        let expected = &["a.b,c", "2,2"];
-        assert_eq!(result.output, expected.join("\n") + "\n");
+        assert_eq!(result.output, expected);
     }
 
-    /// We use internal separators that later are replaced by the user provided ones.
-    /// This checks that the replacement does not make the headers and the data be in a different order.
-    #[test]
-    fn no_reordering_on_non_default_separators() {
-        let flattener = Flattener::new()
-            .set_key_separator("]")
-            .set_array_formatting(ArrayFormatting::Surrounded {
-                start: ".".to_string(),
-                end: "".to_string(),
-            })
-            .set_preserve_empty_arrays(true)
-            .set_preserve_empty_objects(true);
-        let result = execute(r#"{"a": [1,2,3]} {"a": {"b": 2}}"#, &flattener);
-        let expected = &["a.0,a.1,a.2,a]b", "1,2,3,", ",,,2"];
-        assert_eq!(result.output, expected.join("\n") + "\n");
+    #[rstest]
+    #[case::wide(Json2Csv::new(Flattener::new()), "\u{feff}a\nà\n")]
+    #[case::schema(
+        Json2Csv::new(Flattener::new()).with_schema(Schema::new(vec!["a".to_string()])),
+        "\u{feff}a\nà\n"
+    )]
+    #[case::long(
+        Json2Csv::new(Flattener::new()).set_output_shape(OutputShape::Long {
+            with_types: false,
+            skip_empty: false,
+        }),
+        "\u{feff}row_id,key,value\n0,a,à\n"
+    )]
+    fn write_bom(#[case] json2csv: Json2Csv, #[case] expected: &str) {
+        let json2csv = json2csv.set_write_bom(true);
+        let output = json2csv
+            .convert_from_reader_to_string(r#"{"a": "à"}"#.as_bytes())
+            .unwrap();
+        assert_eq!(output, expected);
+
+        let mut output = Vec::new();
+        json2csv
+            .convert_from_reader(r#"{"a": "à"}"#.as_bytes(), json2csv.csv_writer(&mut output))
+            .unwrap();
+        assert_eq!(std::str::from_utf8(&output).unwrap(), expected);
     }
 
-    /// An error must be reported when flattening makes two keys in an object look the same.
     #[rstest]
-    #[case::in_one_object(r#"{"a": {"b": 1}, "a.b": 2}"#)]
-    #[case::in_different_objects(r#"{"a": {"b": 1}}{"a.b": 2}"#)]
-    fn error_on_collision(#[case] input: &str) {
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Plain)
-            .set_preserve_empty_arrays(false)
-            .set_preserve_empty_objects(false);
-        for err in execute_expect_err(input, &flattener) {
-            assert!(
-                matches!(err, Error::FlattenedKeysCollision),
-                "Unexpected error: {}",
-                err
-            );
-        }
+    #[case::always(QuoteStyle::Always, "\u{feff}\"a,b\"\n\"1\"\n")]
+    #[case::necessary(QuoteStyle::Necessary, "\u{feff}\"a,b\"\n1\n")]
+    fn write_bom_before_quoted_header(#[case] style: QuoteStyle, #[case] expected: &str) {
+        let json2csv = Json2Csv::new(Flattener::new())
+            .set_write_bom(true)
+            .set_quote_style(style);
+        let output = json2csv
+            .convert_from_reader_to_string(r#"{"a,b": 1}"#.as_bytes())
+            .unwrap();
+        assert_eq!(output, expected);
     }
 
-    /// An error must be reported when flattening makes two keys in an object look the same, even
-    /// when it's due to array formatting.
+    #[test]
+    fn write_bom_without_headers() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_write_bom(true);
+        let output = json2csv.convert_from_reader_to_string("{}".as_bytes());
+        assert_eq!(output.unwrap(), "");
+    }
+
+    #[test]
+    fn write_bom_only_with_writers_created_by_json2csv() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_write_bom(true);
+        let result = execute_with(r#"{"a": 1}"#, &json2csv);
+        assert_eq!(result.output, "a\n1\n");
+    }
+
+    /// The objects of arrays can be flattened in parallel, so check that the output and the errors
+    /// are still the same as when reading them one by one.
     #[rstest]
-    #[case::in_one_object(r#"{"a[0]": 1, "a": [2]}"#, "[", "]")]
-    #[case::in_different_objects(r#"{"a[0]": 1} {"a": [2]}"#, "[", "]")]
-    fn error_on_collision_array_formatting(
-        #[case] input: &str,
-        #[case] start: &str,
-        #[case] end: &str,
-    ) {
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Surrounded {
-                start: start.to_string(),
-                end: end.to_string(),
+    #[case::many_objects(Json2Csv::new(Flattener::new()), None)]
+    #[case::skipped_scalars(
+        Json2Csv::new(Flattener::new())
+            .set_root_pointer(Some("/rows".to_string()))
+            .set_scalar_element_handling(ScalarElement::Skip),
+        None
+    )]
+    #[case::too_many_fields(
+        Json2Csv::new(Flattener::new()).set_max_fields_per_object(Some(3)),
+        Some("The object at position 500 has too many fields after flattening: 4")
+    )]
+    #[case::too_many_objects(
+        Json2Csv::new(Flattener::new()).set_max_objects(Some(2100)),
+        Some("The input contains more than 2100 objects")
+    )]
+    #[case::max_rows(
+        Json2Csv::new(Flattener::new())
+            .set_max_rows(Some(1500))
+            .set_headers_from_written_rows(true),
+        None
+    )]
+    fn many_objects(#[case] json2csv: Json2Csv, #[case] expected_error: Option<&str>) {
+        let input: String = (0..3000)
+            .map(|i| match i {
+                500 => r#"{"rows": [{"a": 1, "b": 2, "c": 3, "d": 4}]}"#.to_string(),
+                _ if i % 7 == 0 => format!(r#"{{"rows": [{i}, {{"a": {i}}}]}}"#),
+                _ => format!(r#"{{"rows": [{{"b": {{"c": {i}}}}}]}}"#),
             })
-            .set_preserve_empty_arrays(false)
-            .set_preserve_empty_objects(false);
-        for err in execute_expect_err(input, &flattener) {
-            assert!(
-                matches!(err, Error::FlattenedKeysCollision),
-                "Unexpected error: {}",
-                err
-            );
+            .collect();
+        let check = || match expected_error {
+            Some(expected_error) => {
+                for error in execute_with_expect_err(&input, &json2csv) {
+                    assert_eq!(error.to_string(), expected_error);
+                }
+            }
+            None => {
+                execute_with(&input, &json2csv);
+            }
+        };
+        // The objects are only flattened in parallel if there are several threads
+        #[cfg(feature = "rayon")]
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(4)
+            .build()
+            .unwrap()
+            .install(check);
+        #[cfg(not(feature = "rayon"))]
+        check();
+    }
+
+    #[rstest]
+    #[case::objects(Json2Csv::new(Flattener::new()), r#"{"a": 1} {"b": {"c": "x,y"}}"#)]
+    #[case::no_headers(Json2Csv::new(Flattener::new()), "{} {}")]
+    #[case::sorted(
+        Json2Csv::new(Flattener::new())
+            .set_sort_by_columns(vec![("a".to_string(), SortDir::Descending)]),
+        r#"{"a": 1} {"a": 3} {"a": 2}"#
+    )]
+    #[case::schema(
+        Json2Csv::new(Flattener::new()).with_schema(Schema::new(vec!["b".to_string(), "a".to_string()])),
+        r#"{"a": 1} {"a": 3, "c": 4}"#
+    )]
+    #[case::schema_without_objects(
+        Json2Csv::new(Flattener::new()).with_schema(Schema::new(vec!["a".to_string()])),
+        ""
+    )]
+    fn rows_iterator(#[case] json2csv: Json2Csv, #[case] input: &str) {
+        let expected: Vec<Vec<String>> = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_reader(execute_with(input, &json2csv).output.as_bytes())
+            .records()
+            .map(|record| record.unwrap().iter().map(String::from).collect())
+            .collect();
+
+        let rows = json2csv.rows_from_reader(input.as_bytes()).unwrap();
+        assert_eq!(rows.collect::<Result<Vec<_>, _>>().unwrap(), expected);
+
+        let objects: Vec<Value> = Deserializer::from_str(input)
+            .into_iter()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let rows = json2csv.rows_from_array(&objects).unwrap();
+        assert_eq!(rows.collect::<Result<Vec<_>, _>>().unwrap(), expected);
+    }
+
+    #[test]
+    fn custom_header_order() {
+        fn natural(a: &str, b: &str) -> Ordering {
+            let index = |key: &str| key.rsplit('.').next().and_then(|i| i.parse::<usize>().ok());
+            index(a).cmp(&index(b))
         }
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_header_order(HeaderOrder::custom(natural));
+        let input = r#"{"b": [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10], "a": 1}"#;
+        let result = execute_with(input, &json2csv);
+        assert_eq!(
+            result.output,
+            "a,b.0,b.1,b.2,b.3,b.4,b.5,b.6,b.7,b.8,b.9,b.10\n1,0,1,2,3,4,5,6,7,8,9,10\n"
+        );
     }
 
-    /// In all those cases there are no headers after flattening the input, so the resulting CSV is
-    /// empty.
     #[rstest]
-    #[case::empty_string("")]
-    #[case::empty_json_doc("{}")]
-    #[case::multiple_empty_json_docs("{}{}{}{}")]
-    #[case::empty_array(r#"{"a": []}"#)]
-    #[case::empty_obj(r#"{"b": {}}"#)]
-    #[case::empty_array_obj_and_json_doc(r#"{"a": []} {"b": {}} {}"#)]
-    fn empty_csv_when_no_headers(#[case] input: &str) {
-        let expected = "";
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Plain)
-            .set_preserve_empty_arrays(false)
-            .set_preserve_empty_objects(false);
-        let result = execute(input, &flattener);
-        assert_eq!(result.output, expected);
+    #[case::plain(
+        ArrayFormatting::Plain,
+        "a.0,a.1,a.2,a.3,a.4,a.5,a.6,a.7,a.8,a.9,a.10,a.11,a.12,b"
+    )]
+    #[case::surrounded(
+        ArrayFormatting::Surrounded { start: "[".to_string(), end: "]".to_string() },
+        "a[0],a[1],a[2],a[3],a[4],a[5],a[6],a[7],a[8],a[9],a[10],a[11],a[12],b"
+    )]
+    fn natural_header_order(#[case] formatting: ArrayFormatting, #[case] expected: &str) {
+        let flattener = Flattener::new().set_array_formatting(formatting);
+        let json2csv = Json2Csv::new(flattener).set_header_order(HeaderOrder::Natural);
+        let result = execute_with(
+            r#"{"b": 1, "a": [0,1,2,3,4,5,6,7,8,9,10,11,12]}"#,
+            &json2csv,
+        );
+        assert_eq!(result.output.lines().next().unwrap(), expected);
     }
 
     #[rstest]
-    #[case::empty_array(r#"{"a": []}"#)]
-    #[case::empty_array_extra_obj(r#"{"a": []} {} {}"#)]
-    #[case::empty_obj(r#"{"a": {}}"#)]
-    #[case::empty_obj_extra_obj(r#"{"a": {}} {}"#)]
-    fn preserved_empty(#[case] input: &str) {
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Plain)
-            .set_preserve_empty_arrays(true)
-            .set_preserve_empty_objects(true);
-        let result = execute(input, &flattener);
+    #[case::numbers("a.2", "a.10", Ordering::Less)]
+    #[case::nested_numbers("a.10.b.9", "a.10.b.10", Ordering::Less)]
+    #[case::text("a.b", "a.c", Ordering::Less)]
+    #[case::number_and_text("a.1", "a.b", Ordering::Less)]
+    #[case::prefix("a", "a.1", Ordering::Less)]
+    #[case::leading_zeros("a.01", "a.1", Ordering::Less)]
+    #[case::leading_zeros_and_bigger_number("a.02", "a.1", Ordering::Greater)]
+    #[case::equal("a.1", "a.1", Ordering::Equal)]
+    fn compare_naturally(#[case] a: &str, #[case] b: &str, #[case] expected: Ordering) {
+        assert_eq!(natural_cmp(a, b), expected);
+        assert_eq!(natural_cmp(b, a), expected.reverse());
+    }
 
-        let mut expected = vec!["a"];
+    #[test]
+    fn schema_from_several_inputs() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let first = r#"{"a": 1} {"b": {"c": 2}}"#;
+        let second = [serde_json::json!({"d": 3}), serde_json::json!({"a": 4})];
 
-        // Extend the vector with as many rows as objects has the input
-        expected.extend(vec![r#""""#; result.input.len()]);
+        let mut builder = json2csv.schema_builder();
+        builder
+            .add_reader(first.as_bytes())
+            .unwrap()
+            .add_array(&second)
+            .unwrap();
+        let schema = builder.build().unwrap();
+        assert_eq!(schema.headers(), ["a", "b.c", "d"]);
 
-        assert_eq!(result.output, expected.join("\n") + "\n");
+        let json2csv = json2csv.with_schema(schema);
+        let mut output = Vec::new();
+        json2csv
+            .convert_from_reader(first.as_bytes(), csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), "a,b.c,d\n1,,\n,2,\n");
+
+        let mut output = Vec::new();
+        json2csv
+            .convert_from_array(&second, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(str::from_utf8(&output).unwrap(), "a,b.c,d\n,,3\n4,,\n");
+    }
+
+    #[test]
+    fn schema_builder_detects_collisions_between_inputs() {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let mut builder = json2csv.schema_builder();
+        builder
+            .add_reader(r#"{"a": {"b": 1}}"#.as_bytes())
+            .unwrap()
+            .add_reader(r#"{"a.b": 2}"#.as_bytes())
+            .unwrap();
+        let result = builder.build();
+        assert!(
+            matches!(result, Err(Error::FlattenedKeysCollision { .. })),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn flat_array() {
+        let json2csv = Json2Csv::new(Flattener::new()).set_header_case_style(CaseStyle::Upper);
+        let objects = [
+            serde_json::json!({"b": 1, "a": "x", "c": null}),
+            serde_json::json!({"a": true, "d": 2.5}),
+            serde_json::json!({}),
+        ];
+
+        let mut expected = Vec::new();
+        json2csv
+            .convert_from_array(&objects, csv::Writer::from_writer(&mut expected))
+            .unwrap();
+        let mut output = Vec::new();
+        let rows = json2csv
+            .convert_from_flat_array(&objects, csv::Writer::from_writer(&mut output))
+            .unwrap();
+        assert_eq!(rows, 3);
+        assert_eq!(output, expected);
+        assert_eq!(
+            str::from_utf8(&output).unwrap(),
+            "A,B,C,D\nx,1,,\ntrue,,,2.5\n,,,\n"
+        );
     }
 
     #[rstest]
-    #[case::empty_array(r#"{"a": [], "b": 3}"#, &["b", "3"])]
-    #[case::empty_array_extra_obj(r#"{"a": [], "b": 3} {} {}"#, &["b", "3", r#""""#, r#""""#])]
-    #[case::empty_obj(r#"{"a": {}, "b": 3}"#, &["b", "3"])]
-    #[case::empty_obj_extra_obj(r#"{"a": {}} {} {"b": 3} {}"#, &["b", r#""""#, r#""""#, "3", r#""""#])]
-    #[case::empty_obj_extra_obj(r#"{"a": {}} {} {"b": 3} {"c": 4}"#, &["b,c", ",", ",", "3,", ",4"])]
-    fn not_preserved_empty(#[case] input: &str, #[case] expected: &[&str]) {
-        let flattener = Flattener::new()
-            .set_key_separator(".")
-            .set_array_formatting(ArrayFormatting::Plain)
-            .set_preserve_empty_arrays(false)
-            .set_preserve_empty_objects(false);
-        let result = execute(input, &flattener);
+    #[case::object(serde_json::json!({"b": {"c": 1}}))]
+    #[case::array(serde_json::json!({"b": [1]}))]
+    #[case::empty_array(serde_json::json!({"b": []}))]
+    fn error_on_nested_value_in_flat_array(#[case] nested: Value) {
+        let json2csv = Json2Csv::new(Flattener::new());
+        let objects = [serde_json::json!({"a": 1}), nested];
+        let result =
+            json2csv.convert_from_flat_array(&objects, csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(
+                &result,
+                Err(Error::NestedValueInFlatInput { object_index: 1, key }) if key == "b"
+            ),
+            "Unexpected result: {:?}",
+            result
+        );
+    }
 
-        assert_eq!(result.output, expected.join("\n") + "\n");
+    #[test]
+    fn nested_value_in_flat_array_reports_input_position() {
+        let json2csv =
+            Json2Csv::new(Flattener::new()).set_scalar_element_handling(ScalarElement::Skip);
+        let objects = [
+            serde_json::json!(1),
+            serde_json::json!({"a": 1}),
+            serde_json::json!({"b": {"c": 2}}),
+        ];
+        let result =
+            json2csv.convert_from_flat_array(&objects, csv::Writer::from_writer(std::io::sink()));
+        assert!(
+            matches!(
+                &result,
+                Err(Error::NestedValueInFlatInput { object_index: 2, key }) if key == "b"
+            ),
+            "Unexpected result: {:?}",
+            result
+        );
     }
 }
@@ -0,0 +1,546 @@
+use crate::error::Error;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::fmt;
+use std::sync::Arc;
+
+/// Wrapper around a user provided function, so it can be stored in the configuration of a
+/// `Json2Csv` while keeping it cloneable and comparable. Two callbacks are only equal if they are
+/// clones of the same one.
+pub struct Callback<F: ?Sized>(Arc<F>);
+
+impl<F: ?Sized> Callback<F> {
+    /// Wraps a function so it can be used as a callback.
+    #[must_use]
+    pub fn new(function: Arc<F>) -> Self {
+        Callback(function)
+    }
+
+    pub(crate) fn get(&self) -> &F {
+        &self.0
+    }
+}
+
+impl<F: ?Sized> Clone for Callback<F> {
+    fn clone(&self) -> Self {
+        Callback(Arc::clone(&self.0))
+    }
+}
+
+impl<F: ?Sized> fmt::Debug for Callback<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Callback")
+    }
+}
+
+impl<F: ?Sized> PartialEq for Callback<F> {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl<F: ?Sized> Eq for Callback<F> {}
+
+/// Enum to specify how the keys of the input objects are decoded before flattening them.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum KeyDecoder {
+    /// Keys are used as they are.
+    None,
+
+    /// Keys are percent-decoded, as in URLs. Example: `{"user%20name": 1}` => `{"user name": 1}`
+    /// Percent signs not followed by two hexadecimal digits are kept as they are.
+    PercentDecode,
+
+    /// Keys are decoded by the provided function.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Callback<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl KeyDecoder {
+    /// Creates a `KeyDecoder::Custom` that decodes the keys using the provided function.
+    #[must_use]
+    pub fn custom(decoder: impl Fn(&str) -> String + Send + Sync + 'static) -> Self {
+        KeyDecoder::Custom(Callback::new(Arc::new(decoder)))
+    }
+
+    fn decode_key(&self, key: &str) -> Result<String, Error> {
+        match self {
+            KeyDecoder::None => Ok(key.to_string()),
+            KeyDecoder::PercentDecode => percent_decode(key),
+            KeyDecoder::Custom(decoder) => Ok(decoder.get()(key)),
+        }
+    }
+
+    /// Decodes all the keys in `value`, recursively. Two keys of the same object that end looking
+    /// the same after being decoded are reported as a collision.
+    pub(crate) fn decode_keys<'a>(&self, value: &'a Value) -> Result<Cow<'a, Value>, Error> {
+        if *self == KeyDecoder::None {
+            return Ok(Cow::Borrowed(value));
+        }
+
+        Ok(Cow::Owned(match value {
+            Value::Object(obj) => {
+                let mut decoded = Map::new();
+                for (key, val) in obj {
+                    let val = self.decode_keys(val)?.into_owned();
+                    let key = self.decode_key(key)?;
+                    if decoded.contains_key(&key) {
+                        return Err(Error::FlattenedKeysCollision { keys: vec![key] });
+                    }
+                    decoded.insert(key, val);
+                }
+                Value::Object(decoded)
+            }
+            Value::Array(arr) => Value::Array(
+                arr.iter()
+                    .map(|val| self.decode_keys(val).map(Cow::into_owned))
+                    .collect::<Result<_, _>>()?,
+            ),
+            _ => value.clone(),
+        }))
+    }
+}
+
+fn percent_decode(key: &str) -> Result<String, Error> {
+    let hex_value = |byte: u8| char::from(byte).to_digit(16);
+
+    let bytes = key.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(u8::try_from(high * 16 + low).expect("Two hex digits fit in a byte"));
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(decoded).map_err(|_| Error::KeyDecoding {
+        key: key.to_string(),
+    })
+}
+
+/// Names of the columns with information about the origin of the rows, added when converting
+/// files or labeled readers. Columns set to `None` are not added.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MetadataColumns {
+    /// Name of the column with the path of the file, or the label of the reader, each row comes
+    /// from.
+    pub source: Option<String>,
+    /// Name of the column with the UTC time at which the conversion started, in RFC 3339 format.
+    pub timestamp: Option<String>,
+}
+
+/// Enum to specify what happens with the empty keys of the input objects, like in `{"": 1}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum EmptyKey {
+    /// Empty keys are reported as an error.
+    Error,
+
+    /// Empty keys are replaced with the provided string.
+    /// Example: If the replacement is `_empty_` then `{"a": {"": 1}}` => `{"a._empty_": 1}`
+    Replace(String),
+
+    /// Empty keys are kept. Example: `{"a": {"": 1}}` => `{"a.": 1}`
+    Keep,
+}
+
+/// Enum to specify what happens with the arrays that mix objects with other values, like the one
+/// in `{"a": [1, {"b": 2}, 3]}`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum MixedArray {
+    /// Mixed arrays are flattened like any other array.
+    /// Example: `{"a": [1, {"b": 2}]}` => `{"a.0": 1, "a.1.b": 2}`
+    Flatten,
+
+    /// Mixed arrays are serialized as JSON into a single value.
+    /// Example: `{"a": [1, {"b": 2}]}` => `{"a": "[1,{\"b\":2}]"}`
+    JsonString,
+
+    /// Mixed arrays are reported as an error.
+    Error,
+}
+
+impl MixedArray {
+    /// Applies the policy to all the mixed arrays in `value`, recursively.
+    pub(crate) fn apply<'a>(&self, value: &'a Value) -> Result<Cow<'a, Value>, Error> {
+        if *self == MixedArray::Flatten {
+            return Ok(Cow::Borrowed(value));
+        }
+        self.apply_at(value, &mut String::new())
+    }
+
+    /// `path` is the JSON Pointer to `value`, used to report where the mixed arrays are.
+    fn apply_at<'a>(&self, value: &'a Value, path: &mut String) -> Result<Cow<'a, Value>, Error> {
+        let mut apply_to_child = |child: &'a Value, token: &str| {
+            let len = path.len();
+            path.push('/');
+            path.push_str(&token.replace('~', "~0").replace('/', "~1"));
+            let child = self.apply_at(child, path).map(Cow::into_owned);
+            path.truncate(len);
+            child
+        };
+
+        Ok(Cow::Owned(match value {
+            Value::Array(arr) if is_mixed(arr) => match self {
+                MixedArray::Flatten => unreachable!("Mixed arrays are flattened as they are"),
+                MixedArray::JsonString => Value::String(value.to_string()),
+                MixedArray::Error => return Err(Error::MixedArray { path: path.clone() }),
+            },
+            Value::Array(arr) => Value::Array(
+                arr.iter()
+                    .enumerate()
+                    .map(|(i, val)| apply_to_child(val, &i.to_string()))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Object(obj) => Value::Object(
+                obj.iter()
+                    .map(|(key, val)| Ok((key.clone(), apply_to_child(val, key)?)))
+                    .collect::<Result<_, Error>>()?,
+            ),
+            _ => return Ok(Cow::Borrowed(value)),
+        }))
+    }
+}
+
+/// An array is mixed if it contains objects and also other kinds of values.
+fn is_mixed(arr: &[Value]) -> bool {
+    arr.iter().any(Value::is_object) && !arr.iter().all(Value::is_object)
+}
+
+/// Enum to specify what happens with the arrays whose elements are all objects, like the one in
+/// `{"items": [{"x": 1}, {"x": 2}]}`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ArrayOfObjectsMode {
+    /// Arrays of objects are flattened like any other array.
+    /// Example: `{"a": [{"b": 1}, {"b": 2}]}` => `{"a.0.b": 1, "a.1.b": 2}`
+    Flatten,
+
+    /// Arrays of objects are serialized as JSON into a single value.
+    /// Example: `{"a": [{"b": 1}, {"b": 2}]}` => `{"a": "[{\"b\":1},{\"b\":2}]"}`
+    JsonString,
+}
+
+impl ArrayOfObjectsMode {
+    /// Applies the mode to all the arrays of objects in `value`, recursively.
+    pub(crate) fn apply<'a>(&self, value: &'a Value) -> Cow<'a, Value> {
+        if *self == ArrayOfObjectsMode::Flatten {
+            return Cow::Borrowed(value);
+        }
+        match value {
+            Value::Array(arr) if is_array_of_objects(arr) => {
+                Cow::Owned(Value::String(value.to_string()))
+            }
+            Value::Array(arr) => Cow::Owned(Value::Array(
+                arr.iter().map(|val| self.apply(val).into_owned()).collect(),
+            )),
+            Value::Object(obj) => Cow::Owned(Value::Object(
+                obj.iter()
+                    .map(|(key, val)| (key.clone(), self.apply(val).into_owned()))
+                    .collect(),
+            )),
+            _ => Cow::Borrowed(value),
+        }
+    }
+}
+
+/// Empty arrays are not arrays of objects, since they are handled by the flattener.
+fn is_array_of_objects(arr: &[Value]) -> bool {
+    !arr.is_empty() && arr.iter().all(Value::is_object)
+}
+
+/// Enum to specify how the arrays and objects that reach the CSV output without being flattened
+/// are written. The flattener only leaves empty ones, but a conversion should not lose data if
+/// non empty ones get there anyway.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ComplexValueMode {
+    /// Arrays and objects are written with the text set with
+    /// `Json2Csv::set_empty_container_representation`, empty by default.
+    Blank,
+
+    /// Non empty arrays and objects are serialized as compact JSON, e.g. `[1,2]`. Empty ones are
+    /// written like with `ComplexValueMode::Blank`.
+    JsonString,
+}
+
+/// Enum to specify the terminator of the records written by the CSV writers created by a
+/// `Json2Csv`. Every record is terminated, including the last one.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RecordTerminator {
+    /// Records end with `\r\n`, as RFC 4180 requires.
+    Crlf,
+
+    /// Records end with the given byte, usually `\n`.
+    Any(u8),
+}
+
+impl RecordTerminator {
+    /// The bytes written at the end of each record.
+    pub(crate) fn as_bytes(&self) -> &[u8] {
+        match self {
+            RecordTerminator::Crlf => b"\r\n",
+            RecordTerminator::Any(byte) => std::slice::from_ref(byte),
+        }
+    }
+}
+
+impl From<RecordTerminator> for csv::Terminator {
+    fn from(terminator: RecordTerminator) -> Self {
+        match terminator {
+            RecordTerminator::Crlf => csv::Terminator::CRLF,
+            RecordTerminator::Any(byte) => csv::Terminator::Any(byte),
+        }
+    }
+}
+
+/// Enum to specify which fields are quoted by the CSV writers created by a `Json2Csv`. Mirror of
+/// `csv::QuoteStyle`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum QuoteStyle {
+    /// Every field is quoted, including the empty ones and the numbers.
+    Always,
+
+    /// Only the fields that need it are quoted, e.g. because they contain the delimiter.
+    Necessary,
+
+    /// Every field that does not look like a number is quoted, including the empty ones.
+    NonNumeric,
+
+    /// No field is quoted, even if that produces invalid CSV.
+    Never,
+}
+
+impl From<QuoteStyle> for csv::QuoteStyle {
+    fn from(quote_style: QuoteStyle) -> Self {
+        match quote_style {
+            QuoteStyle::Always => csv::QuoteStyle::Always,
+            QuoteStyle::Necessary => csv::QuoteStyle::Necessary,
+            QuoteStyle::NonNumeric => csv::QuoteStyle::NonNumeric,
+            QuoteStyle::Never => csv::QuoteStyle::Never,
+        }
+    }
+}
+
+/// Direction in which the rows are sorted by a column.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SortDir {
+    Ascending,
+    Descending,
+}
+
+/// Enum to specify which flattened keys become the headers of the CSV.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HeaderStrategy {
+    /// The headers are the keys present in any of the flattened objects.
+    Union,
+
+    /// The headers are the keys present in all the flattened objects. The values of the rest of
+    /// the keys are dropped.
+    Intersection,
+}
+
+/// Enum to specify the shape of the CSV output.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum OutputShape {
+    /// One row per object and one column per flattened key.
+    Wide,
+
+    /// One row per flattened key of each object, with the columns `row_id`, `key` and `value`,
+    /// where `row_id` is the position of the object in the input, starting at 0. If `with_types`
+    /// is `true` a `type` column is added with the JSON type of the value: `string`, `number`,
+    /// `bool`, `null`, and `array` or `object` for the empty ones that the flattener preserves.
+    /// If `skip_empty` is `true` there are no rows for null values, empty strings or empty arrays
+    /// and objects.
+    Long { with_types: bool, skip_empty: bool },
+}
+
+/// Enum to specify what happens with the values of the input that should be objects but are
+/// scalars, like the `null` in `{"a": 1} null {"b": 2}`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ScalarElement {
+    /// Scalars are reported as an error.
+    Error,
+
+    /// Scalars are ignored.
+    Skip,
+
+    /// Scalars are converted into a row with all the fields empty.
+    EmptyRow,
+}
+
+/// Enum to specify the case style of the keys of the flattened objects. Each key of the input
+/// objects, i.e. each segment of a flattened key, is converted on its own, so the separators are
+/// preserved. Words are delimited by `_`, `-`, spaces and changes from lower to upper case.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum CaseStyle {
+    /// Keys are used as they are.
+    AsIs,
+
+    /// Example: `{"userName": {"HTTPStatus": 1}}` => `{"user_name.http_status": 1}`
+    Snake,
+
+    /// Example: `{"user_name": {"http-status": 1}}` => `{"userName.httpStatus": 1}`
+    Camel,
+
+    /// Example: `{"userName": {"HTTPStatus": 1}}` => `{"user-name.http-status": 1}`
+    Kebab,
+
+    /// Keys are lowercased, without splitting them into words.
+    /// Example: `{"userName": {"HTTP_Status": 1}}` => `{"username.http_status": 1}`
+    Lower,
+
+    /// Keys are uppercased, without splitting them into words.
+    /// Example: `{"userName": {"HTTP_Status": 1}}` => `{"USERNAME.HTTP_STATUS": 1}`
+    Upper,
+}
+
+impl CaseStyle {
+    /// Converts a single key to this case style.
+    pub(crate) fn convert(self, key: &str) -> Cow<'_, str> {
+        let separator = match self {
+            CaseStyle::AsIs => return Cow::Borrowed(key),
+            CaseStyle::Lower => return Cow::Owned(key.to_lowercase()),
+            CaseStyle::Upper => return Cow::Owned(key.to_uppercase()),
+            CaseStyle::Snake => "_",
+            CaseStyle::Kebab => "-",
+            CaseStyle::Camel => "",
+        };
+
+        let words = split_words(key);
+        let words = words.iter().enumerate().map(|(i, word)| {
+            let word = word.to_lowercase();
+            if self != CaseStyle::Camel || i == 0 {
+                return word;
+            }
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect())
+                .unwrap_or_default()
+        });
+        Cow::Owned(words.collect::<Vec<_>>().join(separator))
+    }
+}
+
+/// Splits a key into words. Acronyms are kept together, as in `HTTPStatus` => `HTTP`, `Status`.
+fn split_words(key: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    let chars: Vec<(usize, char)> = key.char_indices().collect();
+    let mut start = None;
+    for (i, &(pos, c)) in chars.iter().enumerate() {
+        if c == '_' || c == '-' || c.is_whitespace() {
+            if let Some(start) = start.take() {
+                words.push(&key[start..pos]);
+            }
+            continue;
+        }
+        if let Some(word_start) = start {
+            let prev = chars[i - 1].1;
+            let next_is_lower = chars
+                .get(i + 1)
+                .is_some_and(|&(_, next)| next.is_lowercase());
+            let starts_word = c.is_uppercase()
+                && (prev.is_lowercase()
+                    || prev.is_numeric()
+                    || (prev.is_uppercase() && next_is_lower));
+            if starts_word {
+                words.push(&key[word_start..pos]);
+                start = Some(pos);
+            }
+        } else {
+            start = Some(pos);
+        }
+    }
+    if let Some(start) = start {
+        words.push(&key[start..]);
+    }
+    words
+}
+
+/// Enum to specify the order of the headers of the CSV.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HeaderOrder {
+    /// Headers are sorted alphabetically.
+    Alphabetical,
+
+    /// Headers are in the order in which they are first found in the input. Note that the keys of
+    /// each object are only kept in the order of the input with the `preserve_order` feature,
+    /// otherwise they are sorted alphabetically within each object.
+    FirstSeen,
+
+    /// Headers are sorted alphabetically, except for the numbers in them, which are compared by
+    /// their value, so array indices are in order: `a.2` goes before `a.10`.
+    Natural,
+
+    /// Headers are sorted with the provided comparison function, e.g. to sort the array indices
+    /// numerically. Headers that compare as equal are sorted alphabetically.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Callback<HeaderComparator>),
+}
+
+/// Compares two keys like text, except for the sequences of digits, which are compared by their
+/// numeric value. Keys that only differ in leading zeros are compared like text.
+pub(crate) fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a_rest, mut b_rest) = (a, b);
+    while let (Some(a_char), Some(b_char)) = (a_rest.chars().next(), b_rest.chars().next()) {
+        let ordering = if a_char.is_ascii_digit() && b_char.is_ascii_digit() {
+            let (a_number, a_tail) = split_number(a_rest);
+            let (b_number, b_tail) = split_number(b_rest);
+            a_rest = a_tail;
+            b_rest = b_tail;
+            // Without leading zeros the longest number is the biggest one
+            let a_number = a_number.trim_start_matches('0');
+            let b_number = b_number.trim_start_matches('0');
+            a_number
+                .len()
+                .cmp(&b_number.len())
+                .then_with(|| a_number.cmp(b_number))
+        } else {
+            a_rest = &a_rest[a_char.len_utf8()..];
+            b_rest = &b_rest[b_char.len_utf8()..];
+            a_char.cmp(&b_char)
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    a_rest.len().cmp(&b_rest.len()).then_with(|| a.cmp(b))
+}
+
+/// Splits the leading ASCII digits of `s` from the rest.
+fn split_number(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+/// Function used to compare two headers.
+type HeaderComparator = dyn Fn(&str, &str) -> Ordering + Send + Sync;
+
+impl HeaderOrder {
+    /// Creates a `HeaderOrder::Custom` that sorts the headers using the provided function.
+    #[must_use]
+    pub fn custom(compare: impl Fn(&str, &str) -> Ordering + Send + Sync + 'static) -> Self {
+        HeaderOrder::Custom(Callback::new(Arc::new(compare)))
+    }
+}
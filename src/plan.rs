@@ -0,0 +1,298 @@
+use crate::error::Error;
+use crate::options::{
+    ArrayOfObjectsMode, CaseStyle, ComplexValueMode, EmptyKey, HeaderOrder, HeaderStrategy,
+    KeyDecoder, MetadataColumns, MixedArray, OutputShape, QuoteStyle, RecordTerminator,
+    ScalarElement, SortDir,
+};
+use crate::schema::Schema;
+use crate::Json2Csv;
+use flatten_json_object::{ArrayFormatting, Flattener};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// All the settings of a `Json2Csv`, so a conversion can be recorded and replayed later, possibly
+/// somewhere else, with exactly the same output. With the `serde` feature it can be serialized and
+/// deserialized, e.g. to keep it under version control.
+///
+/// The deadline is not part of the plan, since it is a point in time of the process that sets it,
+/// and neither are the settings that hold user provided functions, which can not be recorded, or
+/// the path where the temporary file is kept, which is only meant to debug a run on one machine.
+///
+/// The settings missing when a plan is deserialized, e.g. because it was recorded before they were
+/// added, take their default values.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ConversionPlan {
+    /// Version of the format of the plan, which is `ConversionPlan::VERSION` for the plans
+    /// recorded by this version of the library.
+    pub version: u32,
+    pub flattener: FlattenerPlan,
+    pub spill_to_temp_file: bool,
+    pub emit_trailer_stats: bool,
+    pub key_decoder: KeyDecoder,
+    pub metadata_columns: MetadataColumns,
+    pub root_pointer: Option<String>,
+    pub empty_key_handling: EmptyKey,
+    pub max_objects: Option<usize>,
+    pub schema: Option<Schema>,
+    pub quote: u8,
+    pub append_row_count: Option<String>,
+    pub mixed_array_handling: MixedArray,
+    pub sort_by_columns: Vec<(String, SortDir)>,
+    pub header_strategy: HeaderStrategy,
+    pub output_shape: OutputShape,
+    pub escape: Option<u8>,
+    pub double_quote: bool,
+    pub scalar_element_handling: ScalarElement,
+    pub coalesce: Vec<(String, Vec<String>)>,
+    pub max_fields_per_object: Option<usize>,
+    pub header_case_style: CaseStyle,
+    pub null_counts_as_present: bool,
+    pub header_order: HeaderOrder,
+    pub null_representation: String,
+    pub missing_representation: String,
+    pub empty_container_representation: String,
+    pub header_allowlist: Option<Vec<String>>,
+    pub header_denylist: Vec<String>,
+    pub skip_invalid: bool,
+    pub reject_unknown_keys: bool,
+    pub bool_representation: (String, String),
+    pub float_precision: Option<usize>,
+    pub write_bom: bool,
+    pub array_of_objects_mode: ArrayOfObjectsMode,
+    pub complex_value_mode: ComplexValueMode,
+    pub reject_duplicate_keys: bool,
+    pub terminator: RecordTerminator,
+    pub max_columns: Option<usize>,
+    pub constant_columns: Vec<(String, String)>,
+    pub headers_without_rows: bool,
+    pub internal_sentinels: (String, String, String),
+    pub quote_style: QuoteStyle,
+    pub row_index_column: Option<String>,
+    pub row_index_start: usize,
+    pub empty_string_as_null: bool,
+    pub deduplicate_rows: bool,
+    pub max_rows: Option<usize>,
+    pub headers_from_written_rows: bool,
+}
+
+impl ConversionPlan {
+    /// Version of the format of the plans recorded by this version of the library. It is increased
+    /// when the meaning of a setting changes, so older versions can refuse the plans they would
+    /// replay differently.
+    pub const VERSION: u32 = 1;
+}
+
+impl Default for ConversionPlan {
+    /// The plan of a `Json2Csv` with all the settings with their default values.
+    fn default() -> Self {
+        Json2Csv::new(Flattener::new()).record()
+    }
+}
+
+/// Settings of the flattener provided by the user of the library.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct FlattenerPlan {
+    pub key_separator: String,
+    pub array_formatting: ArrayFormattingPlan,
+    pub preserve_empty_arrays: bool,
+    pub preserve_empty_objects: bool,
+}
+
+/// Mirror of `flatten_json_object::ArrayFormatting`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ArrayFormattingPlan {
+    Plain,
+    Surrounded { start: String, end: String },
+}
+
+impl From<&Flattener> for FlattenerPlan {
+    fn from(flattener: &Flattener) -> Self {
+        FlattenerPlan {
+            key_separator: flattener.key_separator().to_string(),
+            array_formatting: match flattener.array_formatting() {
+                ArrayFormatting::Plain => ArrayFormattingPlan::Plain,
+                ArrayFormatting::Surrounded { start, end } => ArrayFormattingPlan::Surrounded {
+                    start: start.clone(),
+                    end: end.clone(),
+                },
+            },
+            preserve_empty_arrays: flattener.preserve_empty_arrays(),
+            preserve_empty_objects: flattener.preserve_empty_objects(),
+        }
+    }
+}
+
+impl From<&FlattenerPlan> for Flattener {
+    fn from(plan: &FlattenerPlan) -> Self {
+        Flattener::new()
+            .set_key_separator(&plan.key_separator)
+            .set_array_formatting(match &plan.array_formatting {
+                ArrayFormattingPlan::Plain => ArrayFormatting::Plain,
+                ArrayFormattingPlan::Surrounded { start, end } => ArrayFormatting::Surrounded {
+                    start: start.clone(),
+                    end: end.clone(),
+                },
+            })
+            .set_preserve_empty_arrays(plan.preserve_empty_arrays)
+            .set_preserve_empty_objects(plan.preserve_empty_objects)
+    }
+}
+
+impl Json2Csv {
+    /// Records the settings of this object in a plan.
+    ///
+    /// # Errors
+    /// Will return `Err` if any of the settings holds a user provided function, like a custom key
+    /// decoder, an object validator, a custom header order, column transforms or a missing value
+    /// function, since they can not be recorded.
+    pub fn to_plan(&self) -> Result<ConversionPlan, Error> {
+        if let KeyDecoder::Custom(_) = self.key_decoder {
+            return Err(not_recordable("key_decoder"));
+        }
+        if self.object_validator.is_some() {
+            return Err(not_recordable("object_validator"));
+        }
+        if let HeaderOrder::Custom(_) = self.header_order {
+            return Err(not_recordable("header_order"));
+        }
+        if !self.column_transforms.is_empty() {
+            return Err(not_recordable("column_transforms"));
+        }
+        if self.missing_value_fn.is_some() {
+            return Err(not_recordable("missing_value_fn"));
+        }
+        Ok(self.record())
+    }
+
+    /// Records the settings that can be part of a plan.
+    fn record(&self) -> ConversionPlan {
+        ConversionPlan {
+            version: ConversionPlan::VERSION,
+            flattener: FlattenerPlan::from(&self.original_flattener),
+            spill_to_temp_file: self.spill_to_temp_file,
+            emit_trailer_stats: self.emit_trailer_stats,
+            key_decoder: self.key_decoder.clone(),
+            metadata_columns: self.metadata_columns.clone(),
+            root_pointer: self.root_pointer.clone(),
+            empty_key_handling: self.empty_key_handling.clone(),
+            max_objects: self.max_objects,
+            schema: self.schema.clone(),
+            quote: self.quote,
+            append_row_count: self.append_row_count.clone(),
+            mixed_array_handling: self.mixed_array_handling.clone(),
+            sort_by_columns: self.sort_by_columns.clone(),
+            header_strategy: self.header_strategy,
+            output_shape: self.output_shape,
+            escape: self.escape,
+            double_quote: self.double_quote,
+            scalar_element_handling: self.scalar_element_handling,
+            coalesce: self.coalesce.clone(),
+            max_fields_per_object: self.max_fields_per_object,
+            header_case_style: self.header_case_style,
+            null_counts_as_present: self.null_counts_as_present,
+            header_order: self.header_order.clone(),
+            null_representation: self.null_representation.clone(),
+            missing_representation: self.missing_representation.clone(),
+            empty_container_representation: self.empty_container_representation.clone(),
+            header_allowlist: self.header_allowlist.clone(),
+            header_denylist: self.header_denylist.clone(),
+            skip_invalid: self.skip_invalid,
+            reject_unknown_keys: self.reject_unknown_keys,
+            bool_representation: self.bool_representation.clone(),
+            float_precision: self.float_precision,
+            write_bom: self.write_bom,
+            array_of_objects_mode: self.array_of_objects_mode,
+            complex_value_mode: self.complex_value_mode,
+            reject_duplicate_keys: self.reject_duplicate_keys,
+            terminator: self.terminator,
+            max_columns: self.max_columns,
+            constant_columns: self.constant_columns.clone(),
+            headers_without_rows: self.headers_without_rows,
+            internal_sentinels: self.internal_sentinels.clone(),
+            quote_style: self.quote_style,
+            row_index_column: self.row_index_column.clone(),
+            row_index_start: self.row_index_start,
+            empty_string_as_null: self.empty_string_as_null,
+            deduplicate_rows: self.deduplicate_rows,
+            max_rows: self.max_rows,
+            headers_from_written_rows: self.headers_from_written_rows,
+        }
+    }
+
+    /// Creates a JSON to CSV object with the settings recorded in the plan.
+    ///
+    /// # Errors
+    /// Will return `Err` if the plan was recorded by a newer version of the library, with a newer
+    /// version of the format of the plan.
+    pub fn from_plan(plan: ConversionPlan) -> Result<Self, Error> {
+        if plan.version > ConversionPlan::VERSION {
+            return Err(Error::UnsupportedPlanVersion {
+                version: plan.version,
+            });
+        }
+        Ok(Json2Csv {
+            spill_to_temp_file: plan.spill_to_temp_file,
+            emit_trailer_stats: plan.emit_trailer_stats,
+            key_decoder: plan.key_decoder,
+            metadata_columns: plan.metadata_columns,
+            root_pointer: plan.root_pointer,
+            empty_key_handling: plan.empty_key_handling,
+            max_objects: plan.max_objects,
+            schema: plan.schema,
+            quote: plan.quote,
+            append_row_count: plan.append_row_count,
+            mixed_array_handling: plan.mixed_array_handling,
+            sort_by_columns: plan.sort_by_columns,
+            header_strategy: plan.header_strategy,
+            output_shape: plan.output_shape,
+            escape: plan.escape,
+            double_quote: plan.double_quote,
+            scalar_element_handling: plan.scalar_element_handling,
+            coalesce: plan.coalesce,
+            max_fields_per_object: plan.max_fields_per_object,
+            header_case_style: plan.header_case_style,
+            null_counts_as_present: plan.null_counts_as_present,
+            header_order: plan.header_order,
+            null_representation: plan.null_representation,
+            missing_representation: plan.missing_representation,
+            empty_container_representation: plan.empty_container_representation,
+            header_allowlist: plan.header_allowlist,
+            header_denylist: plan.header_denylist,
+            skip_invalid: plan.skip_invalid,
+            reject_unknown_keys: plan.reject_unknown_keys,
+            bool_representation: plan.bool_representation,
+            float_precision: plan.float_precision,
+            write_bom: plan.write_bom,
+            array_of_objects_mode: plan.array_of_objects_mode,
+            complex_value_mode: plan.complex_value_mode,
+            reject_duplicate_keys: plan.reject_duplicate_keys,
+            terminator: plan.terminator,
+            max_columns: plan.max_columns,
+            constant_columns: plan.constant_columns,
+            headers_without_rows: plan.headers_without_rows,
+            quote_style: plan.quote_style,
+            row_index_column: plan.row_index_column,
+            row_index_start: plan.row_index_start,
+            empty_string_as_null: plan.empty_string_as_null,
+            deduplicate_rows: plan.deduplicate_rows,
+            max_rows: plan.max_rows,
+            headers_from_written_rows: plan.headers_from_written_rows,
+            ..Json2Csv::new(Flattener::from(&plan.flattener))
+        }
+        .set_internal_sentinels(
+            plan.internal_sentinels.0,
+            plan.internal_sentinels.1,
+            plan.internal_sentinels.2,
+        ))
+    }
+}
+
+fn not_recordable(setting: &str) -> Error {
+    Error::NotRecordable {
+        setting: setting.to_string(),
+    }
+}
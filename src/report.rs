@@ -0,0 +1,29 @@
+use crate::error::Error;
+
+/// Summary of a conversion, returned by the conversion methods whose name ends with
+/// `_with_report`.
+#[derive(Debug, Default)]
+pub struct ConversionReport {
+    /// Number of rows written, not counting the headers.
+    pub rows: usize,
+    /// Errors of the values of the input that could not be parsed and were skipped, in the order
+    /// they were found. Only values skipped because of `Json2Csv::set_skip_invalid` are reported.
+    pub skipped: Vec<Error>,
+    /// Columns left out of the CSV because they are not present in all the objects, with
+    /// `HeaderStrategy::Intersection`, sorted alphabetically. Their values are dropped. Empty with
+    /// any other strategy, or if there is a schema or a header allowlist.
+    pub dropped_columns: Vec<String>,
+}
+
+/// Summary of what converting an input would produce, returned by `Json2Csv::dry_run`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct DryRunReport {
+    /// Headers of the CSV, in the order they would be written. If there are collisions they are
+    /// the headers that would result if the colliding keys were allowed.
+    pub headers: Vec<String>,
+    /// Number of rows that would be written, not counting the headers.
+    pub rows: usize,
+    /// Keys that more than one key of the input ends looking like after flattening, which would
+    /// make the conversion fail with `Error::FlattenedKeysCollision`. Empty if there are none.
+    pub collisions: Vec<String>,
+}
@@ -0,0 +1,150 @@
+use crate::error::Error;
+use crate::{FlatMaps, HeaderCollector, Json2Csv};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+
+/// Headers of a CSV output, in the order they are written, and the names written for them in the
+/// header row. It can be computed from some input with `Json2Csv::headers_for_array` or
+/// `Json2Csv::headers_for_reader`, and then used with `Json2Csv::with_schema` to convert other
+/// inputs with exactly the same columns.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Schema {
+    headers: Vec<String>,
+    /// Names written in the header row instead of some of the headers.
+    #[cfg_attr(feature = "serde", serde(default))]
+    renames: BTreeMap<String, String>,
+}
+
+impl Schema {
+    /// Creates a schema with the provided headers, which are written as they are.
+    #[must_use]
+    pub fn new(headers: Vec<String>) -> Self {
+        Schema {
+            headers,
+            renames: BTreeMap::new(),
+        }
+    }
+
+    /// Writes `name` in the header row instead of `header`. The values of the column are still
+    /// the ones of the key `header` of the flattened objects. Renaming a header that is not part
+    /// of the schema has no effect, and renaming it again replaces the previous name.
+    #[must_use]
+    pub fn rename(mut self, header: String, name: String) -> Self {
+        self.renames.insert(header, name);
+        self
+    }
+
+    /// The name written in the header row for `header`.
+    #[must_use]
+    pub fn name<'a>(&'a self, header: &'a str) -> &'a str {
+        self.renames.get(header).map_or(header, String::as_str)
+    }
+
+    /// The headers of the CSV output, in the order they are written.
+    #[must_use]
+    pub fn headers(&self) -> &[String] {
+        &self.headers
+    }
+
+    /// Consumes the schema, returning its headers.
+    #[must_use]
+    pub fn into_headers(self) -> Vec<String> {
+        self.headers
+    }
+
+    /// Compares the headers of this schema, the old one, with the headers of `other`, the new one.
+    /// The names written for them are not compared.
+    #[must_use]
+    pub fn diff(&self, other: &Schema) -> HeaderDiff {
+        let old: HashSet<&String> = self.headers.iter().collect();
+        let new: HashSet<&String> = other.headers.iter().collect();
+        let (common, removed) = self.headers.iter().cloned().partition(|h| new.contains(h));
+        HeaderDiff {
+            added: other
+                .headers
+                .iter()
+                .filter(|h| !old.contains(h))
+                .cloned()
+                .collect(),
+            removed,
+            common,
+        }
+    }
+}
+
+/// Differences between the headers of two schemas, an old one and a new one. The headers keep the
+/// order they have in the schemas.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HeaderDiff {
+    /// Headers only present in the new schema.
+    pub added: Vec<String>,
+    /// Headers only present in the old schema.
+    pub removed: Vec<String>,
+    /// Headers present in both schemas, in the order of the old one.
+    pub common: Vec<String>,
+}
+
+/// Computes a schema from several inputs, so all of them can be converted with the same columns,
+/// e.g. to append the rows of several files to the same CSV output. The headers are computed as
+/// if all the inputs were a single one. It is created with `Json2Csv::schema_builder`.
+pub struct SchemaBuilder<'a> {
+    json2csv: &'a Json2Csv,
+    counter: FlatMaps,
+    collector: HeaderCollector,
+}
+
+impl<'a> SchemaBuilder<'a> {
+    pub(crate) fn new(json2csv: &'a Json2Csv) -> Self {
+        SchemaBuilder {
+            json2csv,
+            counter: FlatMaps::Discarded(0),
+            collector: HeaderCollector::default(),
+        }
+    }
+
+    /// Adds the keys of the flattened objects in the array slice to the schema.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `Json2Csv::headers_for_array`.
+    pub fn add_array(&mut self, objects: &[Value]) -> Result<&mut Self, Error> {
+        for document in objects {
+            self.json2csv.flatten_document(
+                document,
+                &mut self.counter,
+                &mut self.collector,
+                &Map::new(),
+            )?;
+        }
+        Ok(self)
+    }
+
+    /// Adds the keys of the flattened JSON objects in the reader to the schema.
+    ///
+    /// # Errors
+    /// Will return `Err` in the same cases as `Json2Csv::headers_for_reader`.
+    pub fn add_reader(&mut self, reader: impl Read) -> Result<&mut Self, Error> {
+        self.json2csv.flatten_reader_into(
+            reader,
+            &mut self.counter,
+            &mut self.collector,
+            &Map::new(),
+            &mut |_, _| {},
+        )?;
+        Ok(self)
+    }
+
+    /// Returns the schema with the headers of all the inputs added.
+    ///
+    /// # Errors
+    /// Will return `Err` if two keys of any of the inputs that should be different end looking the
+    /// same after flattening.
+    pub fn build(self) -> Result<Schema, Error> {
+        Ok(Schema::new(
+            self.json2csv.collected_headers(self.collector)?,
+        ))
+    }
+}